@@ -0,0 +1,436 @@
+//! Runtime configuration for the mock MCU.
+//!
+//! Settings are resolved in three layers: built-in defaults, an optional TOML or
+//! JSON config file (`--config`), then individual command-line flags. Each layer
+//! overrides only the values it sets, so one binary can emulate any robot variant.
+//! IMU settings (`[imu]`), safety limits (`[safety]`), traction monitoring
+//! (`[traction]`), thermal limits (`[thermal]`) and motion speed limits
+//! (`[limits]`) can only be set in the config file.
+
+use std::{net::Ipv4Addr, path::Path};
+
+use clap::{Parser, ValueEnum};
+use owb_core::utils::controllers::imu_config::{DEFAULT_IMU_CONFIG, ImuConfigUpdate};
+use owb_core::utils::controllers::limits::MotionLimits;
+use owb_core::utils::controllers::safety::SafetyLimits;
+use owb_core::utils::controllers::thermal::ThermalLimits;
+use owb_core::utils::controllers::traction::TractionConfig;
+use owb_core::utils::{connection::server::MAX_SERVER_LISTENERS, controllers::animation::MAX_FPS};
+use serde::{Deserialize, Serialize};
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+pub struct Opts {
+    /// TOML or JSON config file (format chosen by extension)
+    #[clap(long)]
+    pub config: Option<std::path::PathBuf>,
+    /// TAP device name
+    #[clap(long)]
+    pub tap: Option<String>,
+    /// use a static IP instead of DHCP
+    #[clap(long)]
+    pub static_ip: bool,
+    /// static IPv4 address
+    #[clap(long)]
+    pub ip: Option<Ipv4Addr>,
+    /// static IPv4 prefix length
+    #[clap(long)]
+    pub prefix: Option<u8>,
+    /// static IPv4 gateway
+    #[clap(long)]
+    pub gateway: Option<Ipv4Addr>,
+    /// HTTP/WebSocket port
+    #[clap(long)]
+    pub port: Option<u16>,
+    /// number of connections served concurrently
+    #[clap(long)]
+    pub listeners: Option<usize>,
+    /// wheel radius (m)
+    #[clap(long)]
+    pub wheel_radius: Option<f32>,
+    /// robot center-to-wheel radius (m)
+    #[clap(long)]
+    pub robot_radius: Option<f32>,
+    /// number of LEDs on the simulated strip
+    #[clap(long)]
+    pub led_count: Option<usize>,
+    /// LED animation frame rate (frames per second)
+    #[clap(long)]
+    pub led_fps: Option<u32>,
+    /// show the robot's status on the LEDs at startup
+    #[clap(long)]
+    pub led_status: bool,
+    /// show the direction of travel on the LEDs at startup
+    #[clap(long)]
+    pub led_heading: bool,
+    /// angle of LED 0 from the forward axis (degrees, clockwise)
+    #[clap(long)]
+    pub led_heading_offset: Option<f32>,
+    /// peak amplitude of simulated IMU noise (g, °/s, °C)
+    #[clap(long)]
+    pub noise: Option<f32>,
+    /// IMU telemetry rate for `/events` in Hz (0 disables it)
+    #[clap(long)]
+    pub imu_rate: Option<f32>,
+    /// orientation filter for the attitude estimator
+    #[clap(long, value_enum)]
+    pub attitude: Option<AttitudeFilter>,
+    /// attitude estimator sample rate in Hz
+    #[clap(long)]
+    pub attitude_rate: Option<f32>,
+    /// log output format
+    #[clap(long, value_enum)]
+    pub log_format: Option<LogFormat>,
+}
+
+/// Output format of the tracing subscriber.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    #[default]
+    Full,
+    Compact,
+    Pretty,
+    Json,
+}
+
+/// Attitude estimator filter, or `off` to not run the estimator.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum AttitudeFilter {
+    #[default]
+    Off,
+    Madgwick,
+    Mahony,
+}
+
+/// Fully resolved mock MCU settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MockConfig {
+    pub tap: String,
+    pub static_ip: bool,
+    pub ip: Ipv4Addr,
+    pub prefix: u8,
+    pub gateway: Option<Ipv4Addr>,
+    pub port: u16,
+    pub listeners: usize,
+    pub wheel_radius: f32,
+    pub robot_radius: f32,
+    pub led_count: usize,
+    pub led_fps: u32,
+    pub led_status: bool,
+    pub led_heading: bool,
+    pub led_heading_offset: f32,
+    pub noise: f32,
+    pub imu_rate: f32,
+    pub attitude: AttitudeFilter,
+    pub attitude_rate: f32,
+    /// IMU range, output data rate and power mode changes from the defaults.
+    pub imu: ImuConfigUpdate,
+    /// Tip-over, lift and collision cutoff thresholds.
+    pub safety: SafetyLimits,
+    /// Wheel stall and slip detection settings.
+    pub traction: TractionConfig,
+    /// Motor temperature warning and critical thresholds.
+    pub thermal: ThermalLimits,
+    /// Speed limits applied to motion commands.
+    pub limits: MotionLimits,
+    pub log_format: LogFormat,
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        Self {
+            tap: "tap0".into(),
+            static_ip: false,
+            ip: Ipv4Addr::new(192, 168, 69, 2),
+            prefix: 24,
+            gateway: Some(Ipv4Addr::new(192, 168, 69, 1)),
+            port: 8000,
+            listeners: 2,
+            wheel_radius: 0.148,
+            robot_radius: 0.195,
+            led_count: 2,
+            led_fps: 10,
+            led_status: false,
+            led_heading: false,
+            led_heading_offset: 0.0,
+            noise: 0.0,
+            imu_rate: 10.0,
+            attitude: AttitudeFilter::Off,
+            attitude_rate: 50.0,
+            imu: ImuConfigUpdate::default(),
+            safety: SafetyLimits::default(),
+            traction: TractionConfig::default(),
+            thermal: ThermalLimits::default(),
+            limits: MotionLimits::default(),
+            log_format: LogFormat::Full,
+        }
+    }
+}
+
+impl MockConfig {
+    /// Resolve the configuration from defaults, the optional config file and CLI flags.
+    pub fn load(opts: Opts) -> Result<Self, String> {
+        let mut cfg = match &opts.config {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+
+        if let Some(tap) = opts.tap {
+            cfg.tap = tap;
+        }
+        cfg.static_ip |= opts.static_ip;
+        if let Some(ip) = opts.ip {
+            cfg.ip = ip;
+        }
+        if let Some(prefix) = opts.prefix {
+            cfg.prefix = prefix;
+        }
+        if let Some(gateway) = opts.gateway {
+            cfg.gateway = Some(gateway);
+        }
+        if let Some(port) = opts.port {
+            cfg.port = port;
+        }
+        if let Some(listeners) = opts.listeners {
+            cfg.listeners = listeners;
+        }
+        if let Some(wr) = opts.wheel_radius {
+            cfg.wheel_radius = wr;
+        }
+        if let Some(rr) = opts.robot_radius {
+            cfg.robot_radius = rr;
+        }
+        if let Some(count) = opts.led_count {
+            cfg.led_count = count;
+        }
+        if let Some(fps) = opts.led_fps {
+            cfg.led_fps = fps;
+        }
+        cfg.led_status |= opts.led_status;
+        cfg.led_heading |= opts.led_heading;
+        if let Some(offset) = opts.led_heading_offset {
+            cfg.led_heading_offset = offset;
+        }
+        if let Some(noise) = opts.noise {
+            cfg.noise = noise;
+        }
+        if let Some(rate) = opts.imu_rate {
+            cfg.imu_rate = rate;
+        }
+        if let Some(filter) = opts.attitude {
+            cfg.attitude = filter;
+        }
+        if let Some(rate) = opts.attitude_rate {
+            cfg.attitude_rate = rate;
+        }
+        if let Some(format) = opts.log_format {
+            cfg.log_format = format;
+        }
+
+        cfg.validate()?;
+        Ok(cfg)
+    }
+
+    /// Parse a config file, choosing TOML or JSON from its extension.
+    fn from_file(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&text).map_err(|e| format!("{}: {e}", path.display())),
+            Some("json") => {
+                serde_json::from_str(&text).map_err(|e| format!("{}: {e}", path.display()))
+            }
+            _ => Err(format!(
+                "{}: unknown config format (expected .toml or .json)",
+                path.display()
+            )),
+        }
+    }
+
+    /// Reject settings the firmware cannot run with.
+    fn validate(&self) -> Result<(), String> {
+        if self.prefix > 32 {
+            return Err(format!("prefix must be 0..=32, got {}", self.prefix));
+        }
+        if !(1..=MAX_SERVER_LISTENERS).contains(&self.listeners) {
+            return Err(format!(
+                "listeners must be 1..={MAX_SERVER_LISTENERS}, got {}",
+                self.listeners
+            ));
+        }
+        if !(self.wheel_radius > 0.0 && self.robot_radius > 0.0) {
+            return Err("wheel_radius and robot_radius must be positive".into());
+        }
+        if !(1..=MAX_FPS).contains(&self.led_fps) {
+            return Err(format!(
+                "led_fps must be 1..={MAX_FPS}, got {}",
+                self.led_fps
+            ));
+        }
+        if self.led_status && self.led_heading {
+            return Err("led_status and led_heading cannot both be enabled".into());
+        }
+        if !self.led_heading_offset.is_finite() {
+            return Err("led_heading_offset must be a number".into());
+        }
+        if !(self.noise >= 0.0 && self.noise.is_finite()) {
+            return Err("noise must be a non-negative number".into());
+        }
+        if !(self.imu_rate >= 0.0 && self.imu_rate.is_finite()) {
+            return Err("imu_rate must be a non-negative number".into());
+        }
+        if !(self.attitude_rate > 0.0 && self.attitude_rate.is_finite()) {
+            return Err("attitude_rate must be a positive number".into());
+        }
+        if let Err(error) = DEFAULT_IMU_CONFIG.with(self.imu).check() {
+            return Err(format!("imu: {error}"));
+        }
+        if let Err(error) = self.safety.check() {
+            return Err(format!("safety: {error}"));
+        }
+        if let Err(error) = self.traction.check() {
+            return Err(format!("traction: {error}"));
+        }
+        if let Err(error) = self.thermal.check() {
+            return Err(format!("thermal: {error}"));
+        }
+        if let Err(error) = self.limits.check_limits() {
+            return Err(format!("limits: {error}"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// Write `text` to a config file named `name` in the temp directory.
+    fn config_file(
+        name: &str,
+        text: &str,
+    ) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("mock-mcu-{}-{name}", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn load(args: &[&str]) -> Result<MockConfig, String> {
+        let args = std::iter::once("mock-mcu").chain(args.iter().copied());
+        MockConfig::load(Opts::parse_from(args))
+    }
+
+    #[test]
+    fn loads_toml_and_json() {
+        let toml = config_file(
+            "load.toml",
+            "port = 9001\nled_count = 8\nattitude = \"mahony\"\n\n[thermal]\nwarn = 55.0\n",
+        );
+        let json = config_file(
+            "load.json",
+            r#"{"port":9001,"led_count":8,"attitude":"mahony","thermal":{"warn":55.0}}"#,
+        );
+        for path in [&toml, &json] {
+            let cfg = load(&["--config", path.to_str().unwrap()]).unwrap();
+            assert_eq!(cfg.port, 9001);
+            assert_eq!(cfg.led_count, 8);
+            assert_eq!(cfg.attitude, AttitudeFilter::Mahony);
+            assert_eq!(cfg.thermal.warn, 55.0);
+            assert_eq!(cfg.thermal.critical, ThermalLimits::default().critical);
+            assert_eq!(cfg.tap, "tap0");
+        }
+
+        let yaml = config_file("load.yaml", "port: 9001\n");
+        let error = load(&["--config", yaml.to_str().unwrap()]).unwrap_err();
+        assert!(error.contains("unknown config format"), "{error}");
+    }
+
+    #[test]
+    fn flags_override_the_file() {
+        let path = config_file("flags.toml", "port = 9001\nled_count = 8\nnoise = 0.5\n");
+        let path = path.to_str().unwrap();
+        let cfg = load(&["--config", path, "--port", "9002", "--led-status"]).unwrap();
+        assert_eq!(cfg.port, 9002);
+        assert_eq!(cfg.led_count, 8);
+        assert_eq!(cfg.noise, 0.5);
+        assert!(cfg.led_status);
+
+        assert_eq!(load(&[]).unwrap().port, MockConfig::default().port);
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let path = config_file("unknown.toml", "port = 9001\nled_cuont = 8\n");
+        let error = load(&["--config", path.to_str().unwrap()]).unwrap_err();
+        assert!(error.contains("unknown field `led_cuont`"), "{error}");
+
+        let path = config_file("unknown.json", r#"{"prot":9001}"#);
+        let error = load(&["--config", path.to_str().unwrap()]).unwrap_err();
+        assert!(error.contains("unknown field `prot`"), "{error}");
+    }
+
+    #[test]
+    fn validates_settings() {
+        assert!(MockConfig::default().validate().is_ok());
+
+        let invalid = |cfg: MockConfig| cfg.validate().unwrap_err();
+        assert!(
+            invalid(MockConfig {
+                prefix: 33,
+                ..MockConfig::default()
+            })
+            .starts_with("prefix")
+        );
+        assert!(
+            invalid(MockConfig {
+                listeners: 0,
+                ..MockConfig::default()
+            })
+            .starts_with("listeners")
+        );
+        assert!(
+            invalid(MockConfig {
+                led_fps: 0,
+                ..MockConfig::default()
+            })
+            .starts_with("led_fps")
+        );
+        assert!(
+            invalid(MockConfig {
+                led_status: true,
+                led_heading: true,
+                ..MockConfig::default()
+            })
+            .contains("cannot both")
+        );
+        assert!(
+            invalid(MockConfig {
+                thermal: ThermalLimits {
+                    warn: 90.0,
+                    ..ThermalLimits::default()
+                },
+                ..MockConfig::default()
+            })
+            .starts_with("thermal:")
+        );
+        assert!(
+            invalid(MockConfig {
+                limits: MotionLimits {
+                    max_speed: -1.0,
+                    ..MotionLimits::default()
+                },
+                ..MockConfig::default()
+            })
+            .starts_with("limits:")
+        );
+
+        let error = load(&["--robot-radius", "0"]).unwrap_err();
+        assert!(error.contains("robot_radius"), "{error}");
+    }
+}
@@ -1,123 +1,199 @@
+mod config;
+mod sim;
+
 use clap::Parser;
+use config::{AttitudeFilter, LogFormat, MockConfig, Opts};
 use core::cell::RefCell;
 use embassy_executor::{Executor, Spawner};
-use embassy_net::{Config, Ipv4Address, Ipv4Cidr, Stack, StackResources};
+use embassy_net::{Config, Ipv4Cidr, Runner, StackResources};
 use embassy_net_tuntap::TunTapDevice;
-use embedded_hal_mock::eh1::i2c::{Mock as I2cMock, Transaction as I2cTrans};
 use heapless::Vec;
-use owb_core::utils::{SystemController, wss};
-use owb_core::utils::controllers::{I2C_CHANNEL, LED_CHANNEL, LEDCommand, LedModule};
-use rand_core::{OsRng, RngCore};
+use owb_core::mk_static;
+use owb_core::utils::connection::server::{MAX_SERVER_LISTENERS, Server, ServerBuilder};
+use owb_core::utils::controllers::{
+    attitude::{self, AttitudeConfig, DEFAULT_ATTITUDE, FilterKind},
+    heading::{DEFAULT_HEADING_RING, HeadingRing},
+    imu_config::DEFAULT_IMU_CONFIG,
+    leds::{LEDCommand, LedModule},
+    limits, safety, state, thermal, traction,
+};
+use owb_core::utils::{SystemController, events};
+use rand_core::{OsRng, TryRngCore};
+use sim::SimBus;
+use smart_leds_trait::{RGB8, SmartLedsWrite};
 use static_cell::StaticCell;
-use tracing::{info, error};
-use tracing_subscriber;
 use std::convert::Infallible;
-use smart_leds_trait::{SmartLedsWrite, RGB8};
-
-#[derive(Parser)]
-#[clap(version = "1.0")]
-struct Opts
-{
-    /// TAP device name
-    #[clap(long, default_value = "tap0")]
-    tap: String,
-    /// use a static IP instead of DHCP
-    #[clap(long)]
-    static_ip: bool,
-}
+use tracing::info;
 
 #[embassy_executor::task]
-async fn net_task(stack: &'static Stack<TunTapDevice>) -> ! {
-    stack.run().await
+async fn net_task(mut runner: Runner<'static, TunTapDevice>) -> ! {
+    runner.run().await
 }
 
 #[embassy_executor::task]
-async fn i2c_task(mut ctrl: SystemController<I2cMock>) -> ! {
+async fn i2c_task(mut ctrl: SystemController<SimBus>) -> ! {
     ctrl.i2c_ch().await
 }
 
 #[embassy_executor::task]
 async fn led_task(mut leds: LedModule<SerialLedDriver>) -> ! {
-    loop {
-        let cmd: LEDCommand = LED_CHANNEL.receiver().receive().await;
-        if let Err(e) = leds.ex_command(cmd) {
-            error!("LED command failed: {:?}", e);
-        }
-    }
+    leds.run().await
 }
 
 #[embassy_executor::task]
-async fn main_task(spawner: Spawner) {
-    // I2C mock setup
-    let mut expectations: Vec<I2cTrans, 16> = Vec::new();
-    let mock = I2cMock::new(&expectations);
-    static I2C_BUS: StaticCell<RefCell<I2cMock>> = StaticCell::new();
-    let i2c_bus = I2C_BUS.init(RefCell::new(mock));
-
-    let sys_ctrl = SystemController::new(i2c_bus, None, None);
-    spawner.spawn(i2c_task(sys_ctrl)).unwrap();
+async fn main_task(
+    spawner: Spawner,
+    cfg: &'static MockConfig,
+) {
+    let mut seed_buf = [0; 8];
+    OsRng
+        .try_fill_bytes(&mut seed_buf)
+        .expect("OS random source unavailable");
+    let seed = u64::from_le_bytes(seed_buf);
+
+    state::set_firmware_version(concat!("mock-mcu ", env!("CARGO_PKG_VERSION")));
+    events::set_imu_rate(cfg.imu_rate);
+
+    // Simulated I2C bus (IMU + PWM driver)
+    static I2C_BUS: StaticCell<RefCell<SimBus>> = StaticCell::new();
+    let i2c_bus = I2C_BUS.init(RefCell::new(SimBus::new(cfg.noise, seed)));
 
-    // LED driver that logs to console
-    struct SerialLedDriver;
-    impl SmartLedsWrite for SerialLedDriver {
-        type Color = RGB8;
-        type Error = Infallible;
-
-        fn write<I>(&mut self, iter: I) -> Result<(), Self::Error>
-        where
-            I: IntoIterator<Item = Self::Color>,
-        {
-            for c in iter {
-                info!("LED: {:?}", c);
-            }
-            Ok(())
+    let mut sys_ctrl =
+        SystemController::new(i2c_bus, Some(cfg.wheel_radius), Some(cfg.robot_radius));
+    if cfg.imu != Default::default() {
+        match sys_ctrl.set_imu_config(DEFAULT_IMU_CONFIG.with(cfg.imu)) {
+            Ok(imu) => info!(?imu, "IMU configured"),
+            Err(error) => tracing::warn!(%error, "IMU configuration failed"),
         }
     }
+    safety::set_limits(cfg.safety);
+    traction::set_config(cfg.traction);
+    thermal::set_limits(cfg.thermal);
+    limits::set_limits(cfg.limits).expect("motion limits checked by MockConfig::validate");
+    spawner.spawn(i2c_task(sys_ctrl)).unwrap();
 
-    let leds = LedModule::new(SerialLedDriver);
+    let filter = match cfg.attitude {
+        AttitudeFilter::Off => None,
+        AttitudeFilter::Madgwick => Some(FilterKind::Madgwick),
+        AttitudeFilter::Mahony => Some(FilterKind::Mahony),
+    };
+    if let Some(filter) = filter {
+        let config = AttitudeConfig {
+            filter,
+            rate_hz: cfg.attitude_rate,
+            ..DEFAULT_ATTITUDE
+        };
+        attitude::spawn(&spawner, config).unwrap();
+    }
+
+    let mut leds = LedModule::with_count(SerialLedDriver::new(cfg.led_count), cfg.led_count);
+    leds.set_fps(cfg.led_fps);
+    leds.set_heading_ring(HeadingRing {
+        offset: cfg.led_heading_offset,
+        ..DEFAULT_HEADING_RING
+    });
+    if cfg.led_status {
+        let _ = leds.ex_command(LEDCommand::Status { enabled: true });
+    }
+    if cfg.led_heading {
+        let _ = leds.ex_command(LEDCommand::Heading { enabled: true });
+    }
     spawner.spawn(led_task(leds)).unwrap();
 
-    // Parse CLI and initialize network
-    let opts: Opts = Opts::parse();
-    let device = TunTapDevice::new(&opts.tap).unwrap();
-    let config = if opts.static_ip {
+    // Initialize network
+    let device = TunTapDevice::new(&cfg.tap).unwrap();
+    let config = if cfg.static_ip {
         Config::ipv4_static(embassy_net::StaticConfigV4 {
-            address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+            address: Ipv4Cidr::new(cfg.ip, cfg.prefix),
             dns_servers: Vec::new(),
-            gateway: Some(Ipv4Address::new(192, 168, 69, 1)),
+            gateway: cfg.gateway,
         })
     } else {
         Config::dhcpv4(Default::default())
     };
-    let mut seed_buf = [0; 8];
-    OsRng.fill_bytes(&mut seed_buf);
-    let seed = u64::from_le_bytes(seed_buf);
 
-    static STACK: StaticCell<Stack<TunTapDevice>> = StaticCell::new();
-    static RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
-    let stack = &*STACK.init(Stack::new(
-        device,
-        config,
-        RESOURCES.init(StackResources::<3>::new()),
-        seed,
-    ));
-    spawner.spawn(net_task(stack)).unwrap();
+    // One socket per listener, plus one for DHCP.
+    static RESOURCES: StaticCell<StackResources<{ MAX_SERVER_LISTENERS + 1 }>> = StaticCell::new();
+    let (stack, runner) =
+        embassy_net::new(device, config, RESOURCES.init(StackResources::new()), seed);
+    spawner.spawn(net_task(runner)).unwrap();
 
     info!("Waiting for network link...");
     // TODO: wait for IP assignment if needed
 
-    info!("Starting WebSocket server on port 8000");
-    wss(0, 8000, stack, None).await;
+    info!(
+        "Starting WebSocket server on port {} ({} listeners)",
+        cfg.port, cfg.listeners
+    );
+    let server = mk_static!(
+        Server,
+        ServerBuilder::new(cfg.port)
+            .listeners(cfg.listeners)
+            .build()
+    );
+    server.spawn(&spawner, stack).unwrap();
+}
+
+/// LED driver that logs a strip of `len` pixels to the console.
+///
+/// Pixels beyond the end of a write keep their previous color, like a real strip.
+struct SerialLedDriver {
+    strip: std::vec::Vec<RGB8>,
+}
+
+impl SerialLedDriver {
+    fn new(len: usize) -> Self {
+        Self {
+            strip: vec![RGB8::default(); len],
+        }
+    }
+}
+
+impl SmartLedsWrite for SerialLedDriver {
+    type Color = RGB8;
+    type Error = Infallible;
+
+    fn write<T, I>(
+        &mut self,
+        iterator: T,
+    ) -> Result<(), Self::Error>
+    where
+        T: IntoIterator<Item = I>,
+        I: Into<Self::Color>,
+    {
+        for (pixel, c) in self.strip.iter_mut().zip(iterator) {
+            *pixel = c.into();
+        }
+        for (i, c) in self.strip.iter().enumerate() {
+            info!("LED {}: {:?}", i, c);
+        }
+        Ok(())
+    }
 }
 
 static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+static CONFIG: StaticCell<MockConfig> = StaticCell::new();
 
 fn main() {
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .init();
+    let cfg = match MockConfig::load(Opts::parse()) {
+        Ok(cfg) => CONFIG.init(cfg),
+        Err(e) => {
+            eprintln!("invalid configuration: {e}");
+            std::process::exit(2);
+        }
+    };
+
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env());
+    match cfg.log_format {
+        LogFormat::Full => subscriber.init(),
+        LogFormat::Compact => subscriber.compact().init(),
+        LogFormat::Pretty => subscriber.pretty().init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+
     let executor = EXECUTOR.init(Executor::new());
     executor.run(|spawner| {
-        spawner.spawn(main_task(spawner)).unwrap();
+        spawner.spawn(main_task(spawner, cfg)).unwrap();
     });
-}
\ No newline at end of file
+}
@@ -0,0 +1,221 @@
+//! Simulated I2C bus for the mock MCU.
+//!
+//! `SimBus` answers for the ICM42670 IMU and the PCA9685 PWM driver with a plain
+//! register file per device, so the real `owb-core` drivers can initialize and run
+//! against it. IMU data registers are synthesized on read: a stationary robot with
+//! gravity on +Z, zero rotation and a warm die, each perturbed by uniform noise.
+
+use embedded_hal::i2c::{ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+
+/// I2C address of the simulated ICM42670.
+const IMU_ADDRESS: u8 = 0x68;
+/// I2C address of the simulated PCA9685.
+const PWM_ADDRESS: u8 = 0x55;
+
+/// ICM42670 register addresses the simulation cares about.
+const WHO_AM_I: u8 = 0x75;
+const ICM42670_ID: u8 = 0x67;
+const TEMP_DATA1: u8 = 0x09;
+const ACCEL_DATA_X1: u8 = 0x0B;
+const GYRO_DATA_X1: u8 = 0x11;
+const GYRO_DATA_Z0: u8 = 0x16;
+const GYRO_CONFIG0: u8 = 0x20;
+const ACCEL_CONFIG0: u8 = 0x21;
+
+/// Simulated die temperature (°C).
+const BASE_TEMPERATURE: f32 = 30.0;
+
+/// Register file for one simulated device.
+struct SimDevice {
+    regs: [u8; 256],
+    pointer: u8,
+}
+
+impl SimDevice {
+    fn new() -> Self {
+        Self {
+            regs: [0; 256],
+            pointer: 0,
+        }
+    }
+}
+
+/// Shared-bus simulation of the robot's IMU and motor driver.
+pub struct SimBus {
+    imu: SimDevice,
+    pwm: SimDevice,
+    noise: f32,
+    rng: u64,
+}
+
+impl SimBus {
+    /// Create a new simulated bus.
+    ///
+    /// `noise` is the peak amplitude of the uniform noise added to every IMU
+    /// reading, in the sensor's own units (g, °/s, °C). `seed` initializes the
+    /// noise generator.
+    pub fn new(
+        noise: f32,
+        seed: u64,
+    ) -> Self {
+        let mut imu = SimDevice::new();
+        imu.regs[WHO_AM_I as usize] = ICM42670_ID;
+
+        Self {
+            imu,
+            pwm: SimDevice::new(),
+            noise,
+            rng: seed | 1,
+        }
+    }
+
+    /// Draw a uniform sample in `[-noise, noise]` (xorshift64*).
+    fn jitter(&mut self) -> f32 {
+        if self.noise == 0.0 {
+            return 0.0;
+        }
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        let bits = self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 40;
+        let unit = bits as f32 / (1u64 << 24) as f32;
+        (unit * 2.0 - 1.0) * self.noise
+    }
+
+    /// Latch a fresh sample into the register pair starting at `reg` (high byte).
+    ///
+    /// The driver reads the high byte first, so a new value is generated there and
+    /// the low byte is left in place for the following read.
+    fn latch_sample(
+        &mut self,
+        reg: u8,
+    ) {
+        let accel_scale = match (self.imu.regs[ACCEL_CONFIG0 as usize] >> 5) & 0b11 {
+            3 => 16_384.0,
+            2 => 8_192.0,
+            1 => 4_096.0,
+            _ => 2_048.0,
+        };
+        let gyro_scale = match (self.imu.regs[GYRO_CONFIG0 as usize] >> 5) & 0b11 {
+            3 => 131.0,
+            2 => 65.5,
+            1 => 32.8,
+            _ => 16.4,
+        };
+
+        let raw = match reg {
+            TEMP_DATA1 => (BASE_TEMPERATURE + self.jitter() - 25.0) * 128.0,
+            r if (ACCEL_DATA_X1..GYRO_DATA_X1).contains(&r) => {
+                let gravity = if r == ACCEL_DATA_X1 + 4 { 1.0 } else { 0.0 };
+                (gravity + self.jitter()) * accel_scale
+            }
+            _ => self.jitter() * gyro_scale,
+        };
+        let [hi, lo] = (raw.clamp(i16::MIN as f32, i16::MAX as f32) as i16).to_be_bytes();
+        self.imu.regs[reg as usize] = hi;
+        self.imu.regs[reg as usize + 1] = lo;
+    }
+
+    /// Serve a read of `buf.len()` bytes from the IMU register pointer.
+    fn read_imu(
+        &mut self,
+        buf: &mut [u8],
+    ) {
+        for byte in buf.iter_mut() {
+            let reg = self.imu.pointer;
+            let is_data_hi = reg == TEMP_DATA1
+                || ((ACCEL_DATA_X1..=GYRO_DATA_Z0).contains(&reg) && reg % 2 == 1);
+            if is_data_hi {
+                self.latch_sample(reg);
+            }
+            *byte = self.imu.regs[reg as usize];
+            self.imu.pointer = reg.wrapping_add(1);
+        }
+    }
+}
+
+impl ErrorType for SimBus {
+    type Error = ErrorKind;
+}
+
+impl I2c for SimBus {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        if address != IMU_ADDRESS && address != PWM_ADDRESS {
+            return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+        }
+
+        for op in operations {
+            match op {
+                Operation::Write(data) => {
+                    let dev = if address == IMU_ADDRESS {
+                        &mut self.imu
+                    } else {
+                        &mut self.pwm
+                    };
+                    if let Some((&reg, values)) = data.split_first() {
+                        dev.pointer = reg;
+                        for &value in values {
+                            dev.regs[dev.pointer as usize] = value;
+                            dev.pointer = dev.pointer.wrapping_add(1);
+                        }
+                    }
+                }
+                Operation::Read(buf) => {
+                    if address == IMU_ADDRESS {
+                        self.read_imu(buf);
+                    } else {
+                        for byte in buf.iter_mut() {
+                            *byte = self.pwm.regs[self.pwm.pointer as usize];
+                            self.pwm.pointer = self.pwm.pointer.wrapping_add(1);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imu_and_pwm_registers_round_trip() {
+        let mut bus = SimBus::new(0.0, 1);
+        let mut byte = [0];
+        bus.write_read(IMU_ADDRESS, &[WHO_AM_I], &mut byte).unwrap();
+        assert_eq!(byte, [ICM42670_ID]);
+
+        // ±4 g range: 1 g on Z reads as 8192.
+        bus.write(IMU_ADDRESS, &[ACCEL_CONFIG0, 0x40]).unwrap();
+        bus.write_read(IMU_ADDRESS, &[ACCEL_CONFIG0], &mut byte)
+            .unwrap();
+        assert_eq!(byte, [0x40]);
+        let mut accel = [0; 6];
+        bus.write_read(IMU_ADDRESS, &[ACCEL_DATA_X1], &mut accel)
+            .unwrap();
+        assert_eq!(accel, [0, 0, 0, 0, 0x20, 0x00]);
+
+        let mut temp = [0; 2];
+        bus.write_read(IMU_ADDRESS, &[TEMP_DATA1], &mut temp)
+            .unwrap();
+        assert_eq!(i16::from_be_bytes(temp), 640);
+
+        // PWM channel registers read back what was written, auto-incrementing.
+        bus.write(PWM_ADDRESS, &[0x06, 0x00, 0x00, 0xFF, 0x0F])
+            .unwrap();
+        let mut channel = [0; 4];
+        bus.write_read(PWM_ADDRESS, &[0x06], &mut channel).unwrap();
+        assert_eq!(channel, [0x00, 0x00, 0xFF, 0x0F]);
+
+        assert_eq!(
+            bus.write(0x40, &[0x00]),
+            Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address))
+        );
+    }
+}
@@ -0,0 +1,272 @@
+//! Keyboard mapping and drive state for the teleoperation client.
+//!
+//! Keys are translated into `Action`s, and `Drive` turns actions into the
+//! `SystemCommand`s understood by the robot's `/ws` endpoint. Directions follow
+//! the convention of the web UI and `kinematics`: 0° is forward, increasing
+//! clockwise seen from above, and positive rotation speeds turn clockwise.
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use owb_core::utils::controllers::{SystemCommand, i2c::I2CCommand, leds::LEDCommand};
+
+/// Step applied by the speed adjustment keys.
+const SPEED_STEP: f32 = 0.1;
+
+/// User intent decoded from a key press.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// Translate towards the given direction (degrees).
+    Move(f32),
+    /// Rotate in place; `1.0` is clockwise, `-1.0` counter-clockwise.
+    Rotate(f32),
+    Stop,
+    SpeedUp,
+    SpeedDown,
+    RotSpeedUp,
+    RotSpeedDown,
+    ToggleOrbit,
+    ToggleLights,
+    Color(u8, u8, u8),
+    Enable,
+    Disable,
+    ReadImu,
+    Quit,
+}
+
+impl Action {
+    /// Whether the action starts or continues motion (subject to the dead-man timer).
+    pub fn is_motion(&self) -> bool {
+        matches!(self, Action::Move(_) | Action::Rotate(_))
+    }
+}
+
+/// Decode a key event into an action.
+pub fn action_for(key: KeyEvent) -> Option<Action> {
+    if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+        return Some(Action::Quit);
+    }
+
+    let action = match key.code {
+        KeyCode::Char('w') | KeyCode::Up => Action::Move(0.0),
+        KeyCode::Char('d') | KeyCode::Right => Action::Move(90.0),
+        KeyCode::Char('s') | KeyCode::Down => Action::Move(180.0),
+        KeyCode::Char('a') | KeyCode::Left => Action::Move(270.0),
+        KeyCode::Char('q') => Action::Rotate(-1.0),
+        KeyCode::Char('e') => Action::Rotate(1.0),
+        KeyCode::Char(' ') => Action::Stop,
+        KeyCode::Char('+') | KeyCode::Char('=') => Action::SpeedUp,
+        KeyCode::Char('-') => Action::SpeedDown,
+        KeyCode::Char(']') => Action::RotSpeedUp,
+        KeyCode::Char('[') => Action::RotSpeedDown,
+        KeyCode::Char('o') => Action::ToggleOrbit,
+        KeyCode::Char('l') => Action::ToggleLights,
+        KeyCode::Char('1') => Action::Color(255, 0, 0),
+        KeyCode::Char('2') => Action::Color(0, 255, 0),
+        KeyCode::Char('3') => Action::Color(0, 0, 255),
+        KeyCode::Char('4') => Action::Color(255, 160, 0),
+        KeyCode::Char('5') => Action::Color(160, 0, 255),
+        KeyCode::Char('6') => Action::Color(255, 255, 255),
+        KeyCode::Char('m') => Action::Enable,
+        KeyCode::Char('n') => Action::Disable,
+        KeyCode::Char('i') => Action::ReadImu,
+        KeyCode::Esc | KeyCode::Char('x') => Action::Quit,
+        _ => return None,
+    };
+    Some(action)
+}
+
+/// Operator-adjustable drive settings.
+#[derive(Debug, Clone)]
+pub struct Drive {
+    /// Translational speed (0..=1).
+    pub speed: f32,
+    /// Rotational speed (0..=1).
+    pub rot_speed: f32,
+    /// When set, translation keys send combined `O` commands that also rotate.
+    pub orbit: bool,
+    /// Last LED state requested by this client.
+    pub lights_on: bool,
+}
+
+impl Drive {
+    pub fn new(speed: f32) -> Self {
+        Self {
+            speed: speed.clamp(0.0, 1.0),
+            rot_speed: speed.clamp(0.0, 1.0),
+            orbit: false,
+            lights_on: false,
+        }
+    }
+
+    /// Apply `action`, returning the commands to send to the robot.
+    pub fn apply(
+        &mut self,
+        action: Action,
+    ) -> Vec<SystemCommand> {
+        match action {
+            Action::Move(d) if self.orbit => vec![SystemCommand::I(I2CCommand::O {
+                d,
+                s: self.speed,
+                rs: self.rot_speed,
+                o: None,
+            })],
+            Action::Move(d) => vec![SystemCommand::I(I2CCommand::T { d, s: self.speed })],
+            Action::Rotate(sign) => vec![SystemCommand::I(I2CCommand::Y {
+                s: sign * self.rot_speed,
+                o: None,
+            })],
+            Action::Stop => self.stop(),
+            Action::SpeedUp => {
+                self.speed = (self.speed + SPEED_STEP).min(1.0);
+                vec![]
+            }
+            Action::SpeedDown => {
+                self.speed = (self.speed - SPEED_STEP).max(0.0);
+                vec![]
+            }
+            Action::RotSpeedUp => {
+                self.rot_speed = (self.rot_speed + SPEED_STEP).min(1.0);
+                vec![]
+            }
+            Action::RotSpeedDown => {
+                self.rot_speed = (self.rot_speed - SPEED_STEP).max(0.0);
+                vec![]
+            }
+            Action::ToggleOrbit => {
+                self.orbit = !self.orbit;
+                vec![]
+            }
+            Action::ToggleLights => {
+                self.lights_on = !self.lights_on;
+                let cmd = if self.lights_on {
+                    LEDCommand::On
+                } else {
+                    LEDCommand::Off
+                };
+                vec![SystemCommand::L(cmd)]
+            }
+            Action::Color(r, g, b) => vec![SystemCommand::L(LEDCommand::SC { r, g, b })],
+            Action::Enable => vec![SystemCommand::I(I2CCommand::Enable)],
+            Action::Disable => vec![SystemCommand::I(I2CCommand::Disable)],
+            Action::ReadImu => vec![SystemCommand::I(I2CCommand::ReadIMU)],
+            Action::Quit => self.stop(),
+        }
+    }
+
+    /// Commands that bring the robot to a standstill.
+    pub fn stop(&self) -> Vec<SystemCommand> {
+        vec![
+            SystemCommand::I(I2CCommand::T { d: 0.0, s: 0.0 }),
+            SystemCommand::I(I2CCommand::Y { s: 0.0, o: None }),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use owb_core::utils::connection::protocol::{self, Frame};
+
+    use super::*;
+
+    fn key(code: KeyCode) -> Option<Action> {
+        action_for(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    /// The single I2C command among `cmds`.
+    fn i2c(cmds: &[SystemCommand]) -> I2CCommand {
+        match cmds {
+            [SystemCommand::I(cmd)] => *cmd,
+            other => panic!("expected one I2C command, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn maps_keys_to_actions() {
+        assert_eq!(key(KeyCode::Char('w')), Some(Action::Move(0.0)));
+        assert_eq!(key(KeyCode::Up), Some(Action::Move(0.0)));
+        assert_eq!(key(KeyCode::Char('d')), Some(Action::Move(90.0)));
+        assert_eq!(key(KeyCode::Down), Some(Action::Move(180.0)));
+        assert_eq!(key(KeyCode::Left), Some(Action::Move(270.0)));
+        assert_eq!(key(KeyCode::Char('e')), Some(Action::Rotate(1.0)));
+        assert_eq!(key(KeyCode::Char('q')), Some(Action::Rotate(-1.0)));
+        assert_eq!(key(KeyCode::Char(' ')), Some(Action::Stop));
+        assert_eq!(key(KeyCode::Char('=')), Some(Action::SpeedUp));
+        assert_eq!(key(KeyCode::Char('1')), Some(Action::Color(255, 0, 0)));
+        assert_eq!(key(KeyCode::Esc), Some(Action::Quit));
+        assert_eq!(key(KeyCode::Char('z')), None);
+
+        let ctrl_c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+        assert_eq!(action_for(ctrl_c), Some(Action::Quit));
+        assert!(Action::Rotate(1.0).is_motion());
+        assert!(!Action::Stop.is_motion());
+    }
+
+    #[test]
+    fn drives_with_the_current_speeds() {
+        let mut drive = Drive::new(0.5);
+        assert_eq!(
+            i2c(&drive.apply(Action::Move(90.0))),
+            I2CCommand::T { d: 90.0, s: 0.5 }
+        );
+        assert_eq!(
+            i2c(&drive.apply(Action::Rotate(-1.0))),
+            I2CCommand::Y { s: -0.5, o: None }
+        );
+
+        assert!(drive.apply(Action::SpeedUp).is_empty());
+        assert!(drive.apply(Action::RotSpeedDown).is_empty());
+        assert!(drive.apply(Action::ToggleOrbit).is_empty());
+        assert_eq!(
+            i2c(&drive.apply(Action::Move(0.0))),
+            I2CCommand::O {
+                d: 0.0,
+                s: 0.6,
+                rs: 0.4,
+                o: None
+            }
+        );
+
+        for _ in 0..10 {
+            drive.apply(Action::SpeedUp);
+        }
+        assert_eq!(drive.speed, 1.0);
+        assert_eq!(Drive::new(3.0).speed, 1.0);
+    }
+
+    #[test]
+    fn toggles_lights_and_devices() {
+        let mut drive = Drive::new(0.5);
+        let led = |cmds: Vec<SystemCommand>| match cmds.as_slice() {
+            [SystemCommand::L(cmd)] => cmd.clone(),
+            other => panic!("expected one LED command, got {other:?}"),
+        };
+        assert_eq!(led(drive.apply(Action::ToggleLights)), LEDCommand::On);
+        assert_eq!(led(drive.apply(Action::ToggleLights)), LEDCommand::Off);
+        assert_eq!(
+            led(drive.apply(Action::Color(0, 0, 255))),
+            LEDCommand::SC { r: 0, g: 0, b: 255 }
+        );
+
+        assert_eq!(i2c(&drive.apply(Action::Enable)), I2CCommand::Enable);
+        assert_eq!(i2c(&drive.apply(Action::Disable)), I2CCommand::Disable);
+        assert_eq!(i2c(&drive.apply(Action::ReadImu)), I2CCommand::ReadIMU);
+    }
+
+    #[test]
+    fn stops_with_one_batch() {
+        let mut drive = Drive::new(0.5);
+        for action in [Action::Stop, Action::Quit] {
+            let cmds = drive.apply(action);
+            let json = serde_json::to_string(&cmds).unwrap();
+            let Ok(Frame::Batch(batch)) = protocol::decode_text::<Frame>(&json) else {
+                panic!("stop is not a batch: {json}");
+            };
+            assert!(matches!(
+                batch.as_slice(),
+                [
+                    SystemCommand::I(I2CCommand::T { s: 0.0, .. }),
+                    SystemCommand::I(I2CCommand::Y { s: 0.0, o: None }),
+                ]
+            ));
+        }
+    }
+}
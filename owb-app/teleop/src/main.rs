@@ -0,0 +1,264 @@
+//! Terminal teleoperation client for the Omni-Wheel Bot.
+//!
+//! Connects to the robot's `/ws?session=...` endpoint, maps key presses to
+//! `SystemCommand`s, and shows replies and periodic IMU readings. Motion is held
+//! only while keys repeat: if no motion key arrives within `--hold-ms`, a stop is
+//! sent, so a lost key-release never leaves the robot driving.
+
+mod keymap;
+mod ui;
+
+use std::{
+    io::{self, ErrorKind},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use crossterm::{
+    cursor::{Hide, Show},
+    event::{self, Event, KeyEventKind},
+    execute,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use keymap::{Action, Drive};
+use owb_core::utils::{
+    connection::protocol::{self, Reply},
+    controllers::SystemCommand,
+};
+use tungstenite::{Message, WebSocket, client::IntoClientRequest, stream::MaybeTlsStream};
+use ui::App;
+
+/// Sub-protocol negotiated by the robot's `/ws` route.
+const WS_PROTOCOL: &str = "messages";
+/// Screen refresh and socket poll period.
+const TICK: Duration = Duration::from_millis(50);
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+struct Opts {
+    /// WebSocket endpoint of the robot
+    #[clap(long, default_value = "ws://192.168.69.2:8000/ws")]
+    url: String,
+    /// session id (defaults to one derived from the process id)
+    #[clap(long)]
+    session: Option<String>,
+    /// initial translational and rotational speed (0..=1)
+    #[clap(long, default_value_t = 0.5)]
+    speed: f32,
+    /// IMU polling rate in Hz (0 disables polling)
+    #[clap(long, default_value_t = 2.0)]
+    imu_hz: f32,
+    /// stop the robot if no motion key repeats within this many milliseconds
+    #[clap(long, default_value_t = 600)]
+    hold_ms: u64,
+}
+
+type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+/// Restores the terminal when dropped, including on error paths.
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+        Ok(TerminalGuard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn main() {
+    let opts = Opts::parse();
+    if let Err(e) = run(opts) {
+        eprintln!("owb-app: {e}");
+        std::process::exit(1);
+    }
+}
+
+/// Open the WebSocket with the robot's sub-protocol and a short read timeout so
+/// the UI loop can poll it.
+fn connect(endpoint: &str) -> Result<Socket, Box<dyn std::error::Error>> {
+    let mut request = endpoint.into_client_request()?;
+    request
+        .headers_mut()
+        .insert("Sec-WebSocket-Protocol", WS_PROTOCOL.parse()?);
+    let (socket, _response) = tungstenite::connect(request)?;
+    if let MaybeTlsStream::Plain(stream) = socket.get_ref() {
+        stream.set_read_timeout(Some(Duration::from_millis(5)))?;
+    }
+    Ok(socket)
+}
+
+fn send(
+    socket: &mut Socket,
+    app: &mut App,
+    commands: Vec<SystemCommand>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Multi-command actions (e.g. stop) go out as one batch so they are
+    // applied together.
+    let json = match commands.as_slice() {
+        [] => return Ok(()),
+        [cmd] => serde_json::to_string(cmd)?,
+        cmds => serde_json::to_string(cmds)?,
+    };
+    socket.send(Message::text(json.clone()))?;
+    app.last_command = json;
+    Ok(())
+}
+
+/// Record one reply from the robot, picking out IMU samples for the telemetry pane.
+///
+/// Batch replies are unpacked into their per-command results.
+fn on_reply(
+    app: &mut App,
+    reply: Result<Reply, protocol::ProtocolError>,
+    raw: &str,
+) {
+    match reply {
+        Ok(Reply::Batch { results }) => {
+            for result in results {
+                on_reply(app, Ok(result), raw);
+            }
+        }
+        Ok(Reply::Imu(imu)) => app.imu = Some((imu, Instant::now())),
+        Ok(Reply::Ack { msg }) => app.push_log(msg),
+        Ok(Reply::Error { msg }) => app.push_log(format!("error: {msg}")),
+        Ok(Reply::Hello(hello)) => app.push_log(format!(
+            "connected: firmware {} protocol {} ({} LEDs, imu {}, pwm {})",
+            hello.firmware,
+            hello.protocol,
+            hello.capabilities.led_count,
+            hello.capabilities.devices.imu,
+            hello.capabilities.devices.pwm,
+        )),
+        Ok(Reply::Capabilities(caps)) => app.push_log(format!("capabilities: {caps:?}")),
+        Ok(Reply::Leds(leds)) => app.push_log(format!("leds: {leds:?}")),
+        Ok(Reply::Motors(motors)) => app.push_log(format!("motors: {motors:?}")),
+        Ok(Reply::Motion(motion)) => app.push_log(format!("motion: {motion:?}")),
+        Ok(Reply::State(state)) => app.push_log(format!("state: {state:?}")),
+        Ok(Reply::Metrics(metrics)) => app.push_log(format!("metrics: {metrics:?}")),
+        Ok(Reply::ImuCalibration(calibration)) => {
+            app.push_log(format!("imu calibration: {calibration:?}"))
+        }
+        Ok(Reply::Attitude(a)) => app.push_log(format!(
+            "attitude: roll {:.1} pitch {:.1} yaw {:.1}",
+            a.roll, a.pitch, a.yaw
+        )),
+        Ok(Reply::ImuConfig(c)) => app.push_log(format!(
+            "imu: accel {:?} @ {} Hz, gyro {:?} @ {} Hz, {:?}",
+            c.accel_range, c.accel_odr, c.gyro_range, c.gyro_odr, c.power_mode
+        )),
+        Ok(Reply::Recorder(r)) => app.push_log(format!(
+            "recorder: {} Hz, {}/{} samples, {} dropped",
+            r.rate, r.buffered, r.capacity, r.dropped
+        )),
+        Ok(Reply::Samples { samples, remaining }) => app.push_log(format!(
+            "samples: {} received, {} remaining",
+            samples.len(),
+            remaining
+        )),
+        Ok(Reply::Safety(report)) => app.push_log(match report.trip {
+            Some(trip) => format!("safety: tripped ({trip:?})"),
+            None => format!("safety: armed={}", report.limits.enabled),
+        }),
+        Ok(Reply::Traction(report)) => app.push_log(match report.fault {
+            Some(fault) => format!("traction: {fault:?} (duty x{})", report.duty_scale),
+            None => format!("traction: ok (duty x{})", report.duty_scale),
+        }),
+        Ok(Reply::Thermal(report)) => app.push_log(match report.status {
+            Some(s) => format!(
+                "thermal: {:?} {:.1} °C ({:+.1} °C/min, duty x{:.2})",
+                s.level, s.temp, s.trend, s.duty_scale
+            ),
+            None => "thermal: no reading".into(),
+        }),
+        Ok(Reply::MotionLimits(limits)) => app.push_log(format!(
+            "motion limits: speed {} rotation {} ({:?})",
+            limits.max_speed, limits.max_rotation_speed, limits.policy
+        )),
+        Err(_) => app.push_log(raw),
+    }
+}
+
+fn run(opts: Opts) -> Result<(), Box<dyn std::error::Error>> {
+    let session = opts
+        .session
+        .unwrap_or_else(|| format!("teleop-{}", std::process::id()));
+    let endpoint = format!("{}?session={}", opts.url, session);
+    let mut socket = connect(&endpoint)?;
+
+    let mut app = App::new(endpoint, Drive::new(opts.speed));
+    let hold = Duration::from_millis(opts.hold_ms);
+    let imu_period = (opts.imu_hz > 0.0).then(|| Duration::from_secs_f32(1.0 / opts.imu_hz));
+
+    let _guard = TerminalGuard::enter()?;
+    let mut stdout = io::stdout();
+    let mut last_motion: Option<Instant> = None;
+    let mut last_imu = Instant::now();
+
+    loop {
+        if event::poll(TICK)?
+            && let Event::Key(key) = event::read()?
+            && key.kind != KeyEventKind::Release
+            && let Some(action) = keymap::action_for(key)
+        {
+            let commands = app.drive.apply(action);
+            send(&mut socket, &mut app, commands)?;
+            match action {
+                Action::Quit => break,
+                Action::Stop => last_motion = None,
+                a if a.is_motion() => last_motion = Some(Instant::now()),
+                _ => {}
+            }
+        }
+
+        if last_motion.is_some_and(|at| at.elapsed() > hold) {
+            let commands = app.drive.stop();
+            send(&mut socket, &mut app, commands)?;
+            last_motion = None;
+        }
+
+        if let Some(period) = imu_period
+            && last_imu.elapsed() >= period
+        {
+            // Polls are not operator commands, so they bypass `send` and leave
+            // the "last" line alone.
+            let json = serde_json::to_string(&app.drive.apply(Action::ReadImu)[0])?;
+            socket.send(Message::text(json))?;
+            last_imu = Instant::now();
+        }
+
+        loop {
+            match socket.read() {
+                Ok(Message::Text(text)) => on_reply(
+                    &mut app,
+                    protocol::decode_text(text.as_str()),
+                    text.as_str(),
+                ),
+                Ok(Message::Binary(data)) => {
+                    on_reply(&mut app, protocol::decode_binary(&data), "<binary frame>")
+                }
+                Ok(Message::Close(_)) => return Err("connection closed by robot".into()),
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(e))
+                    if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                {
+                    break;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        app.draw(&mut stdout)?;
+    }
+
+    let _ = socket.close(None);
+    Ok(())
+}
@@ -0,0 +1,125 @@
+//! Terminal rendering for the teleoperation client.
+//!
+//! The screen is redrawn in full from `App` state on every refresh; the layout is
+//! small enough that this stays flicker-free in the alternate screen buffer.
+
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    time::Instant,
+};
+
+use crossterm::{
+    cursor::MoveTo,
+    queue,
+    style::Print,
+    terminal::{self, Clear, ClearType},
+};
+use owb_core::utils::controllers::ImuData;
+
+use crate::keymap::Drive;
+
+/// Number of replies kept in the scroll-back log.
+const LOG_LINES: usize = 10;
+
+const HELP: [&str; 3] = [
+    "w/a/s/d or arrows move   q/e rotate   space stop   +/- speed   [/] rotation   o orbit",
+    "l lights   1-6 colors   m enable motors   n disable motors   i read IMU",
+    "esc / x / ctrl-c quit",
+];
+
+/// Everything the screen shows.
+pub struct App {
+    pub endpoint: String,
+    pub drive: Drive,
+    pub last_command: String,
+    pub imu: Option<(ImuData, Instant)>,
+    log: VecDeque<String>,
+}
+
+impl App {
+    pub fn new(
+        endpoint: String,
+        drive: Drive,
+    ) -> Self {
+        Self {
+            endpoint,
+            drive,
+            last_command: String::from("-"),
+            imu: None,
+            log: VecDeque::with_capacity(LOG_LINES),
+        }
+    }
+
+    /// Append a line to the reply log, dropping the oldest entry when full.
+    pub fn push_log(
+        &mut self,
+        line: impl Into<String>,
+    ) {
+        if self.log.len() == LOG_LINES {
+            self.log.pop_front();
+        }
+        self.log.push_back(line.into());
+    }
+
+    /// Redraw the whole screen.
+    pub fn draw(
+        &self,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        let width = terminal::size().map(|(w, _)| w as usize).unwrap_or(80);
+        let mut lines: Vec<String> = Vec::new();
+
+        lines.push(format!("OWB teleop  {}", self.endpoint));
+        lines.push(format!(
+            "speed {:.1}   rotation {:.1}   mode {}   lights {}",
+            self.drive.speed,
+            self.drive.rot_speed,
+            if self.drive.orbit {
+                "orbit"
+            } else {
+                "translate"
+            },
+            if self.drive.lights_on { "on" } else { "off" },
+        ));
+        lines.push(format!("last  {}", self.last_command));
+        lines.push(String::new());
+
+        match &self.imu {
+            Some((imu, at)) => {
+                let [ax, ay, az] = imu.accel;
+                let [gx, gy, gz] = imu.gyro;
+                lines.push(format!("IMU   accel {ax:+7.3} {ay:+7.3} {az:+7.3} g"));
+                lines.push(format!("      gyro  {gx:+7.2} {gy:+7.2} {gz:+7.2} °/s"));
+                lines.push(format!(
+                    "      temp  {:.1} °C   ({:.1} s ago)",
+                    imu.temp,
+                    at.elapsed().as_secs_f32()
+                ));
+            }
+            None => {
+                lines.push(String::from("IMU   no data yet"));
+                lines.push(String::new());
+                lines.push(String::new());
+            }
+        }
+        lines.push(String::new());
+
+        lines.push(String::from("replies"));
+        for entry in &self.log {
+            lines.push(format!("  {entry}"));
+        }
+        for _ in self.log.len()..LOG_LINES {
+            lines.push(String::new());
+        }
+        lines.push(String::new());
+        lines.extend(HELP.iter().map(|h| h.to_string()));
+
+        queue!(out, Clear(ClearType::All))?;
+        for (row, line) in lines.iter().enumerate() {
+            let clipped: String = line.chars().take(width).collect();
+            queue!(out, MoveTo(0, row as u16), Print(clipped))?;
+        }
+        out.flush()
+    }
+}
@@ -0,0 +1,120 @@
+//! HTTP REST endpoints.
+//!
+//! A thin layer over the WebSocket dispatch path for clients that cannot (or
+//! would rather not) hold a WebSocket open, e.g. `curl` in a test rig:
+//!
+//! - `POST /api/command`: body is one `SystemCommand` as JSON; replies with the
+//!   same `Reply` the WebSocket would send.
+//! - `GET /api/status`: LED, motor and motion state (`Reply::State`).
+//! - `GET /api/imu`: one IMU sample (`Reply::Imu`).
+//!
+//! Malformed bodies and out-of-range parameters get `400`, commands for
+//! devices that failed to initialize get `503`, and dispatch errors get `500`.
+
+use embedded_io_async::Read;
+use picoserve::{
+    extract::FromRequest,
+    request::{RequestBody, RequestParts},
+    response::{Connection, IntoResponse, Json, ResponseWriter, StatusCode},
+    ResponseSent,
+};
+
+use super::{
+    protocol::{self, Reply},
+    server::{self, Rejection},
+};
+use crate::utils::{
+    controllers::{i2c::I2CCommand, GetCommand, SystemCommand},
+    metrics,
+};
+
+/// A `Reply` sent as a JSON response with an explicit status code.
+pub struct ApiReply {
+    pub status: StatusCode,
+    pub reply: Reply,
+}
+
+impl ApiReply {
+    /// Pick the status code for a reply produced by the dispatcher.
+    pub fn from_reply(reply: Reply) -> Self {
+        let status = match reply {
+            Reply::Error { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::OK,
+        };
+        ApiReply { status, reply }
+    }
+
+    /// An error reply with the given status code.
+    pub fn error(
+        status: StatusCode,
+        msg: &str,
+    ) -> Self {
+        ApiReply {
+            status,
+            reply: Reply::error(msg),
+        }
+    }
+}
+
+impl IntoResponse for ApiReply {
+    async fn write_to<R: Read, W: ResponseWriter<Error = R::Error>>(
+        self,
+        connection: Connection<'_, R>,
+        response_writer: W,
+    ) -> Result<ResponseSent, W::Error> {
+        Json(self.reply)
+            .into_response()
+            .with_status_code(self.status)
+            .write_to(connection, response_writer)
+            .await
+    }
+}
+
+/// Request body decoded as a `SystemCommand`.
+pub struct CommandBody(pub SystemCommand);
+
+impl<'r, S> FromRequest<'r, S> for CommandBody {
+    type Rejection = ApiReply;
+
+    async fn from_request<R: Read>(
+        state: &'r S,
+        parts: RequestParts<'r>,
+        body: RequestBody<'r, R>,
+    ) -> Result<Self, Self::Rejection> {
+        let text = <&str>::from_request(state, parts, body)
+            .await
+            .map_err(|_| ApiReply::error(StatusCode::BAD_REQUEST, "Invalid request body"))?;
+
+        protocol::decode_text::<SystemCommand>(text)
+            .map(CommandBody)
+            .map_err(|_| {
+                metrics::parse_failure();
+                ApiReply::error(StatusCode::BAD_REQUEST, "Invalid command format")
+            })
+    }
+}
+
+/// Dispatch a command through the WebSocket path, answering `503` if its
+/// device is missing and `400` if its parameters are out of range.
+async fn dispatch(cmd: SystemCommand) -> ApiReply {
+    match server::try_dispatch(cmd).await {
+        Ok(reply) => ApiReply::from_reply(reply),
+        Err(Rejection::Unavailable(msg)) => ApiReply::error(StatusCode::SERVICE_UNAVAILABLE, msg),
+        Err(Rejection::Invalid(msg)) => ApiReply::error(StatusCode::BAD_REQUEST, &msg),
+    }
+}
+
+/// `POST /api/command`
+pub async fn command(CommandBody(cmd): CommandBody) -> ApiReply {
+    dispatch(cmd).await
+}
+
+/// `GET /api/status`
+pub async fn status() -> ApiReply {
+    dispatch(SystemCommand::G(GetCommand::State)).await
+}
+
+/// `GET /api/imu`
+pub async fn imu() -> ApiReply {
+    dispatch(SystemCommand::I(I2CCommand::ReadIMU)).await
+}
@@ -4,8 +4,17 @@
 //! implementation.
 //!
 //! # Modules
+//! - `api`: HTTP REST endpoints over the same dispatch path.
+//! - `protocol`: JSON/CBOR frame encoding and the reply type.
 //! - `server`: Manages the WebSocket server, routes, and message handling.
+//! - `sse`: Server-Sent Events telemetry stream.
 
+/// Module with the `/api` REST endpoints.
+pub mod api;
+/// Module defining the wire format of commands and replies.
+pub mod protocol;
 /// Module for managing the WebSocket server, including routes and connection
 /// handling.
 pub mod server;
+/// Module streaming bus events on `/events`.
+pub mod sse;
@@ -0,0 +1,567 @@
+//! Wire format for commands and replies on the `/ws` endpoint.
+//!
+//! Text frames carry JSON. Binary frames carry CBOR behind a one-byte framing
+//! version, `[FRAME_VERSION, cbor...]`, so the encoding can evolve without
+//! breaking older clients. Both encodings use the same serde types from
+//! `controllers` and the `Reply` type below.
+//!
+//! CBOR is used instead of postcard because the command enums are internally
+//! tagged (`ct`, `ic`, `lc`, `gc`), which requires a self-describing format.
+//!
+//! A frame holds either one `SystemCommand` or an array of them (a batch). A
+//! batch is decoded and checked in full before anything is dispatched, so one
+//! malformed, unavailable or out-of-range entry rejects the whole frame;
+//! otherwise the commands run back to back and the client gets a single
+//! `Reply::Batch` with one result per command.
+//!
+//! On connect the server sends a `Hello` reply describing the firmware, so one
+//! client can adapt to different builds; the same data is available later with
+//! `{"ct":"g","gc":"capabilities"}`.
+
+extern crate alloc;
+
+use alloc::{string::String, vec::Vec};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::utils::{
+    controllers::{
+        attitude::Attitude,
+        calibration::ImuCalibration,
+        i2c::I2CCommand,
+        imu_config::ImuConfig,
+        leds::LEDCommand,
+        limits::MotionLimits,
+        recorder::{ImuRecord, RecorderStatus},
+        safety::SafetyReport,
+        state::{self, GetCommand, LedState, MotionState, MotorState, StateReport},
+        thermal::ThermalReport,
+        traction::TractionReport,
+        ImuData, SystemCommand,
+    },
+    metrics::MetricsReport,
+};
+
+/// Version byte that prefixes every binary frame.
+pub const FRAME_VERSION: u8 = 1;
+
+/// Version of the message schema; bumped on incompatible changes only.
+///
+/// Additive changes (new commands, new fields) are discovered through
+/// `Capabilities` instead.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Largest number of commands accepted in one batch frame.
+pub const MAX_BATCH: usize = 16;
+
+/// Scratch space used by the CBOR decoder for strings and byte strings.
+const CBOR_SCRATCH: usize = 64;
+
+/// Payload of one incoming frame.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Frame {
+    /// Commands dispatched together, in order, without interleaving.
+    Batch(Vec<SystemCommand>),
+    /// A single command.
+    Single(SystemCommand),
+}
+
+/// Replies sent back to the client for each received frame.
+///
+/// Serialized with tag `"rt"` (reply type).
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "rt", rename_all = "snake_case")]
+pub enum Reply {
+    /// The command was accepted and forwarded to its controller.
+    Ack { msg: String },
+    /// The frame was rejected or the command could not be carried out.
+    Error { msg: String },
+    /// IMU sample returned for `ReadIMU`.
+    Imu(ImuData),
+    /// Greeting sent once when a WebSocket connection opens.
+    Hello(Hello),
+    /// Answer to `GetCommand::Capabilities`.
+    Capabilities(Capabilities),
+    /// Answer to `GetCommand::Leds`.
+    Leds(LedState),
+    /// Answer to `GetCommand::Motors`.
+    Motors(MotorState),
+    /// Answer to `GetCommand::Motion`.
+    Motion(MotionState),
+    /// Answer to `GetCommand::State`.
+    State(StateReport),
+    /// Answer to `GetCommand::Metrics`.
+    Metrics(MetricsReport),
+    /// Coefficients from `CalibrateImu`, or the answer to
+    /// `GetCommand::ImuCalibration`.
+    ImuCalibration(ImuCalibration),
+    /// Answer to `GetCommand::Attitude`.
+    Attitude(Attitude),
+    /// Settings read back after `ConfigureImu`, or the answer to
+    /// `GetCommand::ImuConfig`.
+    ImuConfig(ImuConfig),
+    /// Answer to `GetCommand::Recorder`.
+    Recorder(RecorderStatus),
+    /// Recorded IMU samples for `GetCommand::Samples`, oldest first, and the
+    /// number still held.
+    Samples {
+        samples: Vec<ImuRecord>,
+        remaining: usize,
+    },
+    /// Answer to `GetCommand::Safety`, also sent unasked over WebSocket when
+    /// the cutoff trips or clears.
+    Safety(SafetyReport),
+    /// Answer to `GetCommand::Traction`.
+    Traction(TractionReport),
+    /// Answer to `GetCommand::Thermal`, also sent unasked over WebSocket when
+    /// the thermal level changes.
+    Thermal(ThermalReport),
+    /// Answer to `GetCommand::MotionLimits`.
+    MotionLimits(MotionLimits),
+    /// Per-command replies for a batch frame, in command order.
+    Batch { results: Vec<Reply> },
+}
+
+/// Handshake message sent when a client connects.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Hello {
+    /// Firmware version string.
+    pub firmware: String,
+    /// `PROTOCOL_VERSION` of the firmware.
+    pub protocol: u16,
+    /// Binary framing version (`FRAME_VERSION`).
+    pub frame: u8,
+    pub capabilities: Capabilities,
+}
+
+/// Robot description and the features this firmware build offers.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Capabilities {
+    /// Wheel radius (m).
+    pub wheel_radius: f32,
+    /// Robot center-to-wheel distance (m).
+    pub robot_radius: f32,
+    /// Number of addressable LEDs.
+    pub led_count: usize,
+    /// Which devices initialized successfully.
+    pub devices: Devices,
+    /// Command names understood by the firmware, keyed by `ct`.
+    pub commands: Commands,
+}
+
+/// Availability of the robot's devices.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Devices {
+    pub imu: bool,
+    pub pwm: bool,
+}
+
+/// Command names per command type: `i` (`ic` values), `l` (`lc`), `g` (`gc`).
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Commands {
+    pub i: Vec<String>,
+    pub l: Vec<String>,
+    pub g: Vec<String>,
+}
+
+impl Capabilities {
+    /// Describe the running firmware from the shared controller state.
+    pub fn current() -> Self {
+        let s = state::snapshot();
+        let names = |list: &[&str]| list.iter().map(|n| String::from(*n)).collect();
+        Capabilities {
+            wheel_radius: s.wheel_radius,
+            robot_radius: s.robot_radius,
+            led_count: s.led_count,
+            devices: Devices {
+                imu: s.imu_ready,
+                pwm: s.pwm_ready,
+            },
+            commands: Commands {
+                i: names(I2CCommand::NAMES),
+                l: names(LEDCommand::NAMES),
+                g: names(GetCommand::NAMES),
+            },
+        }
+    }
+}
+
+impl Hello {
+    /// Build the handshake for the running firmware.
+    pub fn current() -> Self {
+        Hello {
+            firmware: String::from(state::snapshot().firmware),
+            protocol: PROTOCOL_VERSION,
+            frame: FRAME_VERSION,
+            capabilities: Capabilities::current(),
+        }
+    }
+}
+
+impl Reply {
+    /// Build an `Ack` reply.
+    pub fn ack(msg: &str) -> Self {
+        Reply::Ack { msg: msg.into() }
+    }
+
+    /// Build an `Error` reply.
+    pub fn error(msg: &str) -> Self {
+        Reply::Error { msg: msg.into() }
+    }
+}
+
+/// Errors raised while decoding or encoding frames.
+#[derive(Debug, PartialEq)]
+pub enum ProtocolError {
+    /// A binary frame had no version byte.
+    EmptyFrame,
+    /// A binary frame used a framing version this firmware does not speak.
+    UnsupportedVersion(u8),
+    /// The payload was not a valid message.
+    Decode,
+    /// The message could not be serialized.
+    Encode,
+}
+
+/// Decode a JSON text frame.
+pub fn decode_text<T: DeserializeOwned>(data: &str) -> Result<T, ProtocolError> {
+    serde_json::from_str(data).map_err(|_| ProtocolError::Decode)
+}
+
+/// Encode a message as a JSON text frame.
+pub fn encode_text<T: Serialize>(msg: &T) -> Result<String, ProtocolError> {
+    serde_json::to_string(msg).map_err(|_| ProtocolError::Encode)
+}
+
+/// Decode a versioned CBOR binary frame.
+pub fn decode_binary<T: DeserializeOwned>(data: &[u8]) -> Result<T, ProtocolError> {
+    let (&version, payload) = data.split_first().ok_or(ProtocolError::EmptyFrame)?;
+    if version != FRAME_VERSION {
+        return Err(ProtocolError::UnsupportedVersion(version));
+    }
+    let mut scratch = [0u8; CBOR_SCRATCH];
+    ciborium::de::from_reader_with_buffer(payload, &mut scratch).map_err(|_| ProtocolError::Decode)
+}
+
+/// Encode a message as a versioned CBOR binary frame.
+pub fn encode_binary<T: Serialize>(msg: &T) -> Result<Vec<u8>, ProtocolError> {
+    let mut frame = Vec::with_capacity(32);
+    frame.push(FRAME_VERSION);
+    ciborium::ser::into_writer(msg, &mut frame).map_err(|_| ProtocolError::Encode)?;
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use alloc::vec;
+
+    use super::*;
+    use crate::utils::controllers::{
+        animation::{Animation, Effect},
+        color::ColorSpec,
+        limits::DEFAULT_LIMITS,
+        safety::DEFAULT_SAFETY,
+        thermal::DEFAULT_THERMAL,
+        traction::DEFAULT_TRACTION,
+    };
+
+    #[test]
+    fn binary_round_trip_command() {
+        let cmd = SystemCommand::I(I2CCommand::O {
+            d: 45.0,
+            s: 0.5,
+            rs: -0.25,
+            o: None,
+        });
+        let frame = encode_binary(&cmd).unwrap();
+        assert_eq!(frame[0], FRAME_VERSION);
+        // Considerably smaller than the JSON text encoding
+        assert!(frame.len() < encode_text(&cmd).unwrap().len());
+
+        match decode_binary::<SystemCommand>(&frame).unwrap() {
+            SystemCommand::I(I2CCommand::O { d, s, rs, o }) => {
+                assert_eq!((d, s, rs, o), (45.0, 0.5, -0.25, None));
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+    }
+
+    #[test]
+    fn binary_rejects_bad_frames() {
+        assert_eq!(
+            decode_binary::<SystemCommand>(&[]).unwrap_err(),
+            ProtocolError::EmptyFrame
+        );
+
+        let mut frame = encode_binary(&SystemCommand::L(LEDCommand::On)).unwrap();
+        frame[0] = FRAME_VERSION + 1;
+        assert_eq!(
+            decode_binary::<SystemCommand>(&frame).unwrap_err(),
+            ProtocolError::UnsupportedVersion(FRAME_VERSION + 1)
+        );
+
+        assert_eq!(
+            decode_binary::<SystemCommand>(&[FRAME_VERSION, 0xff]).unwrap_err(),
+            ProtocolError::Decode
+        );
+    }
+
+    #[test]
+    fn advertised_command_names_match_serde() {
+        let tag = |cmd: &SystemCommand, key: &str| {
+            let v: serde_json::Value = serde_json::to_value(cmd).unwrap();
+            String::from(v[key].as_str().unwrap())
+        };
+        let i2c = [
+            I2CCommand::T { d: 0.0, s: 0.0 },
+            I2CCommand::Y { s: 0.0, o: None },
+            I2CCommand::O {
+                d: 0.0,
+                s: 0.0,
+                rs: 0.0,
+                o: None,
+            },
+            I2CCommand::ReadIMU,
+            I2CCommand::Enable,
+            I2CCommand::Disable,
+            I2CCommand::CalibrateImu,
+            I2CCommand::SetImuCalibration(ImuCalibration::IDENTITY),
+            I2CCommand::ConfigureImu(Default::default()),
+            I2CCommand::Record { rate: 0.0 },
+            I2CCommand::Capture { n: 1, rate: 1.0 },
+            I2CCommand::ClearSafety,
+            I2CCommand::SetSafetyLimits(DEFAULT_SAFETY),
+            I2CCommand::SetTractionConfig(DEFAULT_TRACTION),
+            I2CCommand::SetThermalLimits(DEFAULT_THERMAL),
+            I2CCommand::SetMotionLimits(DEFAULT_LIMITS),
+        ];
+        let names: Vec<String> = i2c
+            .into_iter()
+            .map(|c| {
+                assert_eq!(tag(&SystemCommand::I(c), "ic"), c.name());
+                tag(&SystemCommand::I(c), "ic")
+            })
+            .collect();
+        assert_eq!(names, I2CCommand::NAMES);
+
+        let led = [
+            LEDCommand::On,
+            LEDCommand::Off,
+            LEDCommand::SC { r: 0, g: 0, b: 0 },
+            LEDCommand::Px {
+                i: 0,
+                r: 0,
+                g: 0,
+                b: 0,
+            },
+            LEDCommand::Range {
+                start: 0,
+                end: 1,
+                r: 0,
+                g: 0,
+                b: 0,
+            },
+            LEDCommand::Frame { colors: Vec::new() },
+            LEDCommand::Anim(Animation {
+                fx: Effect::Blink,
+                speed: 1.0,
+                color: [0; 3],
+                color2: [0; 3],
+            }),
+            LEDCommand::Brightness { level: 0 },
+            LEDCommand::Status { enabled: true },
+            LEDCommand::Hsv {
+                h: 0.0,
+                s: 0.0,
+                v: 0.0,
+            },
+            LEDCommand::Color {
+                name: String::from("red"),
+            },
+            LEDCommand::Fade {
+                color: ColorSpec::Rgb([0; 3]),
+                ms: 0,
+            },
+            LEDCommand::Heading { enabled: true },
+        ];
+        let names: Vec<String> = led
+            .into_iter()
+            .map(|c| {
+                let name = c.name();
+                assert_eq!(tag(&SystemCommand::L(c.clone()), "lc"), name);
+                tag(&SystemCommand::L(c), "lc")
+            })
+            .collect();
+        assert_eq!(names, LEDCommand::NAMES);
+
+        let get = [
+            GetCommand::Capabilities,
+            GetCommand::Leds,
+            GetCommand::Motors,
+            GetCommand::Motion,
+            GetCommand::State,
+            GetCommand::Metrics,
+            GetCommand::ImuCalibration,
+            GetCommand::Attitude,
+            GetCommand::ImuConfig,
+            GetCommand::Recorder,
+            GetCommand::Samples { max: None },
+            GetCommand::Safety,
+            GetCommand::Traction,
+            GetCommand::Thermal,
+            GetCommand::MotionLimits,
+        ];
+        let names: Vec<String> = get
+            .into_iter()
+            .map(|c| {
+                assert_eq!(tag(&SystemCommand::G(c), "gc"), c.name());
+                tag(&SystemCommand::G(c), "gc")
+            })
+            .collect();
+        assert_eq!(names, GetCommand::NAMES);
+    }
+
+    #[test]
+    fn hello_round_trip() {
+        let hello = Reply::Hello(Hello {
+            firmware: "0.1.0".into(),
+            protocol: PROTOCOL_VERSION,
+            frame: FRAME_VERSION,
+            capabilities: Capabilities {
+                wheel_radius: 0.148,
+                robot_radius: 0.195,
+                led_count: 2,
+                devices: Devices {
+                    imu: false,
+                    pwm: true,
+                },
+                commands: Commands {
+                    i: vec!["t".into()],
+                    l: vec![],
+                    g: vec!["capabilities".into()],
+                },
+            },
+        });
+        let text = encode_text(&hello).unwrap();
+        assert!(text.starts_with(r#"{"rt":"hello","firmware":"0.1.0""#));
+        assert_eq!(decode_text::<Reply>(&text).unwrap(), hello);
+        assert_eq!(
+            decode_binary::<Reply>(&encode_binary(&hello).unwrap()).unwrap(),
+            hello
+        );
+    }
+
+    #[test]
+    fn batch_frames() {
+        let text =
+            r#"[{"ct":"l","lc":"sc","r":255,"g":0,"b":0},{"ct":"i","ic":"y","s":0.5,"o":null}]"#;
+        match decode_text::<Frame>(text).unwrap() {
+            Frame::Batch(cmds) => {
+                assert!(matches!(
+                    cmds[0],
+                    SystemCommand::L(LEDCommand::SC { r: 255, .. })
+                ));
+                assert!(matches!(cmds[1], SystemCommand::I(I2CCommand::Y { .. })));
+            }
+            other => panic!("unexpected frame {:?}", other),
+        }
+        assert!(matches!(
+            decode_text::<Frame>(r#"{"ct":"l","lc":"on"}"#).unwrap(),
+            Frame::Single(SystemCommand::L(LEDCommand::On))
+        ));
+
+        // One bad entry rejects the whole batch
+        let bad = r#"[{"ct":"l","lc":"on"},{"ct":"i","ic":"t","d":"fast"}]"#;
+        assert_eq!(
+            decode_text::<Frame>(bad).unwrap_err(),
+            ProtocolError::Decode
+        );
+
+        let batch = Frame::Batch(vec![
+            SystemCommand::L(LEDCommand::Off),
+            SystemCommand::I(I2CCommand::Disable),
+        ]);
+        match decode_binary::<Frame>(&encode_binary(&batch).unwrap()).unwrap() {
+            Frame::Batch(cmds) => assert_eq!(cmds.len(), 2),
+            other => panic!("unexpected frame {:?}", other),
+        }
+
+        let reply = Reply::Batch {
+            results: vec![Reply::ack("a"), Reply::error("b")],
+        };
+        assert_eq!(
+            encode_text(&reply).unwrap(),
+            r#"{"rt":"batch","results":[{"rt":"ack","msg":"a"},{"rt":"error","msg":"b"}]}"#
+        );
+    }
+
+    #[test]
+    fn state_reply_format() {
+        let report = Reply::State(StateReport {
+            leds: LedState {
+                on: true,
+                color: Some([255, 0, 0]),
+                animation: None,
+                brightness: 255,
+            },
+            motors: MotorState {
+                enabled: true,
+                duties: [0.5, -0.25, 0.0],
+            },
+            motion: MotionState {
+                last: Some(I2CCommand::T { d: 90.0, s: 0.5 }),
+            },
+        });
+        let text = encode_text(&report).unwrap();
+        assert_eq!(
+            text,
+            r#"{"rt":"state","leds":{"on":true,"color":[255,0,0],"animation":null,"brightness":255},"motors":{"enabled":true,"duties":[0.5,-0.25,0.0]},"motion":{"last":{"ic":"t","d":90.0,"s":0.5}}}"#
+        );
+        assert_eq!(decode_text::<Reply>(&text).unwrap(), report);
+        assert_eq!(
+            decode_binary::<Reply>(&encode_binary(&report).unwrap()).unwrap(),
+            report
+        );
+    }
+
+    #[test]
+    fn samples_reply_format() {
+        let cmd: SystemCommand = decode_text(r#"{"ct":"g","gc":"samples","max":8}"#).unwrap();
+        assert!(matches!(
+            cmd,
+            SystemCommand::G(GetCommand::Samples { max: Some(8) })
+        ));
+
+        let reply = Reply::Samples {
+            samples: vec![ImuRecord {
+                t: 1500,
+                sample: ImuData {
+                    accel: [0.0, 0.0, 1.0],
+                    gyro: [0.0; 3],
+                    temp: 25.0,
+                },
+            }],
+            remaining: 3,
+        };
+        let text = encode_text(&reply).unwrap();
+        assert_eq!(
+            text,
+            r#"{"rt":"samples","samples":[{"t":1500,"accel":[0.0,0.0,1.0],"gyro":[0.0,0.0,0.0],"temp":25.0}],"remaining":3}"#
+        );
+        assert_eq!(decode_text::<Reply>(&text).unwrap(), reply);
+        assert_eq!(
+            decode_binary::<Reply>(&encode_binary(&reply).unwrap()).unwrap(),
+            reply
+        );
+    }
+
+    #[test]
+    fn text_reply_format() {
+        let text = encode_text(&Reply::ack("ok")).unwrap();
+        assert_eq!(text, r#"{"rt":"ack","msg":"ok"}"#);
+        assert_eq!(decode_text::<Reply>(&text).unwrap(), Reply::ack("ok"));
+    }
+}
@@ -7,10 +7,16 @@
 
 extern crate alloc;
 
-use alloc::{string::String, vec::Vec};
+use alloc::{format, string::String, vec, vec::Vec};
+use core::pin::pin;
 
+use embassy_executor::{SpawnError, Spawner};
+use embassy_futures::select::{select, Either};
 use embassy_net::Stack;
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    mutex::{Mutex, MutexGuard},
+};
 use embassy_time::Duration;
 use embedded_io_async::Read;
 use hashbrown::HashMap;
@@ -28,13 +34,49 @@ use picoserve::{
 };
 use serde::Deserialize;
 
+use super::{
+    api,
+    protocol::{self, Capabilities, Frame, Hello, ProtocolError, Reply, MAX_BATCH},
+    sse,
+};
 use crate::utils::{
-    controllers::{SystemCommand, I2C_CHANNEL, LED_CHANNEL},
+    controllers::{
+        attitude,
+        i2c::I2CCommand,
+        i2c::{CALIBRATION_RESULT, IMU_CONFIG_RESULT},
+        limits, recorder,
+        safety::{self, SafetyReport},
+        state,
+        thermal::{self, ThermalLevel, ThermalReport},
+        traction, GetCommand, SystemCommand, I2C_CHANNEL, IMU_READING, LED_CHANNEL,
+        MOTION_SETPOINT,
+    },
+    events::{self, Event, Listener},
     frontend::{CSS, HTML, JAVA},
+    metrics,
 };
 
+/// How long a `ReadIMU` request waits for the I2C task to publish a sample.
+const IMU_REPLY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How long a `CalibrateImu` request waits for the I2C task to finish sampling.
+const CALIBRATION_REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Held while commands are forwarded to their controllers, so a batch from one
+/// session is never interleaved with commands from another. Released before
+/// waiting for the I2C task to answer.
+static DISPATCH_LOCK: Mutex<CriticalSectionRawMutex, ()> = Mutex::new(());
+
+/// Held by a request that waits on `IMU_READING`, `CALIBRATION_RESULT` or
+/// `IMU_CONFIG_RESULT`, from before its commands are forwarded until they are
+/// answered, so no other request takes its results.
+static REPLY_LOCK: Mutex<CriticalSectionRawMutex, ()> = Mutex::new(());
+
 pub struct ServerTimer;
-pub struct WebSocket;
+/// A WebSocket session, accepting messages of up to `max_message_size` bytes.
+pub struct WebSocket {
+    pub max_message_size: usize,
+}
 #[derive(Clone, Debug)]
 pub struct SessionState {
     pub last_seen: u64,
@@ -66,6 +108,45 @@ impl picoserve::Timer for ServerTimer {
 /// Handles incoming WebSocket connections.
 impl WebSocketCallback for WebSocket {
     async fn run<Reader, Writer>(
+        self,
+        rx: SocketRx<Reader>,
+        tx: SocketTx<Writer>,
+    ) -> Result<(), Writer::Error>
+    where
+        Reader: embedded_aio::Read,
+        Writer: embedded_aio::Write<Error = Reader::Error>,
+    {
+        metrics::ws_connected();
+        let clients = state::update(|s| {
+            s.ws_clients += 1;
+            s.ws_clients
+        });
+        events::publish(Event::Connection {
+            connected: true,
+            clients,
+        });
+        let result = self.serve(rx, tx).await;
+        metrics::ws_disconnected();
+        let clients = state::update(|s| {
+            s.ws_clients -= 1;
+            s.ws_clients
+        });
+        events::publish(Event::Connection {
+            connected: false,
+            clients,
+        });
+        result
+    }
+}
+
+impl WebSocket {
+    /// Exchange frames until the client closes the connection.
+    ///
+    /// While waiting for a frame the session watches the event bus, and pushes
+    /// a `thermal` report as soon as the level changes and a `safety` report
+    /// whenever the cutoff trips or clears, in the encoding of the client's
+    /// last frame.
+    async fn serve<Reader, Writer>(
         self,
         mut rx: SocketRx<Reader>,
         mut tx: SocketTx<Writer>,
@@ -74,48 +155,76 @@ impl WebSocketCallback for WebSocket {
         Reader: embedded_aio::Read,
         Writer: embedded_aio::Write<Error = Reader::Error>,
     {
-        let mut buffer = [0; 1024];
+        let mut buffer = vec![0; self.max_message_size];
 
-        tx.send_text("Connected").await?;
+        match protocol::encode_text(&Reply::Hello(Hello::current())) {
+            Ok(hello) => tx.send_text(&hello).await?,
+            Err(error) => tracing::error!(?error, "error encoding hello"),
+        }
 
+        let mut listener = Listener::background();
+        let mut thermal_seen = ThermalLevel::Normal;
+        let mut binary = false;
         let close_reason = loop {
-            match rx.next_message(&mut buffer).await {
+            // Events are handled without dropping the pending read, so a frame
+            // arriving meanwhile is never cut in half.
+            let message = {
+                let mut next = pin!(rx.next_message(&mut buffer));
+                loop {
+                    let event = async {
+                        match listener.as_mut() {
+                            Some(listener) => listener.next().await,
+                            None => core::future::pending().await,
+                        }
+                    };
+                    let event = match select(next.as_mut(), event).await {
+                        Either::First(message) => break message,
+                        Either::Second(event) => event,
+                    };
+                    let report =
+                        thermal_change(&mut thermal_seen, &event).or_else(|| safety_change(&event));
+                    if let Some(report) = report {
+                        send_reply(&mut tx, &report, binary).await?;
+                    }
+                }
+            };
+
+            match message {
                 Ok(Message::Pong(_)) => continue,
                 Ok(Message::Ping(data)) => tx.send_pong(data).await?,
                 Ok(Message::Close(reason)) => {
                     tracing::info!(?reason, "websocket closed");
                     break None;
                 }
-                Ok(Message::Text(data)) => match serde_json::from_str::<SystemCommand>(data) {
-                    Ok(SystemCommand::I(i2c_cmd)) => {
-                        I2C_CHANNEL.send(i2c_cmd).await;
-                        tx.send_text("I2C command received and forwarded").await?;
-                    }
-                    Ok(SystemCommand::L(led_cmd)) => {
-                        LED_CHANNEL.send(led_cmd).await;
-                        tx.send_text("LED command received and forwarded").await?;
-                    }
-                    Err(error) => {
-                        tracing::error!(?error, "error deserializing SystemCommand");
-                        tx.send_text("Invalid command format").await?
-                    }
-                },
-                Ok(Message::Binary(data)) => match serde_json::from_slice::<SystemCommand>(data) {
-                    Ok(SystemCommand::I(i2c_cmd)) => {
-                        I2C_CHANNEL.send(i2c_cmd).await;
-                        tx.send_binary(b"I2C command received and forwarded")
-                            .await?
-                    }
-                    Ok(SystemCommand::L(led_cmd)) => {
-                        LED_CHANNEL.send(led_cmd).await;
-                        tx.send_binary(b"LED command received and forwarded")
-                            .await?
-                    }
-                    Err(error) => {
-                        tracing::error!(?error, "error deserializing incoming message");
-                        tx.send_binary(b"Invalid command format").await?
-                    }
-                },
+                Ok(Message::Text(data)) => {
+                    binary = false;
+                    let reply = match protocol::decode_text::<Frame>(data) {
+                        Ok(frame) => dispatch_frame(frame).await,
+                        Err(error) => {
+                            metrics::parse_failure();
+                            tracing::error!(?error, "error deserializing SystemCommand");
+                            Reply::error("Invalid command format")
+                        }
+                    };
+                    send_reply(&mut tx, &reply, binary).await?;
+                }
+                Ok(Message::Binary(data)) => {
+                    binary = true;
+                    let reply = match protocol::decode_binary::<Frame>(data) {
+                        Ok(frame) => dispatch_frame(frame).await,
+                        Err(ProtocolError::UnsupportedVersion(version)) => {
+                            metrics::parse_failure();
+                            tracing::warn!(version, "unsupported binary frame version");
+                            Reply::error("Unsupported frame version")
+                        }
+                        Err(error) => {
+                            metrics::parse_failure();
+                            tracing::error!(?error, "error decoding binary frame");
+                            Reply::error("Invalid command format")
+                        }
+                    };
+                    send_reply(&mut tx, &reply, binary).await?;
+                }
                 Err(error) => {
                     tracing::error!(?error, "websocket error");
                     let code = match error {
@@ -135,6 +244,378 @@ impl WebSocketCallback for WebSocket {
     }
 }
 
+/// Encode `reply` as a binary or text frame and send it.
+async fn send_reply<Writer: embedded_aio::Write>(
+    tx: &mut SocketTx<Writer>,
+    reply: &Reply,
+    binary: bool,
+) -> Result<(), Writer::Error> {
+    if binary {
+        match protocol::encode_binary(reply) {
+            Ok(frame) => tx.send_binary(&frame).await?,
+            Err(error) => tracing::error!(?error, "error encoding reply"),
+        }
+    } else {
+        match protocol::encode_text(reply) {
+            Ok(text) => tx.send_text(&text).await?,
+            Err(error) => tracing::error!(?error, "error encoding reply"),
+        }
+    }
+    Ok(())
+}
+
+/// A thermal report if `event` is a reading whose level differs from `seen`,
+/// which is updated.
+fn thermal_change(
+    seen: &mut ThermalLevel,
+    event: &Event,
+) -> Option<Reply> {
+    let Event::Thermal(status) = event else {
+        return None;
+    };
+    if status.level == *seen {
+        return None;
+    }
+    *seen = status.level;
+    Some(Reply::Thermal(ThermalReport {
+        limits: thermal::limits(),
+        status: Some(*status),
+    }))
+}
+
+/// A safety report if `event` is the cutoff tripping or clearing.
+fn safety_change(event: &Event) -> Option<Reply> {
+    let Event::Safety(trip) = event else {
+        return None;
+    };
+    Some(Reply::Safety(SafetyReport {
+        limits: safety::limits(),
+        trip: *trip,
+    }))
+}
+
+/// Dispatch a decoded frame, either a single command or a batch.
+pub async fn dispatch_frame(frame: Frame) -> Reply {
+    match frame {
+        Frame::Single(cmd) => dispatch(cmd).await,
+        Frame::Batch(cmds) => dispatch_batch(cmds).await,
+    }
+}
+
+/// Check a decoded command, forward it to its controller and build the reply.
+pub async fn dispatch(cmd: SystemCommand) -> Reply {
+    match try_dispatch(cmd).await {
+        Ok(reply) => reply,
+        Err(rejection) => Reply::error(rejection.message()),
+    }
+}
+
+/// Count `cmd`, check it, then forward it and build the reply; the path shared
+/// by WebSocket and REST clients. Returns why `cmd` was refused if it never
+/// reached its controller.
+pub async fn try_dispatch(cmd: SystemCommand) -> Result<Reply, Rejection> {
+    metrics::command(&cmd);
+    let cmd = check(cmd)?;
+    let _replies = claim_replies(core::slice::from_ref(&cmd)).await;
+    let forwarded = {
+        let _guard = DISPATCH_LOCK.lock().await;
+        forward(cmd).await
+    };
+    Ok(forwarded.reply().await)
+}
+
+/// Forward a batch of commands in order, holding the dispatch lock until all of
+/// them are forwarded.
+///
+/// Every command is checked before any is forwarded, so one that is
+/// unavailable or out of range rejects the whole batch; otherwise the reply
+/// carries one result per command.
+pub async fn dispatch_batch(cmds: Vec<SystemCommand>) -> Reply {
+    if cmds.is_empty() {
+        return Reply::error("Empty batch");
+    }
+    if cmds.len() > MAX_BATCH {
+        return Reply::error("Batch too large");
+    }
+
+    for cmd in &cmds {
+        metrics::command(cmd);
+    }
+    let mut checked = Vec::with_capacity(cmds.len());
+    for (index, cmd) in cmds.into_iter().enumerate() {
+        match check(cmd) {
+            Ok(cmd) => checked.push(cmd),
+            Err(rejection) => {
+                let msg = rejection.message();
+                return Reply::error(&format!("Batch rejected at command {index}: {msg}"));
+            }
+        }
+    }
+    let cmds = checked;
+
+    let _replies = claim_replies(&cmds).await;
+    let mut forwarded = Vec::with_capacity(cmds.len());
+    {
+        let _guard = DISPATCH_LOCK.lock().await;
+        for cmd in cmds {
+            forwarded.push(forward(cmd).await);
+        }
+    }
+    let mut results = Vec::with_capacity(forwarded.len());
+    for command in forwarded {
+        results.push(command.reply().await);
+    }
+    Reply::Batch { results }
+}
+
+/// Take `REPLY_LOCK` if any of `cmds` is answered by the I2C task, dropping
+/// results left over from requests that timed out.
+async fn claim_replies(
+    cmds: &[SystemCommand]
+) -> Option<MutexGuard<'static, CriticalSectionRawMutex, ()>> {
+    let awaits_reply = |cmd: &SystemCommand| {
+        matches!(
+            cmd,
+            SystemCommand::I(
+                I2CCommand::ReadIMU | I2CCommand::CalibrateImu | I2CCommand::ConfigureImu(_)
+            )
+        )
+    };
+    if !cmds.iter().any(awaits_reply) {
+        return None;
+    }
+    let guard = REPLY_LOCK.lock().await;
+    IMU_READING.clear();
+    CALIBRATION_RESULT.clear();
+    IMU_CONFIG_RESULT.clear();
+    Some(guard)
+}
+
+/// Why `cmd` cannot be carried out right now, if its device failed to
+/// initialize. Without the motor driver only motion, `enable` and `disable`
+/// are refused; IMU commands and settings still go through.
+pub fn unavailable(cmd: &SystemCommand) -> Option<&'static str> {
+    let s = state::snapshot();
+    match cmd {
+        SystemCommand::I(
+            I2CCommand::ReadIMU
+            | I2CCommand::CalibrateImu
+            | I2CCommand::ConfigureImu(_)
+            | I2CCommand::Record { .. }
+            | I2CCommand::Capture { .. },
+        ) if !s.imu_ready => Some("IMU not initialized"),
+        SystemCommand::I(i2c_cmd)
+            if !s.pwm_ready
+                && (i2c_cmd.is_motion()
+                    || matches!(i2c_cmd, I2CCommand::Enable | I2CCommand::Disable)) =>
+        {
+            Some("Motor driver not initialized")
+        }
+        SystemCommand::I(i2c_cmd) if i2c_cmd.is_motion() && s.safety.is_some() => {
+            Some("Safety cutoff latched; send clear_safety")
+        }
+        SystemCommand::I(I2CCommand::Enable)
+            if s.thermal.is_some_and(|t| t.level == ThermalLevel::Critical) =>
+        {
+            Some("Motors too hot; wait for them to cool down")
+        }
+        SystemCommand::L(_) if s.led_count == 0 => Some("LED controller not running"),
+        SystemCommand::G(GetCommand::Attitude) if attitude::current().is_none() => {
+            Some("Attitude estimator not running")
+        }
+        _ => None,
+    }
+}
+
+/// Why a command was refused before it was forwarded.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rejection {
+    /// Its device failed to initialize or is locked out (see `unavailable`).
+    Unavailable(&'static str),
+    /// Its parameters are out of range (see `validate`).
+    Invalid(String),
+}
+
+impl Rejection {
+    /// Message sent back to the client.
+    pub fn message(&self) -> &str {
+        match self {
+            Rejection::Unavailable(msg) => msg,
+            Rejection::Invalid(msg) => msg,
+        }
+    }
+}
+
+/// Refuse `cmd` if `unavailable` or `validate` objects to it, otherwise return
+/// it normalized.
+fn check(cmd: SystemCommand) -> Result<SystemCommand, Rejection> {
+    if let Some(msg) = unavailable(&cmd) {
+        return Err(Rejection::Unavailable(msg));
+    }
+    validate(cmd).map_err(|msg| {
+        tracing::warn!(%msg, "command rejected");
+        Rejection::Invalid(msg)
+    })
+}
+
+/// Validate and normalize motion parameters against `limits::limits()`, LED
+/// addresses against the strip length, and IMU calibrations, settings,
+/// recording parameters, safety, traction, thermal and motion limits,
+/// describing the problem on failure.
+pub fn validate(cmd: SystemCommand) -> Result<SystemCommand, String> {
+    match cmd {
+        SystemCommand::I(I2CCommand::SetImuCalibration(calibration)) => match calibration.check() {
+            Ok(()) => Ok(cmd),
+            Err(error) => Err(format!("Invalid IMU calibration: {error}")),
+        },
+        SystemCommand::I(I2CCommand::ConfigureImu(update)) => {
+            match state::snapshot().imu_config.with(update).check() {
+                Ok(()) => Ok(cmd),
+                Err(error) => Err(format!("Invalid IMU configuration: {error}")),
+            }
+        }
+        SystemCommand::I(I2CCommand::Record { rate }) => match recorder::check(rate, None) {
+            Ok(()) => Ok(cmd),
+            Err(error) => Err(format!("Invalid recording: {error}")),
+        },
+        SystemCommand::I(I2CCommand::Capture { n, rate }) => match recorder::check(rate, Some(n)) {
+            Ok(()) => Ok(cmd),
+            Err(error) => Err(format!("Invalid recording: {error}")),
+        },
+        SystemCommand::I(I2CCommand::SetSafetyLimits(limits)) => match limits.check() {
+            Ok(()) => Ok(cmd),
+            Err(error) => Err(format!("Invalid safety limits: {error}")),
+        },
+        SystemCommand::I(I2CCommand::SetTractionConfig(config)) => match config.check() {
+            Ok(()) => Ok(cmd),
+            Err(error) => Err(format!("Invalid traction settings: {error}")),
+        },
+        SystemCommand::I(I2CCommand::SetThermalLimits(limits)) => match limits.check() {
+            Ok(()) => Ok(cmd),
+            Err(error) => Err(format!("Invalid thermal limits: {error}")),
+        },
+        SystemCommand::I(I2CCommand::SetMotionLimits(limits)) => match limits.check_limits() {
+            Ok(()) => Ok(cmd),
+            Err(error) => Err(format!("Invalid motion limits: {error}")),
+        },
+        SystemCommand::I(i2c_cmd) => limits::check(i2c_cmd)
+            .map(SystemCommand::I)
+            .map_err(|error| format!("Invalid motion command: {error}")),
+        SystemCommand::L(led_cmd) => match led_cmd.check(state::snapshot().led_count) {
+            Ok(()) => Ok(SystemCommand::L(led_cmd)),
+            Err(error) => Err(format!("Invalid LED command: {error}")),
+        },
+        other => Ok(other),
+    }
+}
+
+/// A forwarded command: its reply, or the answer it waits for from the I2C
+/// task.
+enum Forwarded {
+    Reply(Reply),
+    /// Waits for a sample, or the error that failed the read, on `IMU_READING`.
+    ImuReading,
+    /// Waits for the coefficients on `CALIBRATION_RESULT`.
+    Calibration,
+    /// Waits for the settings read back from the IMU on `IMU_CONFIG_RESULT`.
+    ImuConfig,
+}
+
+impl Forwarded {
+    /// Build the reply, waiting for the I2C task where needed.
+    ///
+    /// Replies with an error if the I2C task did not answer in time (e.g. the
+    /// IMU failed to initialize), failed to read the IMU, or refused a
+    /// calibration or configuration.
+    async fn reply(self) -> Reply {
+        match self {
+            Forwarded::Reply(reply) => reply,
+            Forwarded::ImuReading => {
+                let sample = IMU_READING.receive();
+                match embassy_time::with_timeout(IMU_REPLY_TIMEOUT, sample).await {
+                    Ok(Ok(imu)) => Reply::Imu(imu),
+                    Ok(Err(kind)) => Reply::error(&format!("IMU read failed: {kind}")),
+                    Err(_) => Reply::error("IMU read timed out"),
+                }
+            }
+            Forwarded::Calibration => {
+                let result = CALIBRATION_RESULT.receive();
+                match embassy_time::with_timeout(CALIBRATION_REPLY_TIMEOUT, result).await {
+                    Ok(Ok(calibration)) => Reply::ImuCalibration(calibration),
+                    Ok(Err(error)) => Reply::error(&format!("IMU calibration refused: {error}")),
+                    Err(_) => Reply::error("IMU calibration timed out"),
+                }
+            }
+            Forwarded::ImuConfig => {
+                let result = IMU_CONFIG_RESULT.receive();
+                match embassy_time::with_timeout(IMU_REPLY_TIMEOUT, result).await {
+                    Ok(Ok(config)) => Reply::ImuConfig(config),
+                    Ok(Err(error)) => Reply::error(&format!("IMU configuration failed: {error}")),
+                    Err(_) => Reply::error("IMU configuration timed out"),
+                }
+            }
+        }
+    }
+}
+
+/// Hand a checked `cmd` to its controller. Commands answered by the I2C task
+/// are only queued; `Forwarded::reply` waits for the answer.
+async fn forward(cmd: SystemCommand) -> Forwarded {
+    let reply = match cmd {
+        SystemCommand::I(I2CCommand::ReadIMU) => {
+            I2C_CHANNEL.send(I2CCommand::ReadIMU).await;
+            return Forwarded::ImuReading;
+        }
+        SystemCommand::I(I2CCommand::CalibrateImu) => {
+            I2C_CHANNEL.send(I2CCommand::CalibrateImu).await;
+            return Forwarded::Calibration;
+        }
+        SystemCommand::I(i2c_cmd @ I2CCommand::ConfigureImu(_)) => {
+            I2C_CHANNEL.send(i2c_cmd).await;
+            return Forwarded::ImuConfig;
+        }
+        SystemCommand::I(i2c_cmd) if i2c_cmd.is_motion() => {
+            if MOTION_SETPOINT.signaled() {
+                tracing::debug!("Replacing motion setpoint not yet applied");
+            }
+            MOTION_SETPOINT.signal(i2c_cmd);
+            Reply::ack("Motion setpoint updated")
+        }
+        SystemCommand::I(i2c_cmd) => {
+            I2C_CHANNEL.send(i2c_cmd).await;
+            Reply::ack("I2C command received and forwarded")
+        }
+        SystemCommand::L(led_cmd) => {
+            LED_CHANNEL.send(led_cmd).await;
+            Reply::ack("LED command received and forwarded")
+        }
+        SystemCommand::G(GetCommand::Capabilities) => Reply::Capabilities(Capabilities::current()),
+        SystemCommand::G(GetCommand::Leds) => Reply::Leds(state::snapshot().leds),
+        SystemCommand::G(GetCommand::Motors) => Reply::Motors(state::snapshot().motors),
+        SystemCommand::G(GetCommand::Motion) => Reply::Motion(state::snapshot().motion),
+        SystemCommand::G(GetCommand::State) => Reply::State(state::snapshot().report()),
+        SystemCommand::G(GetCommand::Metrics) => Reply::Metrics(metrics::report()),
+        SystemCommand::G(GetCommand::Attitude) => match attitude::current() {
+            Some(attitude) => Reply::Attitude(attitude),
+            None => Reply::error("Attitude estimator not running"),
+        },
+        SystemCommand::G(GetCommand::ImuCalibration) => {
+            Reply::ImuCalibration(state::snapshot().imu_calibration)
+        }
+        SystemCommand::G(GetCommand::ImuConfig) => Reply::ImuConfig(state::snapshot().imu_config),
+        SystemCommand::G(GetCommand::Recorder) => Reply::Recorder(recorder::status()),
+        SystemCommand::G(GetCommand::Samples { max }) => {
+            let (samples, remaining) = recorder::take(max.unwrap_or(recorder::MAX_DOWNLOAD));
+            Reply::Samples { samples, remaining }
+        }
+        SystemCommand::G(GetCommand::Safety) => Reply::Safety(safety::report()),
+        SystemCommand::G(GetCommand::Traction) => Reply::Traction(traction::report()),
+        SystemCommand::G(GetCommand::Thermal) => Reply::Thermal(thermal::report()),
+        SystemCommand::G(GetCommand::MotionLimits) => Reply::MotionLimits(limits::limits()),
+    };
+    Forwarded::Reply(reply)
+}
+
 #[allow(dead_code)]
 impl SessionManager {
     /// Creates a new session with the given session ID and timestamp.
@@ -203,105 +684,279 @@ impl SessionManager {
 //noinspection ALL
 //noinspection ALL
 /// Creates WS Server
+///
+/// Serves one connection at a time with the default buffer sizes. Use
+/// `ServerBuilder` for larger buffers or several concurrent connections.
 pub async fn run(
     id: usize,
     port: u16,
     stack: Stack<'static>,
     config: Option<&'static picoserve::Config<Duration>>,
 ) -> ! {
-    let default_config = picoserve::Config::new(picoserve::Timeouts {
-        start_read_request: Some(Duration::from_secs(5)),
-        persistent_start_read_request: None,
-        read_request: Some(Duration::from_secs(1)),
-        write: Some(Duration::from_secs(5)),
-    });
-
-    let config = config.unwrap_or(&default_config);
-
-    let router = Router::new()
-        // Serve the HTML file at "/"
-        .route(
-            "/",
-            picoserve::routing::get(|| async {
-                // Serve HTML content
-                picoserve::response::Response::new(
-                    StatusCode::OK,
-                    HTML, // Static HTML content
-                )
-                .with_headers([
-                    ("Content-Type", "text/html; charset=utf-8"),
-                    ("Content-Encoding", "gzip"),
-                ])
-            }),
-        )
-        // Serve the CSS file at "/style.css"
-        .route(
-            "/style.css",
-            picoserve::routing::get(|| async {
-                // Serve CSS content
-                picoserve::response::Response::new(
-                    StatusCode::OK,
-                    CSS, // Static CSS content
-                )
-                .with_headers([
-                    ("Content-Type", "text/css; charset=utf-8"),
-                    ("Content-Encoding", "gzip"),
-                ])
-            }),
-        )
-        // Serve the JS file at "/script.js"
-        .route(
-            "/script.js",
-            picoserve::routing::get(|| async {
-                // Serve JS content
-                picoserve::response::Response::new(
-                    StatusCode::OK,
-                    JAVA, // Static JS content
-                )
-                .with_headers([
-                    ("Content-Type", "application/javascript; charset=utf-8"),
-                    ("Content-Encoding", "gzip"),
-                ])
-            }),
-        )
-        // WebSocket communication on "/ws"
-        .route(
-            "/ws",
-            picoserve::routing::get(|params: WsConnectionParams| async move {
-                let session_id = params.query.session;
-                tracing::info!("New WebSocket connection with session id: {}", session_id);
-                let now = embassy_time::Instant::now().as_secs();
-                SessionManager::create_session(session_id.clone(), now).await;
-                params
-                    .upgrade
-                    .on_upgrade(WebSocket)
-                    .with_protocol("messages")
-            }),
-        );
+    let mut builder = ServerBuilder::new(port);
+    if let Some(config) = config {
+        builder = builder.timeouts(config);
+    }
+    builder.build().serve(id, stack).await
+}
 
-    // Print out the IP and port before starting the server.
-    if let Some(ip_cfg) = stack.config_v4() {
-        tracing::info!("Starting server at {}:{}", ip_cfg.address, port);
-    } else {
-        tracing::warn!(
-            "Starting WebSocket server on port {port}, but no IPv4 address is assigned yet!"
-        );
+/// Upper bound on listener tasks spawned by `Server::spawn`.
+pub const MAX_SERVER_LISTENERS: usize = 8;
+
+/// Builder for `Server`, starting from the sizes `run` has always used.
+///
+/// ```ignore
+/// let server = mk_static!(Server, ServerBuilder::new(80)
+///     .http_buffer(8192)
+///     .max_message_size(4096)
+///     .listeners(4)
+///     .build());
+/// server.spawn(&spawner, stack)?;
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct ServerBuilder {
+    server: Server,
+}
+
+/// Server resources and limits, shared by all listener tasks.
+#[derive(Debug, Clone, Copy)]
+pub struct Server {
+    /// TCP port to listen on.
+    pub port: u16,
+    /// TCP receive buffer per connection, in bytes.
+    pub rx_buffer: usize,
+    /// TCP transmit buffer per connection, in bytes.
+    pub tx_buffer: usize,
+    /// HTTP request buffer per connection (bounds request bodies), in bytes.
+    pub http_buffer: usize,
+    /// Largest WebSocket message accepted, in bytes.
+    pub max_message_size: usize,
+    /// Number of connections served concurrently by `spawn`.
+    pub listeners: usize,
+    /// picoserve timeouts; `None` uses the defaults.
+    pub timeouts: Option<&'static picoserve::Config<Duration>>,
+}
+
+impl ServerBuilder {
+    /// Start from the default sizes: 1 KiB TCP buffers and WebSocket messages,
+    /// a 4 KiB HTTP buffer and a single listener.
+    pub fn new(port: u16) -> Self {
+        ServerBuilder {
+            server: Server {
+                port,
+                rx_buffer: 1024,
+                tx_buffer: 1024,
+                http_buffer: 4096,
+                max_message_size: 1024,
+                listeners: 1,
+                timeouts: None,
+            },
+        }
+    }
+
+    /// TCP receive buffer per connection, in bytes.
+    pub fn rx_buffer(
+        mut self,
+        bytes: usize,
+    ) -> Self {
+        self.server.rx_buffer = bytes;
+        self
+    }
+
+    /// TCP transmit buffer per connection, in bytes.
+    pub fn tx_buffer(
+        mut self,
+        bytes: usize,
+    ) -> Self {
+        self.server.tx_buffer = bytes;
+        self
+    }
+
+    /// HTTP request buffer per connection, in bytes.
+    pub fn http_buffer(
+        mut self,
+        bytes: usize,
+    ) -> Self {
+        self.server.http_buffer = bytes;
+        self
+    }
+
+    /// Largest WebSocket message accepted, in bytes.
+    pub fn max_message_size(
+        mut self,
+        bytes: usize,
+    ) -> Self {
+        self.server.max_message_size = bytes;
+        self
     }
 
-    let (mut rx_buffer, mut tx_buffer, mut http_buffer) = ([0; 1024], [0; 1024], [0; 4096]);
+    /// Number of concurrent listener tasks (clamped to `1..=MAX_SERVER_LISTENERS`).
+    pub fn listeners(
+        mut self,
+        count: usize,
+    ) -> Self {
+        self.server.listeners = count.clamp(1, MAX_SERVER_LISTENERS);
+        self
+    }
+
+    /// picoserve timeouts to use instead of the defaults.
+    pub fn timeouts(
+        mut self,
+        config: &'static picoserve::Config<Duration>,
+    ) -> Self {
+        self.server.timeouts = Some(config);
+        self
+    }
+
+    pub fn build(self) -> Server {
+        self.server
+    }
+}
 
-    picoserve::listen_and_serve_with_state(
-        id,
-        &router,
-        config,
-        stack,
-        port,
-        &mut rx_buffer,
-        &mut tx_buffer,
-        &mut http_buffer,
-        &(),
-    )
-    .await
+#[embassy_executor::task(pool_size = MAX_SERVER_LISTENERS)]
+async fn listener_task(
+    id: usize,
+    stack: Stack<'static>,
+    server: &'static Server,
+) -> ! {
+    server.serve(id, stack).await
+}
+
+impl Server {
+    /// Spawn `listeners` tasks on `spawner`, each serving one connection at a
+    /// time.
+    pub fn spawn(
+        &'static self,
+        spawner: &Spawner,
+        stack: Stack<'static>,
+    ) -> Result<(), SpawnError> {
+        for id in 0..self.listeners {
+            spawner.spawn(listener_task(id, stack, self))?;
+        }
+        Ok(())
+    }
+
+    /// Serve connections one after another as listener `id`.
+    pub async fn serve(
+        &self,
+        id: usize,
+        stack: Stack<'static>,
+    ) -> ! {
+        let default_config = picoserve::Config::new(picoserve::Timeouts {
+            start_read_request: Some(Duration::from_secs(5)),
+            persistent_start_read_request: None,
+            read_request: Some(Duration::from_secs(1)),
+            write: Some(Duration::from_secs(5)),
+        });
+
+        let config = self.timeouts.unwrap_or(&default_config);
+        let max_message_size = self.max_message_size;
+
+        let router = Router::new()
+            // Serve the HTML file at "/"
+            .route(
+                "/",
+                picoserve::routing::get(|| async {
+                    // Serve HTML content
+                    picoserve::response::Response::new(
+                        StatusCode::OK,
+                        HTML, // Static HTML content
+                    )
+                    .with_headers([
+                        ("Content-Type", "text/html; charset=utf-8"),
+                        ("Content-Encoding", "gzip"),
+                    ])
+                }),
+            )
+            // Serve the CSS file at "/style.css"
+            .route(
+                "/style.css",
+                picoserve::routing::get(|| async {
+                    // Serve CSS content
+                    picoserve::response::Response::new(
+                        StatusCode::OK,
+                        CSS, // Static CSS content
+                    )
+                    .with_headers([
+                        ("Content-Type", "text/css; charset=utf-8"),
+                        ("Content-Encoding", "gzip"),
+                    ])
+                }),
+            )
+            // Serve the JS file at "/script.js"
+            .route(
+                "/script.js",
+                picoserve::routing::get(|| async {
+                    // Serve JS content
+                    picoserve::response::Response::new(
+                        StatusCode::OK,
+                        JAVA, // Static JS content
+                    )
+                    .with_headers([
+                        ("Content-Type", "application/javascript; charset=utf-8"),
+                        ("Content-Encoding", "gzip"),
+                    ])
+                }),
+            )
+            // REST API over the same dispatch path
+            .route("/api/command", picoserve::routing::post(api::command))
+            .route("/api/status", picoserve::routing::get(api::status))
+            .route("/api/imu", picoserve::routing::get(api::imu))
+            // Counters in Prometheus text format
+            .route(
+                "/metrics",
+                picoserve::routing::get(|| async {
+                    picoserve::response::Response::new(
+                        StatusCode::OK,
+                        metrics::report().to_prometheus(),
+                    )
+                    .with_headers([("Content-Type", "text/plain; version=0.0.4")])
+                }),
+            )
+            // Telemetry stream for plain HTTP clients
+            .route("/events", picoserve::routing::get(sse::events))
+            // WebSocket communication on "/ws"
+            .route(
+                "/ws",
+                picoserve::routing::get(move |params: WsConnectionParams| async move {
+                    let session_id = params.query.session;
+                    tracing::info!("New WebSocket connection with session id: {}", session_id);
+                    let now = embassy_time::Instant::now().as_secs();
+                    SessionManager::create_session(session_id.clone(), now).await;
+                    params
+                        .upgrade
+                        .on_upgrade(WebSocket { max_message_size })
+                        .with_protocol("messages")
+                }),
+            );
+
+        // Print out the IP and port before starting the server.
+        if let Some(ip_cfg) = stack.config_v4() {
+            tracing::info!(id, "Starting server at {}:{}", ip_cfg.address, self.port);
+        } else {
+            tracing::warn!(
+                "Starting WebSocket server on port {}, but no IPv4 address is assigned yet!",
+                self.port
+            );
+        }
+
+        let mut rx_buffer = vec![0; self.rx_buffer];
+        let mut tx_buffer = vec![0; self.tx_buffer];
+        let mut http_buffer = vec![0; self.http_buffer];
+
+        picoserve::listen_and_serve_with_state(
+            id,
+            &router,
+            config,
+            stack,
+            self.port,
+            &mut rx_buffer,
+            &mut tx_buffer,
+            &mut http_buffer,
+            &(),
+        )
+        .await
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -339,3 +994,130 @@ impl<'r, S> FromRequest<'r, S> for WsConnectionParams {
         Ok(WsConnectionParams { upgrade, query })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::utils::controllers::{safety::SafetyTrip, thermal::ThermalStatus};
+
+    /// Serializes tests that change the shared robot state.
+    static STATE: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    #[test]
+    fn imu_commands_work_without_the_motor_driver() {
+        let _state = STATE.lock().unwrap();
+        state::update(|s| {
+            s.imu_ready = true;
+            s.pwm_ready = false;
+        });
+        let i2c = |cmd| unavailable(&SystemCommand::I(cmd));
+        assert_eq!(i2c(I2CCommand::ReadIMU), None);
+        assert_eq!(i2c(I2CCommand::CalibrateImu), None);
+        assert_eq!(
+            i2c(I2CCommand::SetThermalLimits(thermal::DEFAULT_THERMAL)),
+            None
+        );
+        assert_eq!(i2c(I2CCommand::ClearSafety), None);
+
+        let refused = Some("Motor driver not initialized");
+        assert_eq!(i2c(I2CCommand::T { d: 0.0, s: 0.5 }), refused);
+        assert_eq!(i2c(I2CCommand::Y { s: 0.5, o: None }), refused);
+        assert_eq!(i2c(I2CCommand::Enable), refused);
+        assert_eq!(i2c(I2CCommand::Disable), refused);
+    }
+
+    #[test]
+    fn invalid_command_rejects_the_whole_batch() {
+        let _state = STATE.lock().unwrap();
+        state::update(|s| {
+            s.pwm_ready = true;
+            s.safety = None;
+        });
+        MOTION_SETPOINT.reset();
+
+        let batch = vec![
+            SystemCommand::I(I2CCommand::T { d: 90.0, s: 0.5 }),
+            SystemCommand::I(I2CCommand::T {
+                d: 0.0,
+                s: f32::NAN,
+            }),
+        ];
+        let reply = embassy_futures::block_on(dispatch_batch(batch));
+        assert!(matches!(reply, Reply::Error { .. }));
+        assert!(!MOTION_SETPOINT.signaled());
+    }
+
+    #[test]
+    fn rejected_commands_are_counted() {
+        let count = || metrics::report().commands["i"]["set_motion_limits"];
+        let before = count();
+        let bad = limits::MotionLimits {
+            max_speed: -1.0,
+            ..limits::DEFAULT_LIMITS
+        };
+        let cmd = SystemCommand::I(I2CCommand::SetMotionLimits(bad));
+        let result = embassy_futures::block_on(try_dispatch(cmd));
+        assert!(matches!(result, Err(Rejection::Invalid(_))));
+        assert_eq!(count(), before + 1);
+    }
+
+    #[test]
+    fn failed_imu_read_reports_the_error_kind() {
+        let reply = embassy_futures::block_on(async {
+            let _replies = REPLY_LOCK.lock().await;
+            IMU_READING.clear();
+            IMU_READING.send(Err("imu")).await;
+            Forwarded::ImuReading.reply().await
+        });
+        assert_eq!(reply, Reply::error("IMU read failed: imu"));
+    }
+
+    #[test]
+    fn thermal_change_reports_level_changes() {
+        let reading = |level| {
+            Event::Thermal(ThermalStatus {
+                temp: 60.0,
+                trend: 0.0,
+                level,
+                duty_scale: 1.0,
+            })
+        };
+        let mut seen = ThermalLevel::Normal;
+        assert!(thermal_change(&mut seen, &reading(ThermalLevel::Normal)).is_none());
+        assert!(thermal_change(&mut seen, &Event::Safety(None)).is_none());
+
+        let report = thermal_change(&mut seen, &reading(ThermalLevel::Warning));
+        assert!(matches!(
+            report,
+            Some(Reply::Thermal(ThermalReport {
+                status: Some(ThermalStatus {
+                    level: ThermalLevel::Warning,
+                    ..
+                }),
+                ..
+            }))
+        ));
+        assert_eq!(seen, ThermalLevel::Warning);
+        assert!(thermal_change(&mut seen, &reading(ThermalLevel::Warning)).is_none());
+        assert!(thermal_change(&mut seen, &reading(ThermalLevel::Normal)).is_some());
+    }
+    #[test]
+    fn safety_change_reports_trips_and_clears() {
+        let tilt = SafetyTrip::Tilt { angle: 60.0 };
+        let report = safety_change(&Event::Safety(Some(tilt)));
+        assert!(matches!(
+            report,
+            Some(Reply::Safety(SafetyReport {
+                trip: Some(SafetyTrip::Tilt { .. }),
+                ..
+            }))
+        ));
+        assert!(matches!(
+            safety_change(&Event::Safety(None)),
+            Some(Reply::Safety(SafetyReport { trip: None, .. }))
+        ));
+        assert!(safety_change(&Event::Fault { error: "bus" }).is_none());
+    }
+}
@@ -0,0 +1,102 @@
+//! Server-Sent Events stream on `/events`.
+//!
+//! Each connected client takes one `events::Listener` slot and receives every
+//! event as `event: <name>` with a JSON `data:` line. A comment line is sent
+//! when the bus is quiet so proxies keep the connection open.
+
+use embassy_time::Duration;
+use picoserve::{
+    io::Write,
+    response::{
+        sse::{EventSource, EventStream, EventWriter},
+        Json, StatusCode,
+    },
+};
+use serde::Serialize;
+
+use super::api::ApiReply;
+use crate::utils::{
+    controllers::{safety::SafetyTrip, traction::TractionFault},
+    events::{Event, Listener},
+};
+
+/// Idle time after which a keep-alive comment is sent.
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+/// Payload of a `connection` event.
+#[derive(Serialize)]
+struct ConnectionData {
+    connected: bool,
+    clients: usize,
+}
+
+/// Payload of a `fault` event.
+#[derive(Serialize)]
+struct FaultData {
+    error: &'static str,
+}
+
+/// Payload of a `safety` event.
+#[derive(Serialize)]
+struct SafetyData {
+    trip: Option<SafetyTrip>,
+}
+
+/// Payload of a `traction` event.
+#[derive(Serialize)]
+struct TractionData {
+    fault: Option<TractionFault>,
+}
+
+/// Streams bus events to one SSE client.
+pub struct Events(Listener);
+
+impl EventSource for Events {
+    async fn write_events<W: Write>(
+        mut self,
+        mut writer: EventWriter<W>,
+    ) -> Result<(), W::Error> {
+        loop {
+            let event = match embassy_time::with_timeout(KEEPALIVE, self.0.next()).await {
+                Ok(event) => event,
+                Err(_) => {
+                    writer.write_keepalive().await?;
+                    continue;
+                }
+            };
+
+            let name = event.name();
+            match event {
+                Event::Imu(imu) => writer.write_event(name, Json(imu)).await?,
+                Event::Motors(motors) => writer.write_event(name, Json(motors)).await?,
+                Event::Motion(cmd) => writer.write_event(name, Json(cmd)).await?,
+                Event::Connection { connected, clients } => {
+                    writer
+                        .write_event(name, Json(ConnectionData { connected, clients }))
+                        .await?
+                }
+                Event::Fault { error } => {
+                    writer.write_event(name, Json(FaultData { error })).await?
+                }
+                Event::Safety(trip) => writer.write_event(name, Json(SafetyData { trip })).await?,
+                Event::Traction(fault) => {
+                    writer
+                        .write_event(name, Json(TractionData { fault }))
+                        .await?
+                }
+                Event::Thermal(status) => writer.write_event(name, Json(status)).await?,
+            }
+        }
+    }
+}
+
+/// `GET /events`: open an event stream, or `503` if every slot is taken.
+pub async fn events() -> Result<EventStream<Events>, ApiReply> {
+    match Listener::new() {
+        Some(listener) => Ok(EventStream(Events(listener))),
+        None => Err(ApiReply::error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Too many event listeners",
+        )),
+    }
+}
@@ -0,0 +1,204 @@
+//! Built-in LED animations.
+//!
+//! An `Animation` is started with `LEDCommand::Anim` and rendered by
+//! `LedModule::run` at its frame rate until another LED command replaces it.
+//! `Animation::render` is a pure function of elapsed time, so effects never
+//! drift when frames are dropped.
+
+use core::f32::consts::PI;
+
+use serde::{Deserialize, Serialize};
+use smart_leds_trait::RGB8;
+
+use super::color;
+
+/// Frame rate used until `LedModule::set_fps` is called.
+pub const DEFAULT_FPS: u32 = 30;
+/// Highest frame rate `LedModule::set_fps` accepts.
+pub const MAX_FPS: u32 = 120;
+
+/// Animation effects.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Effect {
+    /// Whole strip in `color`.
+    Solid,
+    /// Whole strip toggles between `color` and off.
+    Blink,
+    /// Whole strip fades `color` in and out.
+    Breathe,
+    /// Hue cycle spread along the strip.
+    Rainbow,
+    /// Every third LED lit in `color`, stepping along the strip.
+    Chase,
+    /// A `color` head with a fading tail running along the strip.
+    Comet,
+    /// Even and odd LEDs swap between `color` and `color2`.
+    Alternate,
+}
+
+impl Effect {
+    /// Wire names of all effects.
+    pub const NAMES: &'static [&'static str] = &[
+        "solid",
+        "blink",
+        "breathe",
+        "rainbow",
+        "chase",
+        "comet",
+        "alternate",
+    ];
+}
+
+/// An effect and its parameters.
+///
+/// `speed` is in cycles per second: one blink, breath or swap, one trip of the
+/// chase or comet along the strip, or one full turn of the rainbow.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Animation {
+    pub fx: Effect,
+    #[serde(default = "default_speed")]
+    pub speed: f32,
+    /// Main color (`[r, g, b]`).
+    #[serde(default = "default_color")]
+    pub color: [u8; 3],
+    /// Second color for `alternate` (`[r, g, b]`).
+    #[serde(default)]
+    pub color2: [u8; 3],
+}
+
+fn default_speed() -> f32 {
+    1.0
+}
+
+fn default_color() -> [u8; 3] {
+    [255, 255, 255]
+}
+
+fn rgb([r, g, b]: [u8; 3]) -> RGB8 {
+    RGB8 { r, g, b }
+}
+
+/// Scale a color by `level` in `0.0..=1.0`.
+fn dim(
+    color: RGB8,
+    level: f32,
+) -> RGB8 {
+    let scale = |c: u8| (c as f32 * level.clamp(0.0, 1.0)) as u8;
+    RGB8 {
+        r: scale(color.r),
+        g: scale(color.g),
+        b: scale(color.b),
+    }
+}
+
+/// Fully saturated color for `hue` in turns (`0.0..1.0`).
+fn wheel(hue: f32) -> RGB8 {
+    color::hsv(hue * 360.0, 1.0, 1.0)
+}
+
+impl Animation {
+    /// Fill `frame` with the animation as it looks `t` seconds after it started.
+    pub fn render(
+        &self,
+        t: f32,
+        frame: &mut [RGB8],
+    ) {
+        let color = rgb(self.color);
+        let n = frame.len();
+        let cycles = t * self.speed;
+        let phase = cycles - libm::floorf(cycles);
+        // Position of a runner that crosses the strip once per cycle.
+        let step = (phase * n as f32) as usize;
+
+        for (i, pixel) in frame.iter_mut().enumerate() {
+            *pixel = match self.fx {
+                Effect::Solid => color,
+                Effect::Blink => dim(color, if phase < 0.5 { 1.0 } else { 0.0 }),
+                Effect::Breathe => dim(color, (1.0 - libm::cosf(2.0 * PI * phase)) / 2.0),
+                Effect::Rainbow => wheel(phase + i as f32 / n as f32),
+                Effect::Chase => dim(
+                    color,
+                    if (i + 3 - step % 3) % 3 == 0 {
+                        1.0
+                    } else {
+                        0.0
+                    },
+                ),
+                Effect::Comet => {
+                    let tail = (n / 3).max(1);
+                    let behind = (step + n - i) % n;
+                    dim(color, 1.0 - behind as f32 / tail as f32)
+                }
+                Effect::Alternate => {
+                    if (i % 2 == 0) == (phase < 0.5) {
+                        color
+                    } else {
+                        rgb(self.color2)
+                    }
+                }
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: RGB8 = RGB8 { r: 255, g: 0, b: 0 };
+    const BLACK: RGB8 = RGB8 { r: 0, g: 0, b: 0 };
+
+    fn anim(fx: Effect) -> Animation {
+        Animation {
+            fx,
+            speed: 1.0,
+            color: [255, 0, 0],
+            color2: [0, 0, 0],
+        }
+    }
+
+    #[test]
+    fn blink_and_breathe_follow_the_cycle() {
+        let mut frame = [BLACK; 2];
+        anim(Effect::Blink).render(0.25, &mut frame);
+        assert_eq!(frame, [RED; 2]);
+        anim(Effect::Blink).render(1.75, &mut frame);
+        assert_eq!(frame, [BLACK; 2]);
+
+        anim(Effect::Breathe).render(0.0, &mut frame);
+        assert_eq!(frame, [BLACK; 2]);
+        anim(Effect::Breathe).render(0.5, &mut frame);
+        assert_eq!(frame, [RED; 2]);
+    }
+
+    #[test]
+    fn runners_move_along_the_strip() {
+        let mut frame = [BLACK; 6];
+        anim(Effect::Chase).render(0.0, &mut frame);
+        assert_eq!(frame, [RED, BLACK, BLACK, RED, BLACK, BLACK]);
+        anim(Effect::Chase).render(0.2, &mut frame);
+        assert_eq!(frame, [BLACK, RED, BLACK, BLACK, RED, BLACK]);
+
+        anim(Effect::Comet).render(0.5, &mut frame);
+        assert_eq!(frame[3], RED);
+        assert!(frame[2].r > 0 && frame[2].r < 255);
+        assert_eq!(frame[4], BLACK);
+    }
+
+    #[test]
+    fn rainbow_spreads_hues() {
+        let mut frame = [BLACK; 3];
+        anim(Effect::Rainbow).render(0.0, &mut frame);
+        assert_eq!(frame[0], RED);
+        assert_eq!(frame[1], RGB8 { r: 0, g: 255, b: 0 });
+        assert_eq!(frame[2], RGB8 { r: 0, g: 0, b: 255 });
+    }
+
+    #[test]
+    fn parses_with_defaults() {
+        let a: Animation = serde_json::from_str(r#"{"fx":"comet"}"#).unwrap();
+        assert_eq!(a.speed, 1.0);
+        assert_eq!(a.color, [255, 255, 255]);
+    }
+}
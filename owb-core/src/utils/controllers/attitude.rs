@@ -0,0 +1,232 @@
+//! Attitude estimation.
+//!
+//! `spawn` starts the estimator on its own task and asks the I2C task to feed
+//! it calibrated IMU samples at `AttitudeConfig::rate_hz`. Each sample is fused
+//! by the configured filter (`math::orientation`), and the result is published
+//! for other modules (`current`) and clients (`gc: attitude`). Yaw is relative
+//! to the heading at startup and drifts slowly, since there is no magnetometer.
+
+use core::cell::Cell;
+
+use embassy_executor::{SpawnError, Spawner};
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+
+use super::i2c::ImuData;
+use crate::utils::math::orientation::{self, Madgwick, Mahony};
+
+/// Settings used by `spawn` unless overridden.
+pub const DEFAULT_ATTITUDE: AttitudeConfig = AttitudeConfig {
+    filter: FilterKind::Madgwick,
+    rate_hz: 100.0,
+    beta: 0.1,
+    kp: 1.0,
+    ki: 0.05,
+};
+
+/// Samples further apart than this restart integration instead of bridging
+/// the gap.
+const MAX_STEP: Duration = Duration::from_millis(500);
+
+/// Latest IMU sample for the estimator, with the time it was read.
+static SAMPLE: Signal<CriticalSectionRawMutex, (Instant, ImuData)> = Signal::new();
+
+/// Estimator sampling rate in Hz (0 until `spawn` is called).
+static RATE_HZ: Mutex<CriticalSectionRawMutex, Cell<f32>> = Mutex::new(Cell::new(0.0));
+
+/// Most recent estimate, once the first sample was fused.
+static ATTITUDE: Mutex<CriticalSectionRawMutex, Cell<Option<Attitude>>> =
+    Mutex::new(Cell::new(None));
+
+/// Orientation filter run by the estimator.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterKind {
+    Madgwick,
+    Mahony,
+}
+
+/// Estimator settings.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct AttitudeConfig {
+    pub filter: FilterKind,
+    /// IMU samples fused per second.
+    pub rate_hz: f32,
+    /// Madgwick correction gain.
+    pub beta: f32,
+    /// Mahony proportional gain.
+    pub kp: f32,
+    /// Mahony integral gain.
+    pub ki: f32,
+}
+
+/// Estimated orientation.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Attitude {
+    /// Unit quaternion `[w, x, y, z]`.
+    pub quaternion: [f32; 4],
+    /// Rotation about x, in degrees.
+    pub roll: f32,
+    /// Rotation about y, in degrees.
+    pub pitch: f32,
+    /// Rotation about z, in degrees, counter-clockwise from the startup heading.
+    pub yaw: f32,
+    pub filter: FilterKind,
+}
+
+enum Filter {
+    Madgwick(Madgwick),
+    Mahony(Mahony),
+}
+
+/// Fuses IMU samples into an `Attitude`.
+pub struct Estimator {
+    config: AttitudeConfig,
+    filter: Filter,
+    /// Whether the filter was seeded from gravity.
+    seeded: bool,
+}
+
+impl Estimator {
+    pub fn new(config: AttitudeConfig) -> Self {
+        let filter = match config.filter {
+            FilterKind::Madgwick => Filter::Madgwick(Madgwick::new(config.beta)),
+            FilterKind::Mahony => Filter::Mahony(Mahony::new(config.kp, config.ki)),
+        };
+        Estimator {
+            config,
+            filter,
+            seeded: false,
+        }
+    }
+
+    /// Fuse one sample taken `dt` seconds after the previous one, or seed the
+    /// orientation from gravity if `dt` is `None` and no sample was fused yet.
+    pub fn update(
+        &mut self,
+        sample: ImuData,
+        dt: Option<f32>,
+    ) -> Attitude {
+        match dt {
+            Some(dt) => match &mut self.filter {
+                Filter::Madgwick(f) => f.update(sample.gyro, sample.accel, dt),
+                Filter::Mahony(f) => f.update(sample.gyro, sample.accel, dt),
+            },
+            None if !self.seeded => {
+                if let Some(seed) = orientation::from_gravity(sample.accel) {
+                    match &mut self.filter {
+                        Filter::Madgwick(f) => f.q = seed,
+                        Filter::Mahony(f) => f.q = seed,
+                    }
+                    self.seeded = true;
+                }
+            }
+            None => {}
+        }
+        self.attitude()
+    }
+
+    /// Current estimate.
+    pub fn attitude(&self) -> Attitude {
+        let quaternion = match &self.filter {
+            Filter::Madgwick(f) => f.q,
+            Filter::Mahony(f) => f.q,
+        };
+        let (roll, pitch, yaw) = orientation::to_euler(quaternion);
+        Attitude {
+            quaternion,
+            roll,
+            pitch,
+            yaw,
+            filter: self.config.filter,
+        }
+    }
+
+    /// Fuse samples from the I2C task indefinitely. Never returns.
+    pub async fn run(&mut self) -> ! {
+        let mut last: Option<Instant> = None;
+        loop {
+            let (at, sample) = SAMPLE.wait().await;
+            let dt = last
+                .map(|prev| at.saturating_duration_since(prev))
+                .filter(|&step| step <= MAX_STEP)
+                .map(|step| step.as_micros() as f32 / 1_000_000.0);
+            last = Some(at);
+            let attitude = self.update(sample, dt);
+            ATTITUDE.lock(|a| a.set(Some(attitude)));
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn attitude_task(mut estimator: Estimator) -> ! {
+    estimator.run().await
+}
+
+/// Start the estimator on `spawner` and have the I2C task sample the IMU for
+/// it at `config.rate_hz`.
+pub fn spawn(
+    spawner: &Spawner,
+    config: AttitudeConfig,
+) -> Result<(), SpawnError> {
+    spawner.spawn(attitude_task(Estimator::new(config)))?;
+    RATE_HZ.lock(|rate| rate.set(config.rate_hz.max(0.0)));
+    Ok(())
+}
+
+/// Interval between estimator samples, if the estimator is running.
+pub fn period() -> Option<Duration> {
+    let hz = RATE_HZ.lock(|rate| rate.get());
+    (hz > 0.0).then(|| Duration::from_micros((1_000_000.0 / hz) as u64))
+}
+
+/// Hand a calibrated IMU sample to the estimator, replacing one it has not
+/// picked up yet.
+pub fn feed(sample: ImuData) {
+    SAMPLE.signal((Instant::now(), sample));
+}
+
+/// Latest estimate, or `None` if the estimator is not running or has not
+/// fused a sample yet.
+pub fn current() -> Option<Attitude> {
+    ATTITUDE.lock(|a| a.get())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(gyro_z: f32) -> ImuData {
+        ImuData {
+            accel: [0.0, 0.0, 1.0],
+            gyro: [0.0, 0.0, gyro_z],
+            temp: 25.0,
+        }
+    }
+
+    #[test]
+    fn seeds_then_integrates() {
+        let mut estimator = Estimator::new(AttitudeConfig {
+            filter: FilterKind::Mahony,
+            ..DEFAULT_ATTITUDE
+        });
+        let tilted = ImuData {
+            accel: [0.0, 0.5, 0.866],
+            ..level(0.0)
+        };
+        let seeded = estimator.update(tilted, None);
+        assert!((seeded.roll - 30.0).abs() < 0.5);
+        assert_eq!(seeded.filter, FilterKind::Mahony);
+
+        let mut estimator = Estimator::new(DEFAULT_ATTITUDE);
+        estimator.update(level(0.0), None);
+        for _ in 0..50 {
+            estimator.update(level(-90.0), Some(0.01));
+        }
+        assert!((estimator.attitude().yaw + 45.0).abs() < 0.5);
+    }
+}
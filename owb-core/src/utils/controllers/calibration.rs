@@ -0,0 +1,299 @@
+//! IMU calibration.
+//!
+//! `calibrate_imu` samples the IMU while the robot stands still and level, and
+//! derives an `ImuCalibration` from the averages: the gyro bias, and an
+//! accelerometer offset and scale that make gravity read `[0, 0, 1]` g. If the
+//! samples spread more than sensor noise would, the robot was moving and the
+//! calibration is refused rather than averaging motion into the bias.
+//!
+//! `I2CDevices::read_imu` applies the current calibration to every sample. The
+//! coefficients can be read back (`gc: imu_calibration`) and restored later
+//! (`ic: set_imu_calibration`, or `SystemController::set_imu_calibration` at
+//! boot).
+
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::i2c::ImuData;
+
+/// Samples averaged by one calibration run.
+pub const CALIBRATION_SAMPLES: usize = 200;
+/// Time between calibration samples, in milliseconds.
+pub const CALIBRATION_INTERVAL_MS: u64 = 5;
+/// Largest peak-to-peak gyro reading on any axis of a still robot, in °/s.
+pub const MAX_GYRO_SPREAD: f32 = 2.0;
+/// Largest peak-to-peak accelerometer reading on any axis of a still robot, in g.
+pub const MAX_ACCEL_SPREAD: f32 = 0.05;
+/// Largest horizontal acceleration of a level robot, in g.
+pub const MAX_TILT: f32 = 0.2;
+
+/// Corrections applied to raw IMU samples.
+///
+/// Calibrated values are `gyro - gyro_bias` and
+/// `(accel - accel_offset) * accel_scale`, per axis.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct ImuCalibration {
+    /// Gyro reading at rest (°/s).
+    pub gyro_bias: [f32; 3],
+    /// Accelerometer zero offset (g).
+    pub accel_offset: [f32; 3],
+    /// Accelerometer gain correction.
+    pub accel_scale: [f32; 3],
+}
+
+impl ImuCalibration {
+    /// No correction.
+    pub const IDENTITY: ImuCalibration = ImuCalibration {
+        gyro_bias: [0.0; 3],
+        accel_offset: [0.0; 3],
+        accel_scale: [1.0; 3],
+    };
+
+    /// Correct a raw sample.
+    pub fn apply(
+        &self,
+        raw: ImuData,
+    ) -> ImuData {
+        let mut out = raw;
+        for i in 0..3 {
+            out.gyro[i] = raw.gyro[i] - self.gyro_bias[i];
+            out.accel[i] = (raw.accel[i] - self.accel_offset[i]) * self.accel_scale[i];
+        }
+        out
+    }
+
+    /// Check that every coefficient is finite and every scale positive.
+    pub fn check(&self) -> Result<(), CalibrationError> {
+        let finite = self
+            .gyro_bias
+            .iter()
+            .chain(&self.accel_offset)
+            .chain(&self.accel_scale)
+            .all(|c| c.is_finite());
+        if finite && self.accel_scale.iter().all(|&s| s > 0.0) {
+            Ok(())
+        } else {
+            Err(CalibrationError::BadCoefficients)
+        }
+    }
+}
+
+impl Default for ImuCalibration {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+/// Why a calibration was refused.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalibrationError {
+    /// The wheels were turning when calibration was requested.
+    MotorsRunning,
+    /// A sensor's readings spread more than a still robot's would.
+    Moving { sensor: &'static str, spread: f32 },
+    /// Gravity did not point along the z axis or read roughly 1 g.
+    NotLevel { accel: [f32; 3] },
+    /// No samples were taken.
+    NoSamples,
+    /// Restored coefficients were not finite, or a scale was not positive.
+    BadCoefficients,
+    /// Reading the IMU failed with a `DeviceError` of this kind.
+    Device(&'static str),
+}
+
+impl fmt::Display for CalibrationError {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        match self {
+            CalibrationError::MotorsRunning => write!(f, "motors are running"),
+            CalibrationError::Moving { sensor, spread } => {
+                write!(f, "robot moved ({sensor} readings spread by {spread})")
+            }
+            CalibrationError::NotLevel { accel } => {
+                write!(f, "robot is not level (mean acceleration {accel:?} g)")
+            }
+            CalibrationError::NoSamples => write!(f, "no samples taken"),
+            CalibrationError::BadCoefficients => {
+                write!(f, "coefficients must be finite with positive scales")
+            }
+            CalibrationError::Device(kind) => write!(f, "IMU read failed ({kind})"),
+        }
+    }
+}
+
+/// Accumulates raw samples for one calibration run.
+pub struct Calibrator {
+    count: usize,
+    accel: Axes,
+    gyro: Axes,
+}
+
+/// Running sum and range of one sensor's three axes.
+struct Axes {
+    sum: [f32; 3],
+    min: [f32; 3],
+    max: [f32; 3],
+}
+
+impl Axes {
+    const fn new() -> Self {
+        Axes {
+            sum: [0.0; 3],
+            min: [f32::INFINITY; 3],
+            max: [f32::NEG_INFINITY; 3],
+        }
+    }
+
+    fn push(
+        &mut self,
+        v: [f32; 3],
+    ) {
+        for (i, x) in v.into_iter().enumerate() {
+            self.sum[i] += x;
+            self.min[i] = self.min[i].min(x);
+            self.max[i] = self.max[i].max(x);
+        }
+    }
+
+    fn mean(
+        &self,
+        count: usize,
+    ) -> [f32; 3] {
+        self.sum.map(|s| s / count as f32)
+    }
+
+    /// Largest peak-to-peak range over the three axes.
+    fn spread(&self) -> f32 {
+        (0..3)
+            .map(|i| self.max[i] - self.min[i])
+            .fold(0.0, f32::max)
+    }
+}
+
+impl Default for Calibrator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Calibrator {
+    pub const fn new() -> Self {
+        Calibrator {
+            count: 0,
+            accel: Axes::new(),
+            gyro: Axes::new(),
+        }
+    }
+
+    /// Add a raw sample.
+    pub fn push(
+        &mut self,
+        raw: ImuData,
+    ) {
+        self.count += 1;
+        self.accel.push(raw.accel);
+        self.gyro.push(raw.gyro);
+    }
+
+    /// Derive the calibration from the samples, or refuse it if the robot
+    /// moved or was not level.
+    pub fn finish(&self) -> Result<ImuCalibration, CalibrationError> {
+        if self.count == 0 {
+            return Err(CalibrationError::NoSamples);
+        }
+        for (sensor, axes, max) in [
+            ("gyro", &self.gyro, MAX_GYRO_SPREAD),
+            ("accel", &self.accel, MAX_ACCEL_SPREAD),
+        ] {
+            let spread = axes.spread();
+            if spread > max {
+                return Err(CalibrationError::Moving { sensor, spread });
+            }
+        }
+
+        let accel = self.accel.mean(self.count);
+        let [x, y, z] = accel;
+        if libm::hypotf(x, y) > MAX_TILT || !(0.5..=1.5).contains(&z) {
+            return Err(CalibrationError::NotLevel { accel });
+        }
+        Ok(ImuCalibration {
+            gyro_bias: self.gyro.mean(self.count),
+            accel_offset: [x, y, 0.0],
+            accel_scale: [1.0 / z; 3],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(
+        accel: [f32; 3],
+        gyro: [f32; 3],
+    ) -> ImuData {
+        ImuData {
+            accel,
+            gyro,
+            temp: 25.0,
+        }
+    }
+
+    #[test]
+    fn removes_bias_and_offset() {
+        let mut calibrator = Calibrator::new();
+        for i in 0..10 {
+            let noise = if i % 2 == 0 { 0.01 } else { -0.01 };
+            calibrator.push(sample(
+                [0.05, -0.02, 0.98 + noise],
+                [3.0 + noise, -1.5, 0.25],
+            ));
+        }
+        let calibration = calibrator.finish().unwrap();
+        assert!((calibration.gyro_bias[0] - 3.0).abs() < 1e-4);
+
+        let corrected = calibration.apply(sample([0.05, -0.02, 0.98], [3.0, 8.5, 0.25]));
+        assert!(corrected.accel[0].abs() < 1e-6 && corrected.accel[1].abs() < 1e-6);
+        assert!((corrected.accel[2] - 1.0).abs() < 1e-6);
+        assert!((corrected.gyro[1] - 10.0).abs() < 1e-4);
+        assert!(corrected.gyro[2].abs() < 1e-6);
+    }
+
+    #[test]
+    fn refuses_motion_and_tilt() {
+        let mut moving = Calibrator::new();
+        moving.push(sample([0.0, 0.0, 1.0], [0.0; 3]));
+        moving.push(sample([0.0, 0.0, 1.0], [0.0, 0.0, 30.0]));
+        assert_eq!(
+            moving.finish(),
+            Err(CalibrationError::Moving {
+                sensor: "gyro",
+                spread: 30.0
+            })
+        );
+
+        let mut tilted = Calibrator::new();
+        tilted.push(sample([0.5, 0.0, 0.87], [0.0; 3]));
+        assert!(matches!(
+            tilted.finish(),
+            Err(CalibrationError::NotLevel { .. })
+        ));
+        assert_eq!(Calibrator::new().finish(), Err(CalibrationError::NoSamples));
+    }
+
+    #[test]
+    fn checks_restored_coefficients() {
+        assert!(ImuCalibration::IDENTITY.check().is_ok());
+        let bad = ImuCalibration {
+            accel_scale: [1.0, 0.0, 1.0],
+            ..ImuCalibration::IDENTITY
+        };
+        assert_eq!(bad.check(), Err(CalibrationError::BadCoefficients));
+        let json = r#"{"gyro_bias":[1,2,3],"accel_offset":[0,0,0],"accel_scale":[1,1,1]}"#;
+        let parsed: ImuCalibration = serde_json::from_str(json).unwrap();
+        assert_eq!(parsed.gyro_bias, [1.0, 2.0, 3.0]);
+    }
+}
@@ -0,0 +1,181 @@
+//! Color helpers for the LED controller.
+//!
+//! Converts HSV to RGB, looks up named colors, and parses the `ColorSpec`
+//! accepted by color-taking LED commands: an `[r, g, b]` triple, an
+//! `{"h", "s", "v"}` object, or a name from `NAMED_COLORS`.
+
+extern crate alloc;
+
+use alloc::string::String;
+
+use serde::{Deserialize, Serialize};
+use smart_leds_trait::RGB8;
+
+/// Colors that can be selected by name (matched case-insensitively).
+pub const NAMED_COLORS: &[(&str, [u8; 3])] = &[
+    ("black", [0, 0, 0]),
+    ("white", [255, 255, 255]),
+    ("warm_white", [255, 180, 107]),
+    ("red", [255, 0, 0]),
+    ("orange", [255, 96, 0]),
+    ("amber", [255, 191, 0]),
+    ("yellow", [255, 255, 0]),
+    ("green", [0, 255, 0]),
+    ("cyan", [0, 255, 255]),
+    ("blue", [0, 0, 255]),
+    ("purple", [128, 0, 255]),
+    ("magenta", [255, 0, 255]),
+    ("pink", [255, 64, 128]),
+];
+
+/// Look up a color in `NAMED_COLORS`.
+pub fn named(name: &str) -> Option<RGB8> {
+    NAMED_COLORS
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|&(_, [r, g, b])| RGB8 { r, g, b })
+}
+
+/// Convert hue (degrees, wrapped), saturation and value (`0.0..=1.0`,
+/// clamped) to RGB.
+pub fn hsv(
+    h: f32,
+    s: f32,
+    v: f32,
+) -> RGB8 {
+    let h = (h - 360.0 * libm::floorf(h / 360.0)) / 60.0;
+    let s = s.clamp(0.0, 1.0);
+    let v = v.clamp(0.0, 1.0);
+
+    let sector = libm::floorf(h);
+    let f = h - sector;
+    let p = v * (1.0 - s);
+    let q = v * (1.0 - s * f);
+    let t = v * (1.0 - s * (1.0 - f));
+    let (r, g, b) = match sector as u8 {
+        0 => (v, t, p),
+        1 => (q, v, p),
+        2 => (p, v, t),
+        3 => (p, q, v),
+        4 => (t, p, v),
+        _ => (v, p, q),
+    };
+    let byte = |x: f32| libm::roundf(x * 255.0) as u8;
+    RGB8 {
+        r: byte(r),
+        g: byte(g),
+        b: byte(b),
+    }
+}
+
+/// A color given as RGB, HSV or a name.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum ColorSpec {
+    /// `[r, g, b]`
+    Rgb([u8; 3]),
+    /// `{"h": degrees, "s": 0..1, "v": 0..1}`
+    Hsv { h: f32, s: f32, v: f32 },
+    /// An entry of `NAMED_COLORS`, e.g. `"amber"`.
+    Named(String),
+}
+
+impl ColorSpec {
+    /// The RGB color this spec describes, or `None` for an unknown name or a
+    /// non-finite HSV component.
+    pub fn to_rgb(&self) -> Option<RGB8> {
+        match self {
+            ColorSpec::Rgb([r, g, b]) => Some(RGB8 {
+                r: *r,
+                g: *g,
+                b: *b,
+            }),
+            ColorSpec::Hsv { h, s, v } => {
+                (h.is_finite() && s.is_finite() && v.is_finite()).then(|| hsv(*h, *s, *v))
+            }
+            ColorSpec::Named(name) => named(name),
+        }
+    }
+}
+
+/// Linear blend from `from` (`k = 0`) to `to` (`k = 1`).
+pub fn lerp(
+    from: RGB8,
+    to: RGB8,
+    k: f32,
+) -> RGB8 {
+    let k = k.clamp(0.0, 1.0);
+    let mix = |a: u8, b: u8| libm::roundf(a as f32 + (b as f32 - a as f32) * k) as u8;
+    RGB8 {
+        r: mix(from.r, to.r),
+        g: mix(from.g, to.g),
+        b: mix(from.b, to.b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_hsv() {
+        assert_eq!(hsv(0.0, 1.0, 1.0), RGB8 { r: 255, g: 0, b: 0 });
+        assert_eq!(hsv(120.0, 1.0, 1.0), RGB8 { r: 0, g: 255, b: 0 });
+        assert_eq!(hsv(-120.0, 1.0, 1.0), RGB8 { r: 0, g: 0, b: 255 });
+        assert_eq!(
+            hsv(45.0, 1.0, 1.0),
+            RGB8 {
+                r: 255,
+                g: 191,
+                b: 0
+            }
+        );
+        assert_eq!(
+            hsv(200.0, 0.0, 0.5),
+            RGB8 {
+                r: 128,
+                g: 128,
+                b: 128
+            }
+        );
+    }
+
+    #[test]
+    fn parses_color_specs() {
+        let specs: [ColorSpec; 3] = [
+            serde_json::from_str("[1,2,3]").unwrap(),
+            serde_json::from_str(r#"{"h":0,"s":1,"v":1}"#).unwrap(),
+            serde_json::from_str(r#""Amber""#).unwrap(),
+        ];
+        assert_eq!(specs[0].to_rgb(), Some(RGB8 { r: 1, g: 2, b: 3 }));
+        assert_eq!(specs[1].to_rgb(), Some(RGB8 { r: 255, g: 0, b: 0 }));
+        assert_eq!(
+            specs[2].to_rgb(),
+            Some(RGB8 {
+                r: 255,
+                g: 191,
+                b: 0
+            })
+        );
+        assert_eq!(ColorSpec::Named("mauve".into()).to_rgb(), None);
+    }
+
+    #[test]
+    fn blends() {
+        let black = RGB8::default();
+        let white = RGB8 {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        assert_eq!(
+            lerp(black, white, 0.5),
+            RGB8 {
+                r: 128,
+                g: 128,
+                b: 128
+            }
+        );
+        assert_eq!(lerp(black, white, 2.0), white);
+    }
+}
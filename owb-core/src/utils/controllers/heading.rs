@@ -0,0 +1,261 @@
+//! Heading indicator for ring-mounted LED strips.
+//!
+//! While enabled (`LEDCommand::Heading`), `LedModule` follows the motion
+//! setpoints applied by the I2C task (`Event::Motion`): a lit segment points in
+//! the direction of travel of `T` and `O` commands, brighter the faster the
+//! robot goes, and sweeps around the ring while `Y` rotates it in place.
+//!
+//! Angles follow the `kinematics` convention: 0° is forward and angles
+//! increase clockwise seen from above, so `d = 90` points to the robot's right
+//! and a positive `Y` speed turns it clockwise. The LEDs are taken to be evenly
+//! spaced around the ring with indices increasing clockwise too, and LED 0
+//! sitting `HeadingRing::offset` degrees from the robot's forward axis.
+
+use serde::{Deserialize, Serialize};
+use smart_leds_trait::RGB8;
+
+use super::{
+    color,
+    i2c::I2CCommand,
+    limits::{wrap_degrees, MotionLimits},
+};
+
+/// Ring layout and look used until `LedModule::set_heading_ring` is called.
+pub const DEFAULT_HEADING_RING: HeadingRing = HeadingRing {
+    offset: 0.0,
+    width: 90.0,
+    color: [255, 255, 255],
+    sweep_speed: 1.0,
+};
+
+/// How the heading indicator maps onto the ring.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct HeadingRing {
+    /// Angle of LED 0 from the forward axis, in degrees clockwise.
+    pub offset: f32,
+    /// Width of the lit segment in degrees; at least the nearest LED is lit.
+    pub width: f32,
+    /// Segment color (`[r, g, b]`) at full speed.
+    pub color: [u8; 3],
+    /// Sweep rate in turns per second at full rotation speed.
+    pub sweep_speed: f32,
+}
+
+/// What the ring shows for the current setpoint.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Motion {
+    /// Not moving: the ring is dark.
+    Stopped,
+    /// Translating towards `angle` (degrees) at `level` (`0.0..=1.0`) of the
+    /// speed limit.
+    Travel { angle: f32, level: f32 },
+    /// Rotating at `rate` (`-1.0..=1.0`) of the rotation speed limit,
+    /// positive clockwise.
+    Sweep { rate: f32 },
+}
+
+/// `value` as a fraction of `max`, clamped to `-1.0..=1.0`.
+fn ratio(
+    value: f32,
+    max: f32,
+) -> f32 {
+    if max > 0.0 {
+        (value / max).clamp(-1.0, 1.0)
+    } else {
+        0.0
+    }
+}
+
+impl Motion {
+    /// The motion described by a `T`, `Y` or `O` setpoint, with speeds scaled
+    /// by `limits`; `None` for other commands.
+    ///
+    /// An `O` command shows its translation, or a sweep if it only rotates.
+    pub fn from_setpoint(
+        cmd: I2CCommand,
+        limits: &MotionLimits,
+    ) -> Option<Self> {
+        let travel = |d: f32, s: f32| {
+            let level = ratio(s, limits.max_speed);
+            if level == 0.0 {
+                Motion::Stopped
+            } else if level < 0.0 {
+                Motion::Travel {
+                    angle: wrap_degrees(d + 180.0),
+                    level: -level,
+                }
+            } else {
+                Motion::Travel { angle: d, level }
+            }
+        };
+        let sweep = |s: f32| {
+            let rate = ratio(s, limits.max_rotation_speed);
+            if rate == 0.0 {
+                Motion::Stopped
+            } else {
+                Motion::Sweep { rate }
+            }
+        };
+        match cmd {
+            I2CCommand::T { d, s } => Some(travel(d, s)),
+            I2CCommand::Y { s, .. } => Some(sweep(s)),
+            I2CCommand::O { d, s, rs, .. } => match travel(d, s) {
+                Motion::Stopped => Some(sweep(rs)),
+                motion => Some(motion),
+            },
+            _ => None,
+        }
+    }
+}
+
+impl HeadingRing {
+    /// Fill `frame` with `motion` as it looks `t` seconds after it started,
+    /// with a sweep starting from the `from` angle. Returns the angle the
+    /// segment points at, from which the next motion continues.
+    pub fn render(
+        &self,
+        motion: Motion,
+        from: f32,
+        t: f32,
+        frame: &mut [RGB8],
+    ) -> f32 {
+        let (head, level) = match motion {
+            Motion::Stopped => (from, 0.0),
+            Motion::Travel { angle, level } => (angle, level),
+            Motion::Sweep { rate } => {
+                let turned = rate * self.sweep_speed * 360.0 * t;
+                (wrap_degrees(from + turned), rate.abs())
+            }
+        };
+
+        let n = frame.len();
+        let spacing = 360.0 / n.max(1) as f32;
+        let half_width = (self.width / 2.0).max(spacing / 2.0);
+        let [r, g, b] = self.color;
+        for (i, pixel) in frame.iter_mut().enumerate() {
+            let led = wrap_degrees(self.offset + i as f32 * spacing - head);
+            let distance = led.min(360.0 - led);
+            let k = (1.0 - distance / half_width).max(0.0) * level;
+            *pixel = color::lerp(RGB8::default(), RGB8 { r, g, b }, k);
+        }
+        head
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::controllers::limits::DEFAULT_LIMITS;
+
+    const OFF: RGB8 = RGB8 { r: 0, g: 0, b: 0 };
+    const WHITE: RGB8 = RGB8 {
+        r: 255,
+        g: 255,
+        b: 255,
+    };
+
+    fn ring(offset: f32) -> HeadingRing {
+        HeadingRing {
+            offset,
+            width: 0.0,
+            ..DEFAULT_HEADING_RING
+        }
+    }
+
+    #[test]
+    fn maps_setpoints() {
+        let motion = |cmd| Motion::from_setpoint(cmd, &DEFAULT_LIMITS);
+        assert_eq!(
+            motion(I2CCommand::T { d: 90.0, s: -0.5 }),
+            Some(Motion::Travel {
+                angle: 270.0,
+                level: 0.5
+            })
+        );
+        assert_eq!(
+            motion(I2CCommand::O {
+                d: 0.0,
+                s: 0.0,
+                rs: -0.25,
+                o: None
+            }),
+            Some(Motion::Sweep { rate: -0.25 })
+        );
+        assert_eq!(
+            motion(I2CCommand::Y { s: 0.0, o: None }),
+            Some(Motion::Stopped)
+        );
+        assert_eq!(motion(I2CCommand::Enable), None);
+    }
+
+    #[test]
+    fn lights_the_direction_of_travel() {
+        let mut frame = [OFF; 4];
+        let travel = Motion::Travel {
+            angle: 90.0,
+            level: 1.0,
+        };
+        ring(0.0).render(travel, 0.0, 0.0, &mut frame);
+        assert_eq!(frame, [OFF, WHITE, OFF, OFF]);
+
+        // LED 0 mounted at 90°: LED 0 now faces the direction of travel.
+        ring(90.0).render(travel, 0.0, 0.0, &mut frame);
+        assert_eq!(frame, [WHITE, OFF, OFF, OFF]);
+
+        let slow = Motion::Travel {
+            angle: 90.0,
+            level: 0.5,
+        };
+        ring(0.0).render(slow, 0.0, 0.0, &mut frame);
+        assert_eq!(frame[1].r, 128);
+    }
+
+    #[test]
+    fn clockwise_convention() {
+        // Eight LEDs clockwise from the front: LED 2 faces right, LED 6 left.
+        let mut frame = [OFF; 8];
+        let right = Motion::from_setpoint(
+            I2CCommand::T {
+                d: 90.0,
+                s: DEFAULT_LIMITS.max_speed,
+            },
+            &DEFAULT_LIMITS,
+        )
+        .unwrap();
+        ring(0.0).render(right, 0.0, 0.0, &mut frame);
+        assert_eq!(frame[2], WHITE);
+        assert_eq!(frame.iter().filter(|&&p| p != OFF).count(), 1);
+
+        // A positive `Y` turns clockwise: from the front towards the right.
+        let turn = Motion::from_setpoint(
+            I2CCommand::Y {
+                s: DEFAULT_LIMITS.max_rotation_speed,
+                o: None,
+            },
+            &DEFAULT_LIMITS,
+        )
+        .unwrap();
+        let head = ring(0.0).render(
+            turn,
+            0.0,
+            0.25 / DEFAULT_HEADING_RING.sweep_speed,
+            &mut frame,
+        );
+        assert_eq!(head, 90.0);
+        assert_eq!(frame[2], WHITE);
+    }
+
+    #[test]
+    fn sweeps_from_the_last_heading() {
+        let mut frame = [OFF; 4];
+        let sweep = Motion::Sweep { rate: -0.5 };
+        let head = ring(0.0).render(sweep, 90.0, 0.5, &mut frame);
+        assert_eq!(head, 0.0);
+        assert_eq!(frame[0].r, 128);
+        assert_eq!(
+            ring(0.0).render(Motion::Stopped, head, 1.0, &mut frame),
+            head
+        );
+        assert_eq!(frame, [OFF; 4]);
+    }
+}
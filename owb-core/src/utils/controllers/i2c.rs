@@ -1,16 +1,34 @@
 //! I2C device management for the Omni-Wheel Bot.
 //!
 //! This module provides abstractions for initializing and controlling motor PWM drivers
-//! and the IMU sensor over a shared I2C bus. Commands are received via `I2C_CHANNEL`.
+//! and the IMU sensor over a shared I2C bus. Device management commands are
+//! queued on `I2C_CHANNEL`; motion setpoints go through `MOTION_SETPOINT`, where
+//! each one replaces any that has not been applied yet.
 
 use crate::utils;
 use core::cell::RefCell;
 
+use super::{
+    calibration::{CalibrationError, ImuCalibration},
+    imu_config::{
+        self, ImuConfig, ImuConfigError, ImuConfigUpdate, ImuPowerMode, DEFAULT_IMU_CONFIG,
+    },
+    limits::{self, MotionLimits},
+    recorder,
+    safety::{self, SafetyLimits},
+    state::{self, MotorState, WHEEL_COUNT},
+    thermal::{self, ThermalLimits},
+    traction::{self, TractionConfig},
+};
+use crate::utils::connection::protocol::MAX_BATCH;
+use crate::utils::events::{self, Event};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embedded_hal::i2c::I2c;
 use embedded_hal_bus::i2c::RefCellDevice;
 use icm42670::{
-    accelerometer::{Accelerometer, Error as AccelerometerError},
+    accelerometer::{
+        Accelerometer, Error as AccelerometerError, ErrorKind as AccelerometerErrorKind,
+    },
     Address as ImuAddress, Error as ImuError, Icm42670, PowerMode,
 };
 use pwm_pca9685::{Address as PwmAddress, Channel, Error as PwmError, Pca9685};
@@ -20,6 +38,37 @@ use serde::{Deserialize, Serialize};
 pub static I2C_CHANNEL: embassy_sync::channel::Channel<CriticalSectionRawMutex, I2CCommand, 16> =
     embassy_sync::channel::Channel::new();
 
+/// Latest motion setpoint (`T`, `Y` or `O`) waiting to be applied.
+///
+/// Unlike `I2C_CHANNEL` this holds a single value: a setpoint that arrives
+/// before the previous one was applied replaces it, so the robot follows the
+/// newest input instead of working through a backlog.
+pub static MOTION_SETPOINT: embassy_sync::signal::Signal<CriticalSectionRawMutex, I2CCommand> =
+    embassy_sync::signal::Signal::new();
+
+/// Results of `ReadIMU` commands, in the order the commands were carried out:
+/// a sample, or the `DeviceError::kind` of a failed read.
+pub static IMU_READING: embassy_sync::channel::Channel<
+    CriticalSectionRawMutex,
+    Result<ImuData, &'static str>,
+    MAX_BATCH,
+> = embassy_sync::channel::Channel::new();
+
+/// Outcomes of `CalibrateImu` commands, in order.
+pub static CALIBRATION_RESULT: embassy_sync::channel::Channel<
+    CriticalSectionRawMutex,
+    Result<ImuCalibration, CalibrationError>,
+    MAX_BATCH,
+> = embassy_sync::channel::Channel::new();
+
+/// Outcomes of `ConfigureImu` commands, in order: the settings read back from
+/// the IMU.
+pub static IMU_CONFIG_RESULT: embassy_sync::channel::Channel<
+    CriticalSectionRawMutex,
+    Result<ImuConfig, ImuConfigError>,
+    MAX_BATCH,
+> = embassy_sync::channel::Channel::new();
+
 /// Errors that can occur when interacting with I2C-based devices.
 #[derive(Debug)]
 pub enum DeviceError<E: core::fmt::Debug> {
@@ -30,10 +79,45 @@ pub enum DeviceError<E: core::fmt::Debug> {
     PwmNotInitialized,
 }
 
+/// Names of the `DeviceError` variants, as reported in metrics.
+pub const DEVICE_ERROR_KINDS: &[&str] = &[
+    "pwm",
+    "imu",
+    "accel",
+    "imu_not_initialized",
+    "pwm_not_initialized",
+];
+
+impl<E: core::fmt::Debug> DeviceError<E> {
+    /// Name of this error's variant (an entry of `DEVICE_ERROR_KINDS`).
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DeviceError::PwmError(_) => "pwm",
+            DeviceError::ImuError(_) => "imu",
+            DeviceError::AccelError(_) => "accel",
+            DeviceError::ImuNotInitialized => "imu_not_initialized",
+            DeviceError::PwmNotInitialized => "pwm_not_initialized",
+        }
+    }
+
+    /// Whether the error was caused by a failed I2C transaction.
+    pub fn is_bus_error(&self) -> bool {
+        match self {
+            DeviceError::PwmError(PwmError::I2C(_)) => true,
+            DeviceError::ImuError(ImuError::BusError(_)) => true,
+            DeviceError::AccelError(e) => {
+                e.kind() == AccelerometerErrorKind::Bus
+                    || matches!(e.cause(), Some(ImuError::BusError(_)))
+            }
+            _ => false,
+        }
+    }
+}
+
 /// I2C command variants for motion control and device management.
 ///
 /// Serialized as JSON with tag `"ic"`.
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
 #[serde(tag = "ic", rename_all = "snake_case")]
 pub enum I2CCommand {
     // Motion Control Variants
@@ -51,11 +135,114 @@ pub enum I2CCommand {
 
     // Device Management Variants
     /// Read IMU sensor data (accelerometer, gyro, temperature).
+    #[serde(rename = "read_imu", alias = "read_i_m_u")]
     ReadIMU,
     /// Enable I2C-connected devices.
     Enable,
     /// Disable I2C-connected devices.
     Disable,
+    /// Sample the IMU at rest and replace its calibration (see `calibration`).
+    CalibrateImu,
+    /// Replace the IMU calibration with previously saved coefficients.
+    SetImuCalibration(ImuCalibration),
+    /// Change the IMU range, output data rate or power mode (see `imu_config`).
+    ConfigureImu(ImuConfigUpdate),
+    /// Record IMU samples continuously at `rate` Hz; 0 stops (see `recorder`).
+    Record { rate: f32 },
+    /// Record `n` IMU samples at `rate` Hz, replacing those held.
+    Capture { n: usize, rate: f32 },
+    /// Release a latched safety cutoff (see `safety`).
+    ClearSafety,
+    /// Replace the tip-over, lift and collision thresholds.
+    SetSafetyLimits(SafetyLimits),
+    /// Replace the wheel stall and slip detection settings.
+    SetTractionConfig(TractionConfig),
+    /// Replace the thermal warning and critical thresholds.
+    SetThermalLimits(ThermalLimits),
+    /// Replace the speed limits applied to motion commands (see `limits`).
+    SetMotionLimits(MotionLimits),
+}
+
+impl I2CCommand {
+    /// Wire names of all `I2CCommand` variants, as advertised to clients.
+    pub const NAMES: &'static [&'static str] = &[
+        "t",
+        "y",
+        "o",
+        "read_imu",
+        "enable",
+        "disable",
+        "calibrate_imu",
+        "set_imu_calibration",
+        "configure_imu",
+        "record",
+        "capture",
+        "clear_safety",
+        "set_safety_limits",
+        "set_traction_config",
+        "set_thermal_limits",
+        "set_motion_limits",
+    ];
+
+    /// Wire name of this command.
+    pub fn name(&self) -> &'static str {
+        match self {
+            I2CCommand::T { .. } => "t",
+            I2CCommand::Y { .. } => "y",
+            I2CCommand::O { .. } => "o",
+            I2CCommand::ReadIMU => "read_imu",
+            I2CCommand::Enable => "enable",
+            I2CCommand::Disable => "disable",
+            I2CCommand::CalibrateImu => "calibrate_imu",
+            I2CCommand::SetImuCalibration(_) => "set_imu_calibration",
+            I2CCommand::ConfigureImu(_) => "configure_imu",
+            I2CCommand::Record { .. } => "record",
+            I2CCommand::Capture { .. } => "capture",
+            I2CCommand::ClearSafety => "clear_safety",
+            I2CCommand::SetSafetyLimits(_) => "set_safety_limits",
+            I2CCommand::SetTractionConfig(_) => "set_traction_config",
+            I2CCommand::SetThermalLimits(_) => "set_thermal_limits",
+            I2CCommand::SetMotionLimits(_) => "set_motion_limits",
+        }
+    }
+
+    /// Whether this is a motion setpoint, delivered latest-wins through
+    /// `MOTION_SETPOINT` rather than queued.
+    pub fn is_motion(&self) -> bool {
+        matches!(
+            self,
+            I2CCommand::T { .. } | I2CCommand::Y { .. } | I2CCommand::O { .. }
+        )
+    }
+}
+
+/// A single IMU sample.
+///
+/// `accel` is in g, `gyro` in °/s and `temp` is the die temperature in °C.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct ImuData {
+    pub accel: [f32; 3],
+    pub gyro: [f32; 3],
+    pub temp: f32,
+}
+
+impl From<((f32, f32, f32), (f32, f32, f32), f32)> for ImuData {
+    fn from(((ax, ay, az), (gx, gy, gz), temp): ((f32, f32, f32), (f32, f32, f32), f32)) -> Self {
+        ImuData {
+            accel: [ax, ay, az],
+            gyro: [gx, gy, gz],
+            temp,
+        }
+    }
+}
+
+/// Update the shared motor state and announce the change on the event bus.
+fn set_motor_state(f: impl FnOnce(&mut MotorState)) {
+    let motors = state::update(|s| {
+        f(&mut s.motors);
+        s.motors
+    });
+    events::publish(Event::Motors(motors));
 }
 
 /// High-level driver for PWM motor controller and IMU over a shared I2C bus.
@@ -64,8 +251,14 @@ pub struct I2CDevices<'a, I2C: 'static> {
     i2c: &'a RefCell<I2C>,
     pub pwm: Option<Pca9685<RefCellDevice<'a, I2C>>>,
     imu: Option<Icm42670<RefCellDevice<'a, I2C>>>,
-    motor_channels: [(Channel, Channel); 3],
+    motor_channels: [(Channel, Channel); WHEEL_COUNT],
     embodied: utils::ek,
+    calibration: ImuCalibration,
+    imu_config: ImuConfig,
+    /// Whether `disable` put the IMU to sleep.
+    imu_asleep: bool,
+    /// Wheel speeds last requested, before derating.
+    wheel_speeds: [f32; WHEEL_COUNT],
 }
 
 impl<'a, I2C, E> I2CDevices<'a, I2C>
@@ -89,6 +282,10 @@ where
                 (Channel::C4, Channel::C5),
             ],
             embodied: utils::ek::new(wheel_radius, robot_radius),
+            calibration: ImuCalibration::IDENTITY,
+            imu_config: DEFAULT_IMU_CONFIG,
+            imu_asleep: false,
+            wheel_speeds: [0.0; WHEEL_COUNT],
         }
     }
     /// Initialize the IMU and PWM motor controller on the I2C bus.
@@ -104,6 +301,14 @@ where
         self.pwm = Some(pwm);
         Ok(())
     }
+    /// Whether the IMU driver is initialized.
+    pub fn imu_ready(&self) -> bool {
+        self.imu.is_some()
+    }
+    /// Whether the PWM motor driver is initialized.
+    pub fn pwm_ready(&self) -> bool {
+        self.pwm.is_some()
+    }
     /// Scan the I2C bus for devices and log any found addresses.
     pub fn scan_bus(&self) {
         let mut bus = self.i2c.borrow_mut();
@@ -117,6 +322,7 @@ where
     pub fn configure_pwm(&mut self) -> Result<(), DeviceError<E>> {
         if let Some(pca) = &mut self.pwm {
             pca.enable().map_err(DeviceError::PwmError)?;
+            set_motor_state(|m| m.enabled = true);
             tracing::info!("PWM enabled");
             pca.set_prescale(100).map_err(DeviceError::PwmError)?;
             tracing::info!("PWM prescale set to 60Hz");
@@ -144,11 +350,14 @@ where
     /// Execute a high-level `I2CCommand`, performing motion or sensor operations.
     ///
     /// Returns sensor data for `ReadIMU` or `None` for other commands.
+    /// `CalibrateImu` samples over time and `ClearSafety` resets the safety
+    /// monitor, so both are carried out by `SystemController::i2c_ch` instead;
+    /// here they do nothing.
     pub fn execute_command(
         &mut self,
         command: I2CCommand,
     ) -> Result<Option<((f32, f32, f32), (f32, f32, f32), f32)>, DeviceError<E>> {
-        match command {
+        let result = match command {
             I2CCommand::T { d, s } => {
                 self.set_motor_velocities_strafe(d, s)?;
                 Ok(None)
@@ -159,7 +368,7 @@ where
             }
             I2CCommand::O { d, s, rs, o } => {
                 let orientation = o.unwrap_or(0.0);
-                let new_orientation = (orientation + rs) % 360.0;
+                let new_orientation = limits::wrap_degrees(orientation + rs);
                 let wheel_speeds =
                     self.embodied
                         .compute_wheel_velocities(s, d, new_orientation, rs);
@@ -175,7 +384,49 @@ where
                 self.disable()?;
                 Ok(None)
             }
+            I2CCommand::CalibrateImu => Ok(None),
+            I2CCommand::SetImuCalibration(calibration) => {
+                self.set_calibration(calibration);
+                Ok(None)
+            }
+            I2CCommand::ConfigureImu(update) => {
+                self.configure_imu(self.imu_config.with(update))?;
+                Ok(None)
+            }
+            I2CCommand::Record { rate } => {
+                recorder::record(rate);
+                Ok(None)
+            }
+            I2CCommand::Capture { n, rate } => {
+                recorder::capture(n, rate);
+                Ok(None)
+            }
+            I2CCommand::ClearSafety => Ok(None),
+            I2CCommand::SetSafetyLimits(limits) => {
+                safety::set_limits(limits);
+                Ok(None)
+            }
+            I2CCommand::SetTractionConfig(config) => {
+                traction::set_config(config);
+                Ok(None)
+            }
+            I2CCommand::SetThermalLimits(limits) => {
+                thermal::set_limits(limits);
+                Ok(None)
+            }
+            I2CCommand::SetMotionLimits(motion_limits) => {
+                if let Err(error) = limits::set_limits(motion_limits) {
+                    tracing::warn!(%error, "motion limits rejected");
+                }
+                Ok(None)
+            }
+        };
+
+        // Only reached on success; failed commands returned early above.
+        if command.is_motion() {
+            state::update(|s| s.motion.last = Some(command));
         }
+        result
     }
 
     /// Computes and applies motor speeds for strafing.
@@ -196,22 +447,27 @@ where
         speed: f32,
         orientation: Option<f32>,
     ) -> Result<(), DeviceError<E>> {
-        let new_orientation = (orientation.unwrap_or(0.0) + speed) % 360.0;
+        let new_orientation = limits::wrap_degrees(orientation.unwrap_or(0.0) + speed);
         let wheel_speeds = self
             .embodied
             .compute_wheel_velocities(0.0, 0.0, new_orientation, speed);
         self.apply_wheel_speeds(&wheel_speeds)
     }
 
-    /// Applies calculated motor speeds using the PWM driver.
+    /// Applies calculated motor speeds using the PWM driver, scaled by
+    /// `traction::duty_scale` and `thermal::duty_scale`.
     pub fn apply_wheel_speeds(
         &mut self,
         wheel_speeds: &[f32],
     ) -> Result<(), DeviceError<E>> {
         const MAX_DUTY: u16 = 4095;
 
+        self.wheel_speeds
+            .copy_from_slice(&wheel_speeds[..WHEEL_COUNT]);
+        let scale = traction::duty_scale() * thermal::duty_scale();
+        let mut duties = [0.0; WHEEL_COUNT];
         for (i, &(phase_channel, enable_channel)) in self.motor_channels.iter().enumerate() {
-            let speed = wheel_speeds[i].abs().min(1.0);
+            let speed = wheel_speeds[i].abs().min(1.0) * scale;
             let direction = wheel_speeds[i] >= 0.0;
 
             if let Some(pca) = &mut self.pwm {
@@ -219,12 +475,21 @@ where
                     .map_err(DeviceError::PwmError)?;
                 pca.set_channel_on_off(enable_channel, 0, (speed * MAX_DUTY as f32) as u16)
                     .map_err(DeviceError::PwmError)?;
+                duties[i] = if direction { speed } else { -speed };
             } else {
                 tracing::error!("PWM not initialized");
             }
         }
+        set_motor_state(|m| m.duties = duties);
         Ok(())
     }
+
+    /// Apply the last requested wheel speeds again, e.g. after the duty scale
+    /// changed.
+    pub fn reapply_wheel_speeds(&mut self) -> Result<(), DeviceError<E>> {
+        let wheel_speeds = self.wheel_speeds;
+        self.apply_wheel_speeds(&wheel_speeds)
+    }
     #[allow(dead_code)]
     fn apply_wheels_bulk(
         &mut self,
@@ -233,45 +498,136 @@ where
         todo!("Need to implement function for bulk all on and off for simulations changes")
     }
 
-    /// Read accelerometer, gyroscope, and temperature data from the IMU.
+    /// Read accelerometer, gyroscope, and temperature data from the IMU,
+    /// corrected by the current calibration.
     ///
     /// # Returns
     ///
     /// `Ok(((ax, ay, az), (gx, gy, gz), temp))` on success.
     pub fn read_imu(&mut self) -> Result<((f32, f32, f32), (f32, f32, f32), f32), DeviceError<E>> {
+        let raw = self.read_raw_imu()?;
+        let ImuData { accel, gyro, temp } = self.calibration.apply(raw);
+        Ok((
+            (accel[0], accel[1], accel[2]),
+            (gyro[0], gyro[1], gyro[2]),
+            temp,
+        ))
+    }
+
+    /// Read the IMU without applying the calibration.
+    pub fn read_raw_imu(&mut self) -> Result<ImuData, DeviceError<E>> {
         let imu = self.imu.as_mut().ok_or(DeviceError::ImuNotInitialized)?;
         let accel = imu.accel_norm().map_err(DeviceError::AccelError)?;
         let gyro = imu.gyro_norm().map_err(DeviceError::ImuError)?;
         let temp = imu.temperature().map_err(DeviceError::ImuError)?;
 
-        Ok(((accel.x, accel.y, accel.z), (gyro.x, gyro.y, gyro.z), temp))
+        Ok(ImuData {
+            accel: [accel.x, accel.y, accel.z],
+            gyro: [gyro.x, gyro.y, gyro.z],
+            temp,
+        })
+    }
+
+    /// Calibration applied by `read_imu`.
+    pub fn calibration(&self) -> ImuCalibration {
+        self.calibration
+    }
+
+    /// Replace the calibration applied by `read_imu`.
+    pub fn set_calibration(
+        &mut self,
+        calibration: ImuCalibration,
+    ) {
+        self.calibration = calibration;
+        state::update(|s| s.imu_calibration = calibration);
+    }
+
+    /// Settings the IMU was last configured with.
+    pub fn imu_config(&self) -> ImuConfig {
+        self.imu_config
+    }
+
+    /// Write `config` to the IMU and read it back.
+    ///
+    /// The power mode is only written while the IMU is enabled; after a
+    /// `disable` it takes effect on the next `enable`. `config` must pass
+    /// `ImuConfig::check`; an unsupported ODR falls back to the driver default.
+    pub fn configure_imu(
+        &mut self,
+        config: ImuConfig,
+    ) -> Result<ImuConfig, DeviceError<E>> {
+        let asleep = self.imu_asleep;
+        let imu = self.imu.as_mut().ok_or(DeviceError::ImuNotInitialized)?;
+        let accel_odr = imu_config::accel_odr(config.accel_odr).unwrap_or_default();
+        let gyro_odr = imu_config::gyro_odr(config.gyro_odr).unwrap_or_default();
+
+        imu.set_accel_range(config.accel_range.into())
+            .map_err(DeviceError::ImuError)?;
+        imu.set_gyro_range(config.gyro_range.into())
+            .map_err(DeviceError::ImuError)?;
+        imu.set_accel_odr(accel_odr)
+            .map_err(DeviceError::ImuError)?;
+        imu.set_gyro_odr(gyro_odr).map_err(DeviceError::ImuError)?;
+        if !asleep {
+            imu.set_power_mode(config.power_mode.into())
+                .map_err(DeviceError::ImuError)?;
+        }
+
+        let power_mode = imu.power_mode().map_err(DeviceError::ImuError)?;
+        let applied = ImuConfig {
+            accel_range: imu.accel_range().map_err(DeviceError::ImuError)?.into(),
+            gyro_range: imu.gyro_range().map_err(DeviceError::ImuError)?.into(),
+            accel_odr: imu.accel_odr().map_err(DeviceError::ImuError)?.as_f32(),
+            gyro_odr: imu.gyro_odr().map_err(DeviceError::ImuError)?.as_f32(),
+            power_mode: ImuPowerMode::from_device(power_mode).unwrap_or(config.power_mode),
+        };
+        self.imu_config = applied;
+        state::update(|s| s.imu_config = applied);
+        Ok(applied)
     }
 
     /// Enable the PWM motor controller and power up the IMU sensor.
     pub fn enable(&mut self) -> Result<(), DeviceError<E>> {
         if let Some(pca) = self.pwm.as_mut() {
             pca.enable().map_err(DeviceError::PwmError)?;
+            set_motor_state(|m| m.enabled = true);
         }
 
         if let Some(imu) = self.imu.as_mut() {
-            imu.set_power_mode(PowerMode::SixAxisLowNoise)
+            imu.set_power_mode(self.imu_config.power_mode.into())
                 .map_err(DeviceError::ImuError)?;
+            self.imu_asleep = false;
         }
 
         Ok(())
     }
 
     /// Disable motor PWM and put the IMU into sleep mode.
+    ///
+    /// The IMU stays awake while the thermal monitor is enabled, so a
+    /// critical reading can still clear once the motor drivers cool down.
     pub fn disable(&mut self) -> Result<(), DeviceError<E>> {
-        if let Some(pca) = self.pwm.as_mut() {
-            pca.disable().map_err(DeviceError::PwmError)?;
-        }
+        self.disable_motors()?;
 
+        if thermal::limits().enabled {
+            return Ok(());
+        }
         if let Some(imu) = self.imu.as_mut() {
             imu.set_power_mode(PowerMode::Sleep)
                 .map_err(DeviceError::ImuError)?;
+            self.imu_asleep = true;
         }
 
         Ok(())
     }
+
+    /// Disable motor PWM, leaving the IMU running.
+    pub fn disable_motors(&mut self) -> Result<(), DeviceError<E>> {
+        if let Some(pca) = self.pwm.as_mut() {
+            pca.disable().map_err(DeviceError::PwmError)?;
+            set_motor_state(|m| m.enabled = false);
+            traction::set_duty_scale(1.0);
+        }
+        Ok(())
+    }
 }
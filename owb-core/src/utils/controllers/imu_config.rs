@@ -0,0 +1,340 @@
+//! IMU range, output data rate and power mode.
+//!
+//! `ImuConfig` holds the ICM42670 settings the firmware controls: the full-scale
+//! range and output data rate (ODR) of each sensor, and the power mode used
+//! while the IMU is enabled. `DEFAULT_IMU_CONFIG` is applied at startup; it can
+//! be replaced at boot (`SystemController::set_imu_config`) or changed at
+//! runtime with `ic: configure_imu`, which only sets the fields it names.
+//!
+//! The accelerometer's ODR must suit its mode: 1600 and 800 Hz need low-noise
+//! mode, 6.25 Hz and slower need low-power mode. `check` rejects other
+//! combinations before anything is written. After applying a configuration
+//! the device registers are read back, so the reply shows what the IMU
+//! actually runs with.
+
+use core::fmt;
+
+use icm42670::{AccelOdr, GyroOdr, PowerMode};
+use serde::{Deserialize, Serialize};
+
+/// Settings applied when the I2C devices initialize.
+pub const DEFAULT_IMU_CONFIG: ImuConfig = ImuConfig {
+    accel_range: AccelRange::G16,
+    gyro_range: GyroRange::Dps2000,
+    accel_odr: 800.0,
+    gyro_odr: 800.0,
+    power_mode: ImuPowerMode::SixAxisLowNoise,
+};
+
+/// Accelerometer ODRs in Hz, with the register value for each.
+const ACCEL_ODRS: [(f32, AccelOdr); 11] = [
+    (1600.0, AccelOdr::Hz1600),
+    (800.0, AccelOdr::Hz800),
+    (400.0, AccelOdr::Hz400),
+    (200.0, AccelOdr::Hz200),
+    (100.0, AccelOdr::Hz100),
+    (50.0, AccelOdr::Hz50),
+    (25.0, AccelOdr::Hz25),
+    (12.5, AccelOdr::Hz12_5),
+    (6.25, AccelOdr::Hz6_25),
+    (3.125, AccelOdr::Hz3_125),
+    (1.5625, AccelOdr::Hz1_5625),
+];
+
+/// Gyro ODRs in Hz, with the register value for each.
+const GYRO_ODRS: [(f32, GyroOdr); 8] = [
+    (1600.0, GyroOdr::Hz1600),
+    (800.0, GyroOdr::Hz800),
+    (400.0, GyroOdr::Hz400),
+    (200.0, GyroOdr::Hz200),
+    (100.0, GyroOdr::Hz100),
+    (50.0, GyroOdr::Hz50),
+    (25.0, GyroOdr::Hz25),
+    (12.5, GyroOdr::Hz12_5),
+];
+
+/// Accelerometer ODRs (Hz) available in low-power and low-noise mode.
+const ACCEL_LOW_POWER_ODRS: core::ops::RangeInclusive<f32> = 1.5625..=400.0;
+const ACCEL_LOW_NOISE_ODRS: core::ops::RangeInclusive<f32> = 12.5..=1600.0;
+
+/// Accelerometer full-scale range.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum AccelRange {
+    #[serde(rename = "2g")]
+    G2,
+    #[serde(rename = "4g")]
+    G4,
+    #[serde(rename = "8g")]
+    G8,
+    #[serde(rename = "16g")]
+    G16,
+}
+
+/// Gyro full-scale range.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum GyroRange {
+    #[serde(rename = "250dps")]
+    Dps250,
+    #[serde(rename = "500dps")]
+    Dps500,
+    #[serde(rename = "1000dps")]
+    Dps1000,
+    #[serde(rename = "2000dps")]
+    Dps2000,
+}
+
+/// Power mode of an enabled IMU. `Disable` always puts it to sleep.
+///
+/// In the accelerometer-only modes the gyro is off and reads zero.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImuPowerMode {
+    /// Accelerometer only, duty-cycled for the lowest current.
+    AccelLowPower,
+    /// Accelerometer only.
+    AccelLowNoise,
+    /// Gyro only.
+    GyroLowNoise,
+    /// Accelerometer and gyro.
+    SixAxisLowNoise,
+}
+
+/// IMU sensor settings.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct ImuConfig {
+    pub accel_range: AccelRange,
+    pub gyro_range: GyroRange,
+    /// Accelerometer output data rate in Hz.
+    pub accel_odr: f32,
+    /// Gyro output data rate in Hz.
+    pub gyro_odr: f32,
+    pub power_mode: ImuPowerMode,
+}
+
+/// Changes to an `ImuConfig`; fields left out keep their current value.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(default)]
+pub struct ImuConfigUpdate {
+    pub accel_range: Option<AccelRange>,
+    pub gyro_range: Option<GyroRange>,
+    pub accel_odr: Option<f32>,
+    pub gyro_odr: Option<f32>,
+    pub power_mode: Option<ImuPowerMode>,
+}
+
+/// Why an IMU configuration was rejected or could not be applied.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImuConfigError {
+    /// The sensor has no such output data rate.
+    UnsupportedOdr { sensor: &'static str, odr: f32 },
+    /// The accelerometer cannot run at this rate in this power mode.
+    OdrNotInMode { odr: f32, mode: ImuPowerMode },
+    /// Writing or reading back the settings failed with a `DeviceError` of
+    /// this kind.
+    Device(&'static str),
+}
+
+impl fmt::Display for ImuConfigError {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        match self {
+            ImuConfigError::UnsupportedOdr { sensor, odr } => {
+                write!(f, "{sensor} does not support an ODR of {odr} Hz")
+            }
+            ImuConfigError::OdrNotInMode { odr, mode } => {
+                write!(f, "accel ODR {odr} Hz is not available in {mode:?} mode")
+            }
+            ImuConfigError::Device(kind) => write!(f, "IMU access failed ({kind})"),
+        }
+    }
+}
+
+impl ImuConfig {
+    /// This configuration with `update` applied.
+    pub fn with(
+        self,
+        update: ImuConfigUpdate,
+    ) -> ImuConfig {
+        ImuConfig {
+            accel_range: update.accel_range.unwrap_or(self.accel_range),
+            gyro_range: update.gyro_range.unwrap_or(self.gyro_range),
+            accel_odr: update.accel_odr.unwrap_or(self.accel_odr),
+            gyro_odr: update.gyro_odr.unwrap_or(self.gyro_odr),
+            power_mode: update.power_mode.unwrap_or(self.power_mode),
+        }
+    }
+
+    /// Check that both ODRs exist and the accelerometer's suits the power
+    /// mode.
+    pub fn check(&self) -> Result<(), ImuConfigError> {
+        accel_odr(self.accel_odr)?;
+        gyro_odr(self.gyro_odr)?;
+        let modes = match self.power_mode {
+            ImuPowerMode::AccelLowPower => Some(ACCEL_LOW_POWER_ODRS),
+            ImuPowerMode::AccelLowNoise | ImuPowerMode::SixAxisLowNoise => {
+                Some(ACCEL_LOW_NOISE_ODRS)
+            }
+            ImuPowerMode::GyroLowNoise => None,
+        };
+        match modes {
+            Some(range) if !range.contains(&self.accel_odr) => Err(ImuConfigError::OdrNotInMode {
+                odr: self.accel_odr,
+                mode: self.power_mode,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Default for ImuConfig {
+    fn default() -> Self {
+        DEFAULT_IMU_CONFIG
+    }
+}
+
+/// Register value for an accelerometer ODR in Hz.
+pub(crate) fn accel_odr(hz: f32) -> Result<AccelOdr, ImuConfigError> {
+    ACCEL_ODRS
+        .iter()
+        .find(|(rate, _)| *rate == hz)
+        .map(|&(_, odr)| odr)
+        .ok_or(ImuConfigError::UnsupportedOdr {
+            sensor: "accel",
+            odr: hz,
+        })
+}
+
+/// Register value for a gyro ODR in Hz.
+pub(crate) fn gyro_odr(hz: f32) -> Result<GyroOdr, ImuConfigError> {
+    GYRO_ODRS
+        .iter()
+        .find(|(rate, _)| *rate == hz)
+        .map(|&(_, odr)| odr)
+        .ok_or(ImuConfigError::UnsupportedOdr {
+            sensor: "gyro",
+            odr: hz,
+        })
+}
+
+impl From<AccelRange> for icm42670::AccelRange {
+    fn from(range: AccelRange) -> Self {
+        match range {
+            AccelRange::G2 => icm42670::AccelRange::G2,
+            AccelRange::G4 => icm42670::AccelRange::G4,
+            AccelRange::G8 => icm42670::AccelRange::G8,
+            AccelRange::G16 => icm42670::AccelRange::G16,
+        }
+    }
+}
+
+impl From<icm42670::AccelRange> for AccelRange {
+    fn from(range: icm42670::AccelRange) -> Self {
+        match range {
+            icm42670::AccelRange::G2 => AccelRange::G2,
+            icm42670::AccelRange::G4 => AccelRange::G4,
+            icm42670::AccelRange::G8 => AccelRange::G8,
+            icm42670::AccelRange::G16 => AccelRange::G16,
+        }
+    }
+}
+
+impl From<GyroRange> for icm42670::GyroRange {
+    fn from(range: GyroRange) -> Self {
+        match range {
+            GyroRange::Dps250 => icm42670::GyroRange::Deg250,
+            GyroRange::Dps500 => icm42670::GyroRange::Deg500,
+            GyroRange::Dps1000 => icm42670::GyroRange::Deg1000,
+            GyroRange::Dps2000 => icm42670::GyroRange::Deg2000,
+        }
+    }
+}
+
+impl From<icm42670::GyroRange> for GyroRange {
+    fn from(range: icm42670::GyroRange) -> Self {
+        match range {
+            icm42670::GyroRange::Deg250 => GyroRange::Dps250,
+            icm42670::GyroRange::Deg500 => GyroRange::Dps500,
+            icm42670::GyroRange::Deg1000 => GyroRange::Dps1000,
+            icm42670::GyroRange::Deg2000 => GyroRange::Dps2000,
+        }
+    }
+}
+
+impl From<ImuPowerMode> for PowerMode {
+    fn from(mode: ImuPowerMode) -> Self {
+        match mode {
+            ImuPowerMode::AccelLowPower => PowerMode::AccelLowPower,
+            ImuPowerMode::AccelLowNoise => PowerMode::AccelLowNoise,
+            ImuPowerMode::GyroLowNoise => PowerMode::GyroLowNoise,
+            ImuPowerMode::SixAxisLowNoise => PowerMode::SixAxisLowNoise,
+        }
+    }
+}
+
+impl ImuPowerMode {
+    /// The enabled mode matching a device power mode; `None` for `Sleep` and
+    /// `Standby`.
+    pub fn from_device(mode: PowerMode) -> Option<Self> {
+        match mode {
+            PowerMode::AccelLowPower => Some(ImuPowerMode::AccelLowPower),
+            PowerMode::AccelLowNoise => Some(ImuPowerMode::AccelLowNoise),
+            PowerMode::GyroLowNoise => Some(ImuPowerMode::GyroLowNoise),
+            PowerMode::SixAxisLowNoise => Some(ImuPowerMode::SixAxisLowNoise),
+            PowerMode::Sleep | PowerMode::Standby => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_rates_against_mode() {
+        assert!(DEFAULT_IMU_CONFIG.check().is_ok());
+
+        let low_power = DEFAULT_IMU_CONFIG.with(ImuConfigUpdate {
+            power_mode: Some(ImuPowerMode::AccelLowPower),
+            ..Default::default()
+        });
+        assert_eq!(
+            low_power.check(),
+            Err(ImuConfigError::OdrNotInMode {
+                odr: 800.0,
+                mode: ImuPowerMode::AccelLowPower
+            })
+        );
+        let slow = ImuConfigUpdate {
+            accel_odr: Some(3.125),
+            ..Default::default()
+        };
+        assert!(low_power.with(slow).check().is_ok());
+        assert!(DEFAULT_IMU_CONFIG.with(slow).check().is_err());
+
+        let odd = ImuConfigUpdate {
+            gyro_odr: Some(6.25),
+            ..Default::default()
+        };
+        assert_eq!(
+            DEFAULT_IMU_CONFIG.with(odd).check(),
+            Err(ImuConfigError::UnsupportedOdr {
+                sensor: "gyro",
+                odr: 6.25
+            })
+        );
+    }
+
+    #[test]
+    fn parses_partial_updates() {
+        let json = r#"{"accel_range":"4g","gyro_odr":200,"power_mode":"accel_low_noise"}"#;
+        let update: ImuConfigUpdate = serde_json::from_str(json).unwrap();
+        let config = DEFAULT_IMU_CONFIG.with(update);
+        assert_eq!(config.accel_range, AccelRange::G4);
+        assert_eq!(config.gyro_range, GyroRange::Dps2000);
+        assert_eq!(config.gyro_odr, 200.0);
+        assert_eq!(config.power_mode, ImuPowerMode::AccelLowNoise);
+        assert!(serde_json::from_str::<ImuConfigUpdate>(r#"{"accel_range":"3g"}"#).is_err());
+    }
+}
@@ -1,97 +1,1089 @@
 //! LED control module for the Omni-Wheel Bot.
 //!
 //! Manages an addressable LED strip via `SmartLedsWrite` and dispatches commands
-//! received over `LED_CHANNEL`.
+//! received over `LED_CHANNEL`. The strip length is set when the `LedModule` is
+//! created, and a frame buffer holds the color of every LED so single pixels,
+//! ranges and whole frames can be updated and written in one go.
+//!
+//! Every color passes through a global brightness and a gamma-correction table
+//! on its way to the driver, so the frame buffer always holds the colors as
+//! requested.
+//!
+//! `LedModule::run` also plays `animation` effects, rendering frames between
+//! commands; any other LED command stops the animation and restores the frame
+//! buffer. With the status indicator enabled it listens on the event bus and
+//! plays the `status` pattern for the robot's current state; explicit LED
+//! commands take over the strip until that state changes. With the `heading`
+//! indicator enabled instead, it follows the applied motion setpoints until the
+//! next explicit command.
+
+extern crate alloc;
 
+use alloc::{string::String, vec, vec::Vec};
+use core::fmt;
+
+use embassy_futures::select::{select3, Either3};
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::{Duration, Instant, Timer};
 use serde::{Deserialize, Serialize};
 use smart_leds_trait::{SmartLedsWrite, RGB8};
 
+use super::{
+    animation::{Animation, DEFAULT_FPS, MAX_FPS},
+    color::{self, ColorSpec},
+    heading::{HeadingRing, Motion, DEFAULT_HEADING_RING},
+    limits,
+    state::{self, MotorState},
+    status::{RobotStatus, StatusIndicator},
+};
+use crate::utils::events::{Event, Listener};
+
 /// Channel used to receive LED commands (`LEDCommand` messages).
 pub static LED_CHANNEL: embassy_sync::channel::Channel<CriticalSectionRawMutex, LEDCommand, 16> =
     embassy_sync::channel::Channel::new();
 
-/// Number of LEDs in the attached chain.
-const LED_COUNT: usize = 2;
+/// Number of LEDs driven by `LedModule::new`.
+pub const DEFAULT_LED_COUNT: usize = 2;
 
-/// LED command variants for switching on/off or setting a color.
+/// LED command variants for switching on/off or setting colors.
 ///
 /// Serialized as JSON with tag `"lc"`.
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 #[serde(tag = "lc", rename_all = "snake_case")]
 pub enum LEDCommand {
-    /// Turn the LEDs on (last color or white).
+    /// Turn the LEDs on (last colors, or white if none were set).
     On,
     /// Turn all LEDs off (set to black).
     Off,
     /// Set the LED strip to the given RGB color.
+    #[serde(rename = "sc", alias = "s_c")]
     SC { r: u8, g: u8, b: u8 },
+    /// Set the LED at index `i` to the given RGB color.
+    Px { i: usize, r: u8, g: u8, b: u8 },
+    /// Set LEDs `start..end` to the given RGB color.
+    Range {
+        start: usize,
+        end: usize,
+        r: u8,
+        g: u8,
+        b: u8,
+    },
+    /// Set the whole strip from `[r, g, b]` triples; LEDs past the end of
+    /// `colors` are turned off.
+    Frame { colors: Vec<[u8; 3]> },
+    /// Play an animation until the next LED command.
+    Anim(Animation),
+    /// Scale every LED by `level` (`0` off, `255` full), kept across `off`/`on`.
+    Brightness { level: u8 },
+    /// Show the robot's status automatically (`enabled: true`) or stop.
+    Status { enabled: bool },
+    /// Set the LED strip to the given HSV color (`h` in degrees, `s` and `v`
+    /// in `0.0..=1.0`).
+    Hsv { h: f32, s: f32, v: f32 },
+    /// Set the LED strip to a color from `color::NAMED_COLORS`.
+    Color { name: String },
+    /// Fade the whole strip from its current colors to `color` over `ms`
+    /// milliseconds.
+    Fade { color: ColorSpec, ms: u32 },
+    /// Show the direction of travel on a ring of LEDs (`enabled: true`) or
+    /// stop. Replaces the status indicator.
+    Heading { enabled: bool },
+}
+
+impl LEDCommand {
+    /// Wire names of all `LEDCommand` variants, as advertised to clients.
+    pub const NAMES: &'static [&'static str] = &[
+        "on",
+        "off",
+        "sc",
+        "px",
+        "range",
+        "frame",
+        "anim",
+        "brightness",
+        "status",
+        "hsv",
+        "color",
+        "fade",
+        "heading",
+    ];
+
+    /// Wire name of this command.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LEDCommand::On => "on",
+            LEDCommand::Off => "off",
+            LEDCommand::SC { .. } => "sc",
+            LEDCommand::Px { .. } => "px",
+            LEDCommand::Range { .. } => "range",
+            LEDCommand::Frame { .. } => "frame",
+            LEDCommand::Anim(_) => "anim",
+            LEDCommand::Brightness { .. } => "brightness",
+            LEDCommand::Status { .. } => "status",
+            LEDCommand::Hsv { .. } => "hsv",
+            LEDCommand::Color { .. } => "color",
+            LEDCommand::Fade { .. } => "fade",
+            LEDCommand::Heading { .. } => "heading",
+        }
+    }
+
+    /// Check that every LED this command addresses exists on a strip of
+    /// `led_count` LEDs, and that animation speeds and colors are usable.
+    pub fn check(
+        &self,
+        led_count: usize,
+    ) -> Result<(), LedError> {
+        match *self {
+            LEDCommand::Px { i, .. } if i >= led_count => Err(LedError::IndexOutOfRange {
+                index: i,
+                led_count,
+            }),
+            LEDCommand::Range { start, end, .. } if start > end || end > led_count => {
+                Err(LedError::BadRange {
+                    start,
+                    end,
+                    led_count,
+                })
+            }
+            LEDCommand::Frame { ref colors } if colors.len() > led_count => {
+                Err(LedError::FrameTooLong {
+                    len: colors.len(),
+                    led_count,
+                })
+            }
+            LEDCommand::Anim(Animation { speed, .. }) if !(speed.is_finite() && speed >= 0.0) => {
+                Err(LedError::BadSpeed)
+            }
+            LEDCommand::Hsv { h, s, v } if !(h.is_finite() && s.is_finite() && v.is_finite()) => {
+                Err(LedError::BadColor)
+            }
+            LEDCommand::Color { ref name } if color::named(name).is_none() => {
+                Err(LedError::BadColor)
+            }
+            LEDCommand::Fade { ref color, .. } if color.to_rgb().is_none() => {
+                Err(LedError::BadColor)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Why an `LEDCommand` does not fit the strip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedError {
+    /// `Px` addressed an LED past the end of the strip.
+    IndexOutOfRange { index: usize, led_count: usize },
+    /// `Range` was reversed or ran past the end of the strip.
+    BadRange {
+        start: usize,
+        end: usize,
+        led_count: usize,
+    },
+    /// `Frame` carried more colors than there are LEDs.
+    FrameTooLong { len: usize, led_count: usize },
+    /// `Anim` speed was negative or not a number.
+    BadSpeed,
+    /// A color name was unknown or an HSV component was not a number.
+    BadColor,
 }
 
+impl fmt::Display for LedError {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        match self {
+            LedError::IndexOutOfRange { index, led_count } => {
+                write!(f, "LED {index} does not exist (strip has {led_count})")
+            }
+            LedError::BadRange {
+                start,
+                end,
+                led_count,
+            } => write!(
+                f,
+                "range {start}..{end} does not fit a strip of {led_count}"
+            ),
+            LedError::FrameTooLong { len, led_count } => {
+                write!(
+                    f,
+                    "frame has {len} colors but the strip has {led_count} LEDs"
+                )
+            }
+            LedError::BadSpeed => write!(f, "animation speed must be a non-negative number"),
+            LedError::BadColor => write!(f, "unknown color name or invalid HSV value"),
+        }
+    }
+}
+
+/// Gamma 2.8 correction, mapping requested to emitted intensity so that steps
+/// look even to the eye.
+const GAMMA8: [u8; 256] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 5, 5, 5,
+    5, 6, 6, 6, 6, 7, 7, 7, 7, 8, 8, 8, 9, 9, 9, 10, 10, 10, 11, 11, 11, 12, 12, 13, 13, 13, 14,
+    14, 15, 15, 16, 16, 17, 17, 18, 18, 19, 19, 20, 20, 21, 21, 22, 22, 23, 24, 24, 25, 25, 26, 27,
+    27, 28, 29, 29, 30, 31, 32, 32, 33, 34, 35, 35, 36, 37, 38, 39, 39, 40, 41, 42, 43, 44, 45, 46,
+    47, 48, 49, 50, 50, 51, 52, 54, 55, 56, 57, 58, 59, 60, 61, 62, 63, 64, 66, 67, 68, 69, 70, 72,
+    73, 74, 75, 77, 78, 79, 81, 82, 83, 85, 86, 87, 89, 90, 92, 93, 95, 96, 98, 99, 101, 102, 104,
+    105, 107, 109, 110, 112, 114, 115, 117, 119, 120, 122, 124, 126, 127, 129, 131, 133, 135, 137,
+    138, 140, 142, 144, 146, 148, 150, 152, 154, 156, 158, 160, 162, 164, 167, 169, 171, 173, 175,
+    177, 180, 182, 184, 186, 189, 191, 193, 196, 198, 200, 203, 205, 208, 210, 213, 215, 218, 220,
+    223, 225, 228, 231, 233, 236, 239, 241, 244, 247, 249, 252, 255,
+];
+
+const BLACK: RGB8 = RGB8 { r: 0, g: 0, b: 0 };
+const WHITE: RGB8 = RGB8 {
+    r: 255,
+    g: 255,
+    b: 255,
+};
+
 /// High-level LED controller that drives a strip of addressable LEDs.
 ///
-/// Maintains the on/off state and last selected color.
+/// Maintains the on/off state, the last uniform color and a frame buffer with
+/// the color of each LED, shown whenever the strip is on and no animation is
+/// playing.
 pub struct LedModule<Driver> {
     driver: Driver,
     is_on: bool,
     last_color: Option<RGB8>,
+    frame: Vec<RGB8>,
+    animation: Option<Playing>,
+    /// Animation output, kept to avoid allocating every frame.
+    scratch: Vec<RGB8>,
+    /// Whether the strip last showed `scratch` rather than `frame`.
+    scratch_shown: bool,
+    fps: u32,
+    brightness: u8,
+    gamma: bool,
+    status: StatusIndicator,
+    /// Status whose pattern was last shown, while the indicator is enabled.
+    shown_status: Option<Option<RobotStatus>>,
+    ring: HeadingRing,
+    /// Whether the heading indicator is enabled.
+    heading: bool,
+    /// Angle the heading segment pointed at in the last rendered frame.
+    head: f32,
+}
+
+/// An animation, fade or heading and when it started (set by `run` on its
+/// first frame).
+struct Playing {
+    content: Content,
+    started: Option<Instant>,
+}
+
+enum Content {
+    Animation(Animation),
+    /// Blend from the colors shown when the fade started to `to`, which
+    /// becomes the new frame buffer when the fade completes.
+    Fade {
+        from: Vec<RGB8>,
+        to: RGB8,
+        ms: u32,
+    },
+    /// Heading indicator, sweeping from the `from` angle.
+    Heading {
+        motion: Motion,
+        from: f32,
+    },
+}
+
+impl Playing {
+    fn new(content: Content) -> Self {
+        Playing {
+            content,
+            started: None,
+        }
+    }
+
+    fn animation(&self) -> Option<Animation> {
+        match self.content {
+            Content::Animation(animation) => Some(animation),
+            Content::Fade { .. } | Content::Heading { .. } => None,
+        }
+    }
 }
 
 impl<Driver, E> LedModule<Driver>
 where
     Driver: SmartLedsWrite<Color = RGB8, Error = E>,
 {
-    /// Create a new `LedModule` over the given LED driver.
+    /// Create a new `LedModule` over a strip of `DEFAULT_LED_COUNT` LEDs.
     ///
     /// The strip is initially off with no last color.
     pub fn new(driver: Driver) -> Self {
+        Self::with_count(driver, DEFAULT_LED_COUNT)
+    }
+
+    /// Create a new `LedModule` over a strip of `led_count` LEDs.
+    ///
+    /// The strip is initially off, and every LED lights white on `On` until a
+    /// color is set.
+    pub fn with_count(
+        driver: Driver,
+        led_count: usize,
+    ) -> Self {
+        state::update(|s| s.led_count = led_count);
         Self {
             driver,
             is_on: false,
             last_color: None,
+            frame: vec![WHITE; led_count],
+            animation: None,
+            scratch: vec![BLACK; led_count],
+            scratch_shown: false,
+            fps: DEFAULT_FPS,
+            brightness: u8::MAX,
+            gamma: true,
+            status: StatusIndicator::default(),
+            shown_status: None,
+            ring: DEFAULT_HEADING_RING,
+            heading: false,
+            head: 0.0,
         }
     }
 
+    /// Set the ring layout and look of the heading indicator.
+    pub fn set_heading_ring(
+        &mut self,
+        ring: HeadingRing,
+    ) {
+        self.ring = ring;
+    }
+
+    /// Set the pattern shown for each robot status.
+    pub fn set_status_indicator(
+        &mut self,
+        indicator: StatusIndicator,
+    ) {
+        self.status = indicator;
+        if self.shown_status.is_some() {
+            self.shown_status = Some(None);
+        }
+    }
+
+    /// Set the global brightness (`0` off, `255` full).
+    pub fn set_brightness(
+        &mut self,
+        level: u8,
+    ) {
+        self.brightness = level;
+    }
+
+    /// Enable or disable gamma correction (on by default).
+    pub fn set_gamma_correction(
+        &mut self,
+        enabled: bool,
+    ) {
+        self.gamma = enabled;
+    }
+
+    /// Set the animation frame rate (clamped to `1..=MAX_FPS`).
+    pub fn set_fps(
+        &mut self,
+        fps: u32,
+    ) {
+        self.fps = fps.clamp(1, MAX_FPS);
+    }
+
+    /// Number of LEDs on the strip.
+    pub fn led_count(&self) -> usize {
+        self.frame.len()
+    }
+
     /// Execute an incoming `LEDCommand`, updating internal state and LED strip.
     ///
-    /// - `On`: enable LEDs with the last color or white.
-    /// - `Off`: disable LEDs (all black).
-    /// - `SC {r,g,b}`: set a new color, applied immediately if strip is on.
+    /// - `On`: enable LEDs with the frame buffer (white until a color is set).
+    /// - `Off`: disable LEDs (all black), keeping the frame buffer.
+    /// - `SC {r,g,b}`: fill the frame buffer with a new color.
+    /// - `Px`, `Range`, `Frame`: update part or all of the frame buffer.
+    /// - `Anim`: turn the strip on and play an animation; frames are rendered
+    ///   by `run`.
+    /// - `Brightness`: scale all output, including a running animation.
+    /// - `Status`: start or stop showing the robot's status.
+    /// - `Heading`: start or stop showing the direction of travel.
+    /// - `Hsv`, `Color`: fill the frame buffer like `SC`.
+    /// - `Fade`: blend to a new color over time (rendered by `run`); applied
+    ///   at once if the strip is off.
+    ///
+    /// Every command except `Anim`, `Brightness`, `Status` and `Heading` stops
+    /// a running animation or fade. Changes are written to the strip
+    /// immediately if it is on. Addresses past the end of the strip are
+    /// ignored; use `LEDCommand::check` to reject them beforehand.
     pub fn ex_command(
         &mut self,
         cmd: LEDCommand,
     ) -> Result<(), E> {
+        if let LEDCommand::Status { enabled } = cmd {
+            if enabled {
+                self.heading = false;
+                self.shown_status = Some(None);
+                return self.refresh_status();
+            }
+            if self.shown_status.take().is_some() {
+                self.animation = None;
+                self.show()?;
+                self.publish_state();
+            }
+            return Ok(());
+        }
+        if let LEDCommand::Heading { enabled } = cmd {
+            if enabled {
+                self.shown_status = None;
+                self.heading = true;
+                return self.follow(Motion::Stopped);
+            }
+            self.heading = false;
+            if self.following() {
+                self.animation = None;
+                self.show()?;
+                self.publish_state();
+            }
+            return Ok(());
+        }
+        if let LEDCommand::Anim(animation) = cmd {
+            self.is_on = true;
+            self.animation = Some(Playing::new(Content::Animation(animation)));
+            self.publish_state();
+            return Ok(());
+        }
+        if let LEDCommand::Brightness { level } = cmd {
+            self.brightness = level;
+            if self.animation.is_none() {
+                self.show()?;
+            }
+            self.publish_state();
+            return Ok(());
+        }
+        if let LEDCommand::Fade { color, ms } = &cmd {
+            if let (Some(to), true) = (color.to_rgb(), self.is_on && *ms > 0) {
+                let from = if self.scratch_shown {
+                    self.scratch.clone()
+                } else {
+                    self.frame.clone()
+                };
+                self.animation = Some(Playing::new(Content::Fade { from, to, ms: *ms }));
+                self.publish_state();
+                return Ok(());
+            }
+        }
+
+        self.animation = None;
         match cmd {
-            LEDCommand::On => {
-                self.is_on = true;
-                let color = self.last_color.unwrap_or(RGB8 {
-                    r: 255,
-                    g: 255,
-                    b: 255,
-                });
-                self.set_all(color)?;
-            }
-            LEDCommand::Off => {
-                self.is_on = false;
-                self.set_all(RGB8 { r: 0, g: 0, b: 0 })?;
-            }
-            LEDCommand::SC { r, g, b } => {
-                let new_color = RGB8 { r, g, b };
-                self.last_color = Some(new_color);
-                if self.is_on {
-                    self.set_all(new_color)?;
+            LEDCommand::On => self.is_on = true,
+            LEDCommand::Off => self.is_on = false,
+            LEDCommand::SC { r, g, b } => self.fill(RGB8 { r, g, b }),
+            LEDCommand::Hsv { h, s, v } => self.fill(color::hsv(h, s, v)),
+            LEDCommand::Color { name } => {
+                if let Some(c) = color::named(&name) {
+                    self.fill(c);
+                }
+            }
+            LEDCommand::Fade { color, .. } => {
+                if let Some(c) = color.to_rgb() {
+                    self.fill(c);
+                }
+            }
+            LEDCommand::Px { i, r, g, b } => {
+                if let Some(pixel) = self.frame.get_mut(i) {
+                    *pixel = RGB8 { r, g, b };
+                }
+            }
+            LEDCommand::Range {
+                start,
+                end,
+                r,
+                g,
+                b,
+            } => {
+                let end = end.min(self.frame.len());
+                if let Some(pixels) = self.frame.get_mut(start..end) {
+                    pixels.fill(RGB8 { r, g, b });
+                }
+            }
+            LEDCommand::Frame { colors } => {
+                let mut colors = colors.iter().map(|&[r, g, b]| RGB8 { r, g, b });
+                for pixel in self.frame.iter_mut() {
+                    *pixel = colors.next().unwrap_or(BLACK);
                 }
             }
+            LEDCommand::Anim(_)
+            | LEDCommand::Brightness { .. }
+            | LEDCommand::Status { .. }
+            | LEDCommand::Heading { .. } => unreachable!("handled above"),
         }
+        self.show()?;
+        self.publish_state();
         Ok(())
     }
 
-    /// Set all LEDs in the strip to the specified color.
-    fn set_all(
+    /// Fill the frame buffer with one color, remembering it for `gc: leds`.
+    fn fill(
         &mut self,
         color: RGB8,
+    ) {
+        self.last_color = Some(color);
+        self.frame.fill(color);
+    }
+
+    /// Publish on/off state and color to the shared controller state.
+    fn publish_state(&self) {
+        let leds = state::LedState {
+            on: self.is_on,
+            color: self.last_color.map(|c| [c.r, c.g, c.b]),
+            animation: self
+                .animation
+                .as_ref()
+                .and_then(Playing::animation)
+                .map(|a| a.fx),
+            brightness: self.brightness,
+        };
+        state::update(|s| s.leds = leds);
+    }
+
+    /// Show the pattern for the robot's current status if it changed since the
+    /// last one shown. Does nothing while the indicator is disabled.
+    fn refresh_status(&mut self) -> Result<(), E> {
+        let Some(shown) = self.shown_status.as_mut() else {
+            return Ok(());
+        };
+        let status = RobotStatus::from_state(&state::snapshot());
+        if *shown == Some(status) {
+            return Ok(());
+        }
+        *shown = Some(status);
+        tracing::debug!(?status, "LED status indication");
+        self.ex_command(LEDCommand::Anim(self.status.pattern(status)))
+    }
+
+    /// Update the status or heading indicator for an event-bus message.
+    fn on_event(
+        &mut self,
+        event: Event,
+    ) -> Result<(), E> {
+        if !self.heading {
+            return self.refresh_status();
+        }
+        match event {
+            Event::Motion(cmd) => match Motion::from_setpoint(cmd, &limits::limits()) {
+                Some(motion) => self.follow(motion),
+                None => Ok(()),
+            },
+            Event::Motors(MotorState { enabled: false, .. }) if self.following() => {
+                self.follow(Motion::Stopped)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the heading indicator currently owns the strip.
+    fn following(&self) -> bool {
+        matches!(
+            self.animation,
+            Some(Playing {
+                content: Content::Heading { .. },
+                ..
+            })
+        )
+    }
+
+    /// Show `motion` on the heading indicator, continuing from where the
+    /// segment last pointed.
+    fn follow(
+        &mut self,
+        motion: Motion,
     ) -> Result<(), E> {
-        let data = core::iter::repeat(color).take(LED_COUNT);
-        self.driver.write(data)
+        self.is_on = true;
+        self.animation = Some(Playing::new(Content::Heading {
+            motion,
+            from: self.head,
+        }));
+        self.publish_state();
+        Ok(())
+    }
+
+    /// Process `LED_CHANNEL` commands indefinitely, rendering animation frames
+    /// at the configured frame rate while one is playing and following the
+    /// event bus while the status or heading indicator is enabled. Never
+    /// returns.
+    pub async fn run(&mut self) -> !
+    where
+        E: core::fmt::Debug,
+    {
+        let mut next_frame = Instant::now();
+        let mut listener: Option<Listener> = None;
+        loop {
+            let listening = self.shown_status.is_some() || self.heading;
+            if listening != listener.is_some() {
+                listener = if listening {
+                    Listener::background()
+                } else {
+                    None
+                };
+            }
+
+            let animating = self.animation.is_some();
+            let frame = async {
+                if animating {
+                    Timer::at(next_frame).await
+                } else {
+                    core::future::pending().await
+                }
+            };
+            let event = async {
+                match listener.as_mut() {
+                    Some(listener) => listener.next().await,
+                    None => core::future::pending().await,
+                }
+            };
+
+            let result = match select3(LED_CHANNEL.receive(), frame, event).await {
+                Either3::First(cmd) => {
+                    next_frame = Instant::now();
+                    self.ex_command(cmd)
+                }
+                Either3::Second(()) => {
+                    let period = Duration::from_hz(self.fps as u64);
+                    // Skip frames rather than bursting to catch up.
+                    next_frame = (next_frame + period).max(Instant::now());
+                    self.render_frame()
+                }
+                Either3::Third(event) => {
+                    next_frame = Instant::now();
+                    self.on_event(event)
+                }
+            };
+            if let Err(e) = result {
+                tracing::error!("LED update failed: {:?}", e);
+            }
+        }
+    }
+
+    /// Render and write the current animation, fade or heading frame.
+    fn render_frame(&mut self) -> Result<(), E> {
+        let Some(playing) = self.animation.as_mut() else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let started = *playing.started.get_or_insert(now);
+        self.render_at((now - started).as_micros() as f32 / 1_000_000.0)
+    }
+
+    /// Render and write the frame `t` seconds into the animation, fade or
+    /// heading.
+    fn render_at(
+        &mut self,
+        t: f32,
+    ) -> Result<(), E> {
+        let Some(playing) = self.animation.as_ref() else {
+            return Ok(());
+        };
+        let mut finished = None;
+        match playing.content {
+            Content::Animation(animation) => animation.render(t, &mut self.scratch),
+            Content::Fade { ref from, to, ms } => {
+                let k = t * 1000.0 / ms as f32;
+                for (out, &from) in self.scratch.iter_mut().zip(from) {
+                    *out = color::lerp(from, to, k);
+                }
+                if k >= 1.0 {
+                    finished = Some(to);
+                }
+            }
+            Content::Heading { motion, from } => {
+                self.head = self.ring.render(motion, from, t, &mut self.scratch);
+            }
+        }
+
+        let (brightness, gamma) = (self.brightness, self.gamma);
+        let data = self.scratch.iter().map(|&c| correct(c, brightness, gamma));
+        self.driver.write(data)?;
+        self.scratch_shown = true;
+
+        if let Some(to) = finished {
+            self.animation = None;
+            self.fill(to);
+            self.publish_state();
+        }
+        Ok(())
+    }
+
+    /// Write the frame buffer to the strip if it is on, or black if it is off.
+    fn show(&mut self) -> Result<(), E> {
+        let (on, brightness, gamma) = (self.is_on, self.brightness, self.gamma);
+        let data = self.frame.iter().map(|&c| {
+            if on {
+                correct(c, brightness, gamma)
+            } else {
+                BLACK
+            }
+        });
+        self.driver.write(data)?;
+        self.scratch_shown = false;
+        Ok(())
+    }
+}
+
+/// Scale `color` by `brightness` and optionally gamma-correct it for output.
+fn correct(
+    color: RGB8,
+    brightness: u8,
+    gamma: bool,
+) -> RGB8 {
+    let channel = |c: u8| {
+        let scaled = (c as u16 * brightness as u16 / 255) as u8;
+        if gamma {
+            GAMMA8[scaled as usize]
+        } else {
+            scaled
+        }
+    };
+    RGB8 {
+        r: channel(color.r),
+        g: channel(color.g),
+        b: channel(color.b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::convert::Infallible;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::utils::controllers::{animation::Effect, i2c::I2CCommand};
+
+    /// Records every `write` call as one frame.
+    #[derive(Default)]
+    struct Recorder {
+        writes: Vec<Vec<RGB8>>,
+    }
+
+    impl SmartLedsWrite for Recorder {
+        type Color = RGB8;
+        type Error = Infallible;
+
+        fn write<T, I>(
+            &mut self,
+            iterator: T,
+        ) -> Result<(), Self::Error>
+        where
+            T: IntoIterator<Item = I>,
+            I: Into<Self::Color>,
+        {
+            self.writes
+                .push(iterator.into_iter().map(Into::into).collect());
+            Ok(())
+        }
+    }
+
+    const RED: RGB8 = RGB8 { r: 255, g: 0, b: 0 };
+    const BLUE: RGB8 = RGB8 { r: 0, g: 0, b: 255 };
+
+    #[test]
+    fn addresses_pixels_and_ranges() {
+        let mut leds = LedModule::with_count(Recorder::default(), 4);
+        leds.ex_command(LEDCommand::On).unwrap();
+        leds.ex_command(LEDCommand::SC { r: 255, g: 0, b: 0 })
+            .unwrap();
+        leds.ex_command(LEDCommand::Px {
+            i: 3,
+            r: 0,
+            g: 0,
+            b: 255,
+        })
+        .unwrap();
+        leds.ex_command(LEDCommand::Range {
+            start: 0,
+            end: 2,
+            r: 0,
+            g: 0,
+            b: 0,
+        })
+        .unwrap();
+
+        assert_eq!(leds.driver.writes.len(), 4);
+        assert_eq!(leds.driver.writes[0], [WHITE; 4]);
+        assert_eq!(leds.driver.writes[3], [BLACK, BLACK, RED, BLUE]);
+    }
+
+    #[test]
+    fn frame_is_kept_while_off() {
+        let mut leds = LedModule::with_count(Recorder::default(), 3);
+        leds.ex_command(LEDCommand::Frame {
+            colors: vec![[0, 0, 255]],
+        })
+        .unwrap();
+        assert_eq!(leds.driver.writes[0], [BLACK; 3]);
+
+        leds.ex_command(LEDCommand::On).unwrap();
+        assert_eq!(leds.driver.writes[1], [BLUE, BLACK, BLACK]);
+    }
+
+    #[test]
+    fn static_commands_stop_animations() {
+        let mut leds = LedModule::with_count(Recorder::default(), 2);
+        let anim = Animation {
+            fx: Effect::Rainbow,
+            speed: 1.0,
+            color: [255; 3],
+            color2: [0; 3],
+        };
+        leds.ex_command(LEDCommand::Anim(anim)).unwrap();
+        assert!(leds.is_on);
+        assert!(leds.animation.is_some());
+        assert!(leds.driver.writes.is_empty());
+
+        leds.ex_command(LEDCommand::SC { r: 255, g: 0, b: 0 })
+            .unwrap();
+        assert!(leds.animation.is_none());
+        assert_eq!(leds.driver.writes[0], [RED; 2]);
+    }
+
+    #[test]
+    fn brightness_and_gamma_survive_off_on() {
+        let mut leds = LedModule::with_count(Recorder::default(), 1);
+        leds.ex_command(LEDCommand::SC {
+            r: 255,
+            g: 128,
+            b: 0,
+        })
+        .unwrap();
+        leds.ex_command(LEDCommand::Brightness { level: 128 })
+            .unwrap();
+        leds.ex_command(LEDCommand::Off).unwrap();
+        leds.ex_command(LEDCommand::On).unwrap();
+
+        let dimmed = RGB8 {
+            r: GAMMA8[128],
+            g: GAMMA8[64],
+            b: 0,
+        };
+        assert_eq!(leds.driver.writes.last().unwrap(), &[dimmed]);
+
+        leds.set_gamma_correction(false);
+        leds.ex_command(LEDCommand::Brightness { level: 255 })
+            .unwrap();
+        assert_eq!(
+            leds.driver.writes.last().unwrap(),
+            &[RGB8 {
+                r: 255,
+                g: 128,
+                b: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn status_indicator_yields_to_explicit_commands() {
+        let mut leds = LedModule::with_count(Recorder::default(), 1);
+        leds.ex_command(LEDCommand::Status { enabled: true })
+            .unwrap();
+        let shown = leds.animation.as_ref().and_then(Playing::animation);
+        assert!(shown.is_some());
+        assert!(leds.shown_status.unwrap().is_some());
+
+        // An explicit command takes over; the same status is not re-shown.
+        leds.ex_command(LEDCommand::SC { r: 255, g: 0, b: 0 })
+            .unwrap();
+        leds.refresh_status().unwrap();
+        assert!(leds.animation.is_none());
+
+        // A changed status (here: forced by clearing the shown one) is.
+        leds.shown_status = Some(None);
+        leds.refresh_status().unwrap();
+        assert_eq!(leds.animation.as_ref().and_then(Playing::animation), shown);
+
+        leds.ex_command(LEDCommand::Status { enabled: false })
+            .unwrap();
+        assert!(leds.animation.is_none());
+        assert_eq!(leds.driver.writes.last().unwrap(), &[RED]);
+    }
+
+    #[test]
+    fn fades_from_the_current_colors() {
+        let mut leds = LedModule::with_count(Recorder::default(), 2);
+        leds.set_gamma_correction(false);
+        leds.ex_command(LEDCommand::On).unwrap();
+        leds.ex_command(LEDCommand::Px {
+            i: 1,
+            r: 255,
+            g: 0,
+            b: 0,
+        })
+        .unwrap();
+        leds.ex_command(LEDCommand::Fade {
+            color: ColorSpec::Named("blue".into()),
+            ms: 500,
+        })
+        .unwrap();
+        let writes = leds.driver.writes.len();
+
+        leds.render_at(0.25).unwrap();
+        assert_eq!(
+            leds.driver.writes[writes],
+            [
+                RGB8 {
+                    r: 128,
+                    g: 128,
+                    b: 255
+                },
+                RGB8 {
+                    r: 128,
+                    g: 0,
+                    b: 128
+                }
+            ]
+        );
+        assert!(leds.animation.is_some());
+
+        leds.render_at(0.5).unwrap();
+        assert_eq!(leds.driver.writes[writes + 1], [BLUE; 2]);
+        assert!(leds.animation.is_none());
+        assert_eq!(leds.last_color, Some(BLUE));
+    }
+
+    #[test]
+    fn fades_from_a_running_animation_or_fade() {
+        let mut leds = LedModule::with_count(Recorder::default(), 1);
+        leds.set_gamma_correction(false);
+        leds.ex_command(LEDCommand::SC { r: 0, g: 255, b: 0 })
+            .unwrap();
+        leds.ex_command(LEDCommand::Anim(Animation {
+            fx: Effect::Solid,
+            speed: 1.0,
+            color: [255, 0, 0],
+            color2: [0, 0, 0],
+        }))
+        .unwrap();
+        leds.render_at(0.0).unwrap();
+        assert_eq!(leds.driver.writes.last().unwrap(), &[RED]);
+
+        // Starts from the animation's red, not the green frame buffer.
+        let fade = |ms| LEDCommand::Fade {
+            color: ColorSpec::Named("blue".into()),
+            ms,
+        };
+        leds.ex_command(fade(1000)).unwrap();
+        leds.render_at(0.0).unwrap();
+        assert_eq!(leds.driver.writes.last().unwrap(), &[RED]);
+        leds.render_at(0.5).unwrap();
+        let halfway = RGB8 {
+            r: 128,
+            g: 0,
+            b: 128,
+        };
+        assert_eq!(leds.driver.writes.last().unwrap(), &[halfway]);
+
+        // A new fade picks up where the previous one was.
+        leds.ex_command(fade(500)).unwrap();
+        leds.render_at(0.0).unwrap();
+        assert_eq!(leds.driver.writes.last().unwrap(), &[halfway]);
+    }
+
+    #[test]
+    fn accepts_hsv_and_names() {
+        let mut leds = LedModule::with_count(Recorder::default(), 1);
+        leds.ex_command(LEDCommand::On).unwrap();
+        leds.ex_command(LEDCommand::Hsv {
+            h: 240.0,
+            s: 1.0,
+            v: 1.0,
+        })
+        .unwrap();
+        assert_eq!(leds.driver.writes.last().unwrap(), &[BLUE]);
+        leds.ex_command(LEDCommand::Color { name: "red".into() })
+            .unwrap();
+        assert_eq!(leds.driver.writes.last().unwrap(), &[RED]);
+
+        assert_eq!(
+            LEDCommand::Color {
+                name: "mauve".into()
+            }
+            .check(1),
+            Err(LedError::BadColor)
+        );
+    }
+
+    #[test]
+    fn heading_follows_setpoints() {
+        let mut leds = LedModule::with_count(Recorder::default(), 4);
+        leds.set_gamma_correction(false);
+        leds.ex_command(LEDCommand::Status { enabled: true })
+            .unwrap();
+        leds.ex_command(LEDCommand::Heading { enabled: true })
+            .unwrap();
+        assert!(leds.shown_status.is_none());
+
+        leds.on_event(Event::Motion(I2CCommand::T { d: 180.0, s: 1.0 }))
+            .unwrap();
+        leds.render_at(0.0).unwrap();
+        assert_eq!(
+            leds.driver.writes.last().unwrap(),
+            &[BLACK, BLACK, WHITE, BLACK]
+        );
+
+        // Rotation sweeps on from the last heading, a quarter turn per 0.25 s.
+        leds.on_event(Event::Motion(I2CCommand::Y { s: 1.0, o: None }))
+            .unwrap();
+        leds.render_at(0.25).unwrap();
+        assert_eq!(
+            leds.driver.writes.last().unwrap(),
+            &[BLACK, BLACK, BLACK, WHITE]
+        );
+
+        // An explicit command takes over until the next setpoint.
+        leds.ex_command(LEDCommand::SC { r: 255, g: 0, b: 0 })
+            .unwrap();
+        leds.on_event(Event::Motors(MotorState {
+            enabled: false,
+            duties: [0.0; 3],
+        }))
+        .unwrap();
+        assert!(leds.animation.is_none());
+        leds.on_event(Event::Motion(I2CCommand::T { d: 0.0, s: 0.0 }))
+            .unwrap();
+        assert!(leds.following());
+
+        leds.ex_command(LEDCommand::Heading { enabled: false })
+            .unwrap();
+        assert!(leds.animation.is_none());
+        assert_eq!(leds.driver.writes.last().unwrap(), &[RED; 4]);
+    }
+
+    #[test]
+    fn checks_addresses() {
+        assert!(LEDCommand::Px {
+            i: 4,
+            r: 0,
+            g: 0,
+            b: 0
+        }
+        .check(4)
+        .is_err());
+        let range = LEDCommand::Range {
+            start: 2,
+            end: 5,
+            r: 0,
+            g: 0,
+            b: 0,
+        };
+        assert_eq!(
+            range.check(4),
+            Err(LedError::BadRange {
+                start: 2,
+                end: 5,
+                led_count: 4
+            })
+        );
+        let frame = LEDCommand::Frame {
+            colors: vec![[0; 3]; 4],
+        };
+        assert!(frame.check(4).is_ok());
+        assert!(frame.check(3).is_err());
     }
 }
@@ -0,0 +1,290 @@
+//! Validation of motion command parameters.
+//!
+//! Every `T`, `Y` and `O` command passes through `check` before it reaches the
+//! I2C controller. Non-finite values are rejected, angles are wrapped into
+//! `0.0..360.0`, and speeds above the configured `MotionLimits` are either
+//! clamped or rejected depending on `SpeedPolicy`.
+//!
+//! Clients replace the limits with `ic: set_motion_limits` and read them back
+//! with `gc: motion_limits`; firmware calls `set_limits`.
+
+use core::{cell::Cell, fmt};
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use serde::{Deserialize, Serialize};
+
+use super::i2c::I2CCommand;
+
+/// Limits applied to motion commands until `set_limits` is called.
+pub const DEFAULT_LIMITS: MotionLimits = MotionLimits {
+    max_speed: 1.0,
+    max_rotation_speed: 1.0,
+    policy: SpeedPolicy::Clamp,
+};
+
+static LIMITS: Mutex<CriticalSectionRawMutex, Cell<MotionLimits>> =
+    Mutex::new(Cell::new(DEFAULT_LIMITS));
+
+/// What to do with a speed above its limit.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SpeedPolicy {
+    /// Reduce the speed to the limit, keeping its sign.
+    Clamp,
+    /// Refuse the command.
+    Reject,
+}
+
+/// Bounds on motion command parameters. Fields left out of a command or config
+/// file take their `DEFAULT_LIMITS` values.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct MotionLimits {
+    /// Largest translational speed magnitude (`s` of `t` and `o`).
+    pub max_speed: f32,
+    /// Largest rotational speed magnitude (`s` of `y`, `rs` of `o`).
+    pub max_rotation_speed: f32,
+    pub policy: SpeedPolicy,
+}
+
+impl Default for MotionLimits {
+    fn default() -> Self {
+        DEFAULT_LIMITS
+    }
+}
+
+/// Why motion limits were rejected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BadLimit {
+    pub field: &'static str,
+}
+
+impl fmt::Display for BadLimit {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        write!(f, "`{}` must be a finite, non-negative number", self.field)
+    }
+}
+
+/// Why a motion command was refused.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MotionError {
+    /// A parameter was NaN or infinite.
+    NotFinite { field: &'static str },
+    /// A speed exceeded its limit under `SpeedPolicy::Reject`.
+    TooFast {
+        field: &'static str,
+        value: f32,
+        max: f32,
+    },
+}
+
+impl fmt::Display for MotionError {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        match self {
+            MotionError::NotFinite { field } => write!(f, "`{field}` must be a finite number"),
+            MotionError::TooFast { field, value, max } => {
+                write!(f, "`{field}` = {value} exceeds the limit of {max}")
+            }
+        }
+    }
+}
+
+/// Replace the limits applied to motion commands, keeping the current ones if
+/// `limits` fails `MotionLimits::check_limits`.
+pub fn set_limits(limits: MotionLimits) -> Result<(), BadLimit> {
+    limits.check_limits()?;
+    LIMITS.lock(|l| l.set(limits));
+    Ok(())
+}
+
+/// Limits currently applied to motion commands.
+pub fn limits() -> MotionLimits {
+    LIMITS.lock(|l| l.get())
+}
+
+/// Validate and normalize `cmd` against the current limits.
+///
+/// Commands other than `T`, `Y` and `O` are returned unchanged.
+pub fn check(cmd: I2CCommand) -> Result<I2CCommand, MotionError> {
+    limits().check(cmd)
+}
+
+/// Wrap an angle in degrees into `0.0..360.0`.
+pub fn wrap_degrees(angle: f32) -> f32 {
+    let wrapped = angle % 360.0;
+    if wrapped < 0.0 {
+        // `-1e-6 + 360.0` rounds to 360.0; keep the result in range.
+        (wrapped + 360.0) % 360.0
+    } else {
+        wrapped
+    }
+}
+
+fn finite(
+    field: &'static str,
+    value: f32,
+) -> Result<f32, MotionError> {
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(MotionError::NotFinite { field })
+    }
+}
+
+fn angle(
+    field: &'static str,
+    value: f32,
+) -> Result<f32, MotionError> {
+    finite(field, value).map(wrap_degrees)
+}
+
+impl MotionLimits {
+    /// Check that both speed limits are finite and not negative.
+    pub fn check_limits(&self) -> Result<(), BadLimit> {
+        for (field, value) in [
+            ("max_speed", self.max_speed),
+            ("max_rotation_speed", self.max_rotation_speed),
+        ] {
+            if !(value >= 0.0 && value.is_finite()) {
+                return Err(BadLimit { field });
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate and normalize `cmd` against these limits.
+    pub fn check(
+        &self,
+        cmd: I2CCommand,
+    ) -> Result<I2CCommand, MotionError> {
+        let cmd = match cmd {
+            I2CCommand::T { d, s } => I2CCommand::T {
+                d: angle("d", d)?,
+                s: self.speed("s", s, self.max_speed)?,
+            },
+            I2CCommand::Y { s, o } => I2CCommand::Y {
+                s: self.speed("s", s, self.max_rotation_speed)?,
+                o: o.map(|o| angle("o", o)).transpose()?,
+            },
+            I2CCommand::O { d, s, rs, o } => I2CCommand::O {
+                d: angle("d", d)?,
+                s: self.speed("s", s, self.max_speed)?,
+                rs: self.speed("rs", rs, self.max_rotation_speed)?,
+                o: o.map(|o| angle("o", o)).transpose()?,
+            },
+            other => other,
+        };
+        Ok(cmd)
+    }
+
+    fn speed(
+        &self,
+        field: &'static str,
+        value: f32,
+        max: f32,
+    ) -> Result<f32, MotionError> {
+        let value = finite(field, value)?;
+        if value.abs() <= max {
+            return Ok(value);
+        }
+        match self.policy {
+            SpeedPolicy::Clamp => Ok(value.clamp(-max, max)),
+            SpeedPolicy::Reject => Err(MotionError::TooFast { field, value, max }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_angles() {
+        assert_eq!(wrap_degrees(370.0), 10.0);
+        assert_eq!(wrap_degrees(-90.0), 270.0);
+        assert_eq!(wrap_degrees(10000.0), 280.0);
+        assert!((0.0..360.0).contains(&wrap_degrees(-1e-6)));
+
+        let cmd = DEFAULT_LIMITS.check(I2CCommand::Y {
+            s: 0.5,
+            o: Some(-45.0),
+        });
+        assert_eq!(
+            cmd,
+            Ok(I2CCommand::Y {
+                s: 0.5,
+                o: Some(315.0)
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_non_finite() {
+        let cmd = I2CCommand::O {
+            d: 0.0,
+            s: 0.5,
+            rs: f32::NAN,
+            o: None,
+        };
+        assert_eq!(
+            DEFAULT_LIMITS.check(cmd),
+            Err(MotionError::NotFinite { field: "rs" })
+        );
+        assert!(DEFAULT_LIMITS
+            .check(I2CCommand::T {
+                d: f32::INFINITY,
+                s: 0.5
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn clamps_or_rejects_speed() {
+        let cmd = I2CCommand::T { d: 90.0, s: -1e9 };
+        assert_eq!(
+            DEFAULT_LIMITS.check(cmd),
+            Ok(I2CCommand::T { d: 90.0, s: -1.0 })
+        );
+
+        let strict = MotionLimits {
+            policy: SpeedPolicy::Reject,
+            ..DEFAULT_LIMITS
+        };
+        assert_eq!(
+            strict.check(cmd),
+            Err(MotionError::TooFast {
+                field: "s",
+                value: -1e9,
+                max: 1.0
+            })
+        );
+        assert_eq!(strict.check(I2CCommand::Enable), Ok(I2CCommand::Enable));
+    }
+
+    #[test]
+    fn rejects_bad_limits() {
+        assert_eq!(DEFAULT_LIMITS.check_limits(), Ok(()));
+        let nan = MotionLimits {
+            max_speed: f32::NAN,
+            ..DEFAULT_LIMITS
+        };
+        assert_eq!(nan.check_limits(), Err(BadLimit { field: "max_speed" }));
+        let negative = MotionLimits {
+            max_rotation_speed: -1.0,
+            ..DEFAULT_LIMITS
+        };
+        assert_eq!(
+            set_limits(negative),
+            Err(BadLimit {
+                field: "max_rotation_speed"
+            })
+        );
+        assert_eq!(limits(), DEFAULT_LIMITS);
+    }
+}
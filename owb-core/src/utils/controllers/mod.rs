@@ -4,28 +4,75 @@
 //! and addressable LEDs, and defines the `SystemCommand` enum for incoming commands.
 //!
 //! Submodules:
+//! - `animation`: Built-in LED effects rendered by the LED controller
+//! - `attitude`: Orientation estimator task fed by the I2C controller
+//! - `calibration`: IMU bias, offset and scale correction
+//! - `color`: HSV conversion, named colors and fades for LED commands
+//! - `heading`: Direction-of-travel indicator for ring-mounted LED strips
 //! - `i2c`: Motor PWM and IMU control over I2C bus
+//! - `imu_config`: IMU range, output data rate and power mode
 //! - `leds`: Addressable LED strip control
+//! - `limits`: Validation and normalization of motion commands
+//! - `recorder`: Timestamped IMU sample ring for captures and batch download
+//! - `safety`: Tip-over, lift and collision detection with motor cutoff
+//! - `traction`: Wheel stall, disconnect and slip detection from the gyro
+//! - `thermal`: Motor driver temperature monitoring and derating
+//! - `state`: Shared controller state snapshot and `get` queries
+//! - `status`: Robot status shown on the LED strip
 
+pub mod animation;
+pub mod attitude;
+pub mod calibration;
+pub mod color;
+pub mod heading;
 pub mod i2c;
+pub mod imu_config;
 pub mod leds;
+pub mod limits;
+pub mod recorder;
+pub mod safety;
+pub mod state;
+pub mod status;
+pub mod thermal;
+pub mod traction;
 
 use core::cell::RefCell;
+use embassy_futures::select::{select, Either};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{Instant, Timer};
 use serde::{Deserialize, Serialize};
 
-pub use i2c::I2C_CHANNEL;
+use crate::utils::{
+    connection::protocol::MAX_BATCH,
+    events::{self, Event},
+    metrics,
+};
+use calibration::{
+    CalibrationError, Calibrator, ImuCalibration, CALIBRATION_INTERVAL_MS, CALIBRATION_SAMPLES,
+};
+use imu_config::{ImuConfig, ImuConfigError, DEFAULT_IMU_CONFIG};
+use safety::{SafetyMonitor, SafetyTrip};
+use thermal::{ThermalLevel, ThermalMonitor, ThermalStatus};
+use traction::{TractionAction, TractionChange, TractionMonitor};
+
+pub use i2c::{ImuData, I2C_CHANNEL, IMU_READING, MOTION_SETPOINT};
 pub use leds::LED_CHANNEL;
+pub use state::GetCommand;
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "ct", rename_all = "snake_case")] // ct = command type
 pub enum SystemCommand {
     I(i2c::I2CCommand),
     L(leds::LEDCommand),
+    G(state::GetCommand),
 }
 
 pub struct SystemController<I2C: 'static> {
     pub sensors: Option<i2c::I2CDevices<'static, I2C>>,
     pub robot_dimensions: (f32, f32), // (wheel_radius, robot_radius)
+    safety: SafetyMonitor,
+    traction: TractionMonitor,
+    thermal: ThermalMonitor,
 }
 impl<I2C> SystemController<I2C>
 where
@@ -48,37 +95,156 @@ where
         let sensors = match i2c_dev.init_devices() {
             Ok(()) => {
                 let _ = i2c_dev.configure_pwm();
+                if let Err(e) = i2c_dev.configure_imu(DEFAULT_IMU_CONFIG) {
+                    metrics::device_error(&e);
+                    tracing::warn!("IMU configuration failed: {:?}", e);
+                }
                 i2c_dev.init_imu_data();
                 Some(i2c_dev)
             }
             Err(e) => {
+                metrics::device_error(&e);
                 tracing::warn!("I2C init failed, scanning instead: {:?}", e);
                 i2c_dev.scan_bus();
                 None
             }
         };
 
+        state::update(|s| {
+            s.wheel_radius = wr;
+            s.robot_radius = rr;
+            s.imu_ready = sensors.as_ref().is_some_and(|d| d.imu_ready());
+            s.pwm_ready = sensors.as_ref().is_some_and(|d| d.pwm_ready());
+            s.i2c_failed = sensors.is_none();
+        });
+
         SystemController {
             sensors,
             robot_dimensions: (wr, rr),
+            safety: SafetyMonitor::new(),
+            traction: TractionMonitor::new(wr, rr),
+            thermal: ThermalMonitor::new(),
         }
     }
 
     /// Start processing incoming `SystemCommand` messages indefinitely.
     ///
-    /// This loop receives commands from the global I2C_CHANNEL and dispatches
-    /// motor/IMU operations. Never returns.
+    /// This loop receives commands from the global I2C_CHANNEL and the latest
+    /// setpoint from MOTION_SETPOINT, and dispatches motor/IMU operations. Queued
+    /// commands go first, so an `Enable` sent before a motion command still
+    /// takes effect before it. Between commands it samples the IMU at the rate
+    /// set by `events::set_imu_rate` while anyone listens for events, at the
+    /// `attitude` estimator's rate while it runs, at the `recorder`'s rate
+    /// while it records, and at the `safety`, `traction` and `thermal`
+    /// monitors' rates while they are armed. Motion commands are dropped while
+    /// a safety cutoff is latched, and `Enable` while the motors are too hot.
+    /// Never returns.
     pub async fn i2c_ch(&mut self) -> ! {
+        // Next sample time for events, the attitude estimator, the recorder
+        // and the safety, traction and thermal monitors.
+        let mut next_due = [Instant::now(); 6];
         loop {
-            let i2c_channel = i2c::I2C_CHANNEL.receiver().receive().await;
+            let receive = select(i2c::I2C_CHANNEL.receive(), i2c::MOTION_SETPOINT.wait());
+            let periods = [
+                events::imu_period(),
+                attitude::period(),
+                recorder::period(),
+                safety::period(),
+                traction::period(),
+                thermal::period(),
+            ];
+            let deadline = periods
+                .iter()
+                .zip(next_due)
+                .filter_map(|(period, due)| period.map(|_| due))
+                .min();
+            let next = match deadline {
+                Some(deadline) => match embassy_time::with_deadline(deadline, receive).await {
+                    Ok(next) => next,
+                    Err(_) => {
+                        let now = Instant::now();
+                        let mut sample = [false; 6];
+                        let slots = periods.iter().zip(&mut next_due).zip(&mut sample);
+                        for ((period, due), take) in slots {
+                            if let Some(period) = period.filter(|_| now >= *due) {
+                                *due = now + period;
+                                *take = true;
+                            }
+                        }
+                        self.sample_imu(sample);
+                        continue;
+                    }
+                },
+                None => receive.await,
+            };
+            let i2c_channel = match next {
+                Either::First(cmd) | Either::Second(cmd) => cmd,
+            };
             tracing::info!("Received I2C Command: {:?}", i2c_channel);
+            if i2c_channel == i2c::I2CCommand::CalibrateImu {
+                let result = self.calibrate_imu().await;
+                match result {
+                    Ok(calibration) => tracing::info!(?calibration, "IMU calibrated"),
+                    Err(error) => tracing::warn!(%error, "IMU calibration refused"),
+                }
+                publish_reply(&i2c::CALIBRATION_RESULT, result);
+                continue;
+            }
+            if i2c_channel == i2c::I2CCommand::ClearSafety {
+                self.clear_safety();
+                continue;
+            }
+            if i2c_channel.is_motion() && safety::tripped().is_some() {
+                tracing::warn!("Motion command dropped: safety cutoff latched");
+                continue;
+            }
+            let too_hot = thermal::level() == ThermalLevel::Critical;
+            if i2c_channel == i2c::I2CCommand::Enable && too_hot {
+                tracing::warn!("Enable dropped: motors too hot");
+                continue;
+            }
+            if let i2c::I2CCommand::ConfigureImu(update) = i2c_channel {
+                let current = self
+                    .sensors
+                    .as_ref()
+                    .map_or(DEFAULT_IMU_CONFIG, |d| d.imu_config());
+                let result = self.set_imu_config(current.with(update));
+                match result {
+                    Ok(config) => tracing::info!(?config, "IMU configured"),
+                    Err(error) => tracing::warn!(%error, "IMU configuration failed"),
+                }
+                publish_reply(&i2c::IMU_CONFIG_RESULT, result);
+                continue;
+            }
             if let Some(devs) = self.sensors.as_mut() {
                 match devs.execute_command(i2c_channel) {
                     Ok(Some((accel, gyro, temp))) => {
                         tracing::info!(?accel, ?gyro, ?temp, "IMU Data Read");
+                        let sample = ImuData::from((accel, gyro, temp));
+                        publish_reply(&i2c::IMU_READING, Ok(sample));
                     }
                     Ok(None) => tracing::info!("I2C command executed successfully"),
-                    Err(_) => tracing::error!("I2C command failed"),
+                    Err(e) => {
+                        metrics::device_error(&e);
+                        tracing::error!("I2C command failed: {:?}", e);
+                        state::update(|s| s.fault = Some(e.kind()));
+                        events::publish(Event::Fault { error: e.kind() });
+                        if i2c_channel == i2c::I2CCommand::ReadIMU {
+                            publish_reply(&i2c::IMU_READING, Err(e.kind()));
+                        }
+                        continue;
+                    }
+                }
+                state::update(|s| s.fault = None);
+                if i2c_channel == i2c::I2CCommand::Disable {
+                    // Derating ended with the motors; start traction checks afresh.
+                    self.traction.reset();
+                    if traction::report().fault.is_some() {
+                        self.traction_change(TractionChange::Cleared);
+                    }
+                }
+                if i2c_channel.is_motion() {
+                    events::publish(Event::Motion(i2c_channel));
                 }
             } else {
                 tracing::warn!(
@@ -88,4 +254,240 @@ where
             }
         }
     }
+
+    /// Replace the IMU calibration, e.g. with coefficients saved from an
+    /// earlier `calibrate_imu` run. Does nothing if the I2C devices failed to
+    /// initialize.
+    pub fn set_imu_calibration(
+        &mut self,
+        calibration: ImuCalibration,
+    ) {
+        if let Some(devs) = self.sensors.as_mut() {
+            devs.set_calibration(calibration);
+        }
+    }
+
+    /// Apply IMU range, output data rate and power mode settings, e.g. at boot
+    /// in place of `DEFAULT_IMU_CONFIG`. Returns the settings read back from
+    /// the IMU.
+    pub fn set_imu_config(
+        &mut self,
+        config: ImuConfig,
+    ) -> Result<ImuConfig, ImuConfigError> {
+        config.check()?;
+        let Some(devs) = self.sensors.as_mut().filter(|d| d.imu_ready()) else {
+            return Err(ImuConfigError::Device("imu_not_initialized"));
+        };
+        devs.configure_imu(config).map_err(|e| {
+            metrics::device_error(&e);
+            ImuConfigError::Device(e.kind())
+        })
+    }
+
+    /// Sample the IMU at rest and, if the robot stayed still and level, make
+    /// the derived calibration current. Takes about
+    /// `CALIBRATION_SAMPLES * CALIBRATION_INTERVAL_MS` milliseconds.
+    async fn calibrate_imu(&mut self) -> Result<ImuCalibration, CalibrationError> {
+        let Some(devs) = self.sensors.as_mut().filter(|d| d.imu_ready()) else {
+            return Err(CalibrationError::Device("imu_not_initialized"));
+        };
+        let motors = state::snapshot().motors;
+        if motors.enabled && motors.duties.iter().any(|&d| d != 0.0) {
+            return Err(CalibrationError::MotorsRunning);
+        }
+
+        let mut calibrator = Calibrator::new();
+        for _ in 0..CALIBRATION_SAMPLES {
+            match devs.read_raw_imu() {
+                Ok(sample) => calibrator.push(sample),
+                Err(e) => {
+                    metrics::device_error(&e);
+                    return Err(CalibrationError::Device(e.kind()));
+                }
+            }
+            Timer::after_millis(CALIBRATION_INTERVAL_MS).await;
+        }
+        let calibration = calibrator.finish()?;
+        devs.set_calibration(calibration);
+        Ok(calibration)
+    }
+
+    /// Zero the wheels and latch `trip` until `ClearSafety`.
+    fn safety_stop(
+        &mut self,
+        trip: SafetyTrip,
+    ) {
+        tracing::error!(?trip, "Safety cutoff");
+        i2c::MOTION_SETPOINT.reset();
+        if let Some(devs) = self.sensors.as_mut() {
+            if let Err(e) = devs.apply_wheel_speeds(&[0.0; state::WHEEL_COUNT]) {
+                metrics::device_error(&e);
+                tracing::error!("Failed to stop motors: {:?}", e);
+            }
+        }
+        state::update(|s| s.safety = Some(trip));
+        events::publish(Event::Safety(Some(trip)));
+    }
+
+    /// Release a latched safety cutoff and restart detection from scratch.
+    fn clear_safety(&mut self) {
+        self.safety.reset();
+        if state::update(|s| s.safety.take()).is_some() {
+            tracing::info!("Safety cutoff cleared");
+            events::publish(Event::Safety(None));
+        }
+    }
+
+    /// Report a traction fault or its end, and derate or stop the wheels as
+    /// configured.
+    fn traction_change(
+        &mut self,
+        change: TractionChange,
+    ) {
+        let fault = match change {
+            TractionChange::Fault(fault) => fault,
+            TractionChange::Cleared => {
+                tracing::info!("Traction fault cleared");
+                traction::set_fault(None);
+                events::publish(Event::Traction(None));
+                return;
+            }
+        };
+        tracing::warn!(?fault, "Traction fault");
+        traction::set_fault(Some(fault));
+        events::publish(Event::Traction(Some(fault)));
+
+        let Some(devs) = self.sensors.as_mut() else {
+            return;
+        };
+        let config = traction::config();
+        let result = match config.action {
+            TractionAction::Report => Ok(()),
+            TractionAction::Derate => {
+                traction::set_duty_scale(config.derate);
+                devs.reapply_wheel_speeds()
+            }
+            TractionAction::Stop => {
+                i2c::MOTION_SETPOINT.reset();
+                devs.apply_wheel_speeds(&[0.0; state::WHEEL_COUNT])
+            }
+        };
+        if let Err(e) = result {
+            metrics::device_error(&e);
+            tracing::error!("Failed to apply traction action: {:?}", e);
+        }
+    }
+
+    /// Publish a thermal reading, disable the motors once it is critical and
+    /// apply a changed duty scale to the running wheels.
+    fn thermal_update(
+        &mut self,
+        status: ThermalStatus,
+    ) {
+        let previous = state::update(|s| s.thermal.replace(status));
+        events::publish(Event::Thermal(status));
+        let from = previous.map_or(ThermalLevel::Normal, |p| p.level);
+        if status.level != from {
+            match status.level {
+                ThermalLevel::Critical => tracing::error!(?status, "Motors too hot"),
+                ThermalLevel::Warning => tracing::warn!(?status, "Motors hot, derating"),
+                ThermalLevel::Normal => tracing::info!(?status, "Motor temperature normal"),
+            }
+        }
+
+        let Some(devs) = self.sensors.as_mut() else {
+            return;
+        };
+        let motors = state::snapshot().motors;
+        let result = if status.level == ThermalLevel::Critical && motors.enabled {
+            i2c::MOTION_SETPOINT.reset();
+            devs.apply_wheel_speeds(&[0.0; state::WHEEL_COUNT])
+                .and_then(|()| devs.disable_motors())
+        } else if previous.map_or(1.0, |p| p.duty_scale) != status.duty_scale
+            && motors.duties.iter().any(|&d| d != 0.0)
+        {
+            devs.reapply_wheel_speeds()
+        } else {
+            Ok(())
+        };
+        if let Err(e) = result {
+            metrics::device_error(&e);
+            tracing::error!("Failed to derate motors: {:?}", e);
+        }
+    }
+
+    /// Take one IMU sample for the consumers flagged in `due`: publishing it
+    /// to the event bus (if anyone is listening), handing it to the attitude
+    /// estimator, storing it in the recorder, checking it for a safety trip,
+    /// comparing its yaw rate with the wheels and checking its temperature.
+    fn sample_imu(
+        &mut self,
+        due: [bool; 6],
+    ) {
+        let [publish, estimate, record, guard, grip, heat] = due;
+        let publish = publish && events::has_listeners();
+        if !(publish || estimate || record || guard || grip || heat) {
+            return;
+        }
+        let mut trip = None;
+        let mut change = None;
+        let mut thermal = None;
+        if let Some(devs) = self.sensors.as_mut().filter(|d| d.imu_ready()) {
+            match devs.read_imu() {
+                Ok(sample) => {
+                    let sample = ImuData::from(sample);
+                    if guard {
+                        let now = Instant::now().as_micros();
+                        trip = self.safety.check(&safety::limits(), &sample, now);
+                    }
+                    if grip {
+                        let now = Instant::now().as_micros();
+                        let duties = state::snapshot().motors.duties;
+                        let config = traction::config();
+                        change = self.traction.check(&config, duties, sample.gyro[2], now);
+                    }
+                    if heat {
+                        let now = Instant::now().as_micros();
+                        thermal = Some(self.thermal.update(&thermal::limits(), sample.temp, now));
+                    }
+                    if publish {
+                        events::publish(Event::Imu(sample));
+                    }
+                    if estimate {
+                        attitude::feed(sample);
+                    }
+                    if record {
+                        recorder::push(recorder::ImuRecord {
+                            t: Instant::now().as_micros(),
+                            sample,
+                        });
+                    }
+                }
+                Err(e) => {
+                    metrics::device_error(&e);
+                    tracing::warn!("Periodic IMU read failed: {:?}", e);
+                }
+            }
+        }
+        if let Some(trip) = trip {
+            self.safety_stop(trip);
+        }
+        if let Some(change) = change {
+            self.traction_change(change);
+        }
+        if let Some(status) = thermal {
+            self.thermal_update(status);
+        }
+    }
+}
+
+/// Queue the result of a command for the server, dropping it if the queue is
+/// full of results nobody waited for.
+fn publish_reply<T>(
+    channel: &Channel<CriticalSectionRawMutex, T, MAX_BATCH>,
+    reply: T,
+) {
+    if channel.try_send(reply).is_err() {
+        tracing::warn!("Command result dropped: reply queue full");
+    }
 }
@@ -0,0 +1,320 @@
+//! IMU recorder.
+//!
+//! While recording, the I2C task samples the IMU at the requested rate between
+//! commands and stores each calibrated sample, stamped with the time it was
+//! read, in a ring of `RECORDER_CAPACITY` samples:
+//!
+//! - `ic: record` records continuously, overwriting the oldest samples when the
+//!   ring is full (counted in `RecorderStatus::dropped`); a rate of 0 stops.
+//! - `ic: capture` clears the ring, records `n` samples and stops, e.g. to
+//!   catch a vibration profile or the moments around a crash.
+//!
+//! Clients download the samples oldest first with `gc: samples`, which removes
+//! up to `MAX_DOWNLOAD` samples per reply and reports how many remain.
+//!
+//! Samples are polled over I2C: the ICM42670 FIFO is not used, as the driver
+//! does not expose it. The achievable rate is bounded by the bus and by the
+//! IMU's output data rate (`imu_config`); the timestamps show when each
+//! sample was actually taken.
+
+use core::{cell::RefCell, fmt};
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::Duration;
+use serde::{Deserialize, Serialize};
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+use super::i2c::ImuData;
+
+/// Samples held by the ring.
+pub const RECORDER_CAPACITY: usize = 512;
+/// Highest recording rate accepted, in Hz.
+pub const MAX_RECORD_RATE: f32 = 1000.0;
+/// Most samples returned by one `gc: samples` reply.
+pub const MAX_DOWNLOAD: usize = 32;
+
+static RECORDER: Mutex<CriticalSectionRawMutex, RefCell<Recorder>> =
+    Mutex::new(RefCell::new(Recorder::new()));
+
+/// An IMU sample with the time it was read.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub struct ImuRecord {
+    /// Microseconds since boot.
+    pub t: u64,
+    #[serde(flatten)]
+    pub sample: ImuData,
+}
+
+/// What the recorder is doing and how much it holds.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct RecorderStatus {
+    /// Recording rate in Hz, or 0 when stopped.
+    pub rate: f32,
+    /// Samples still to take for the current capture, if one is running.
+    pub capture_left: Option<usize>,
+    /// Samples waiting to be downloaded.
+    pub buffered: usize,
+    pub capacity: usize,
+    /// Samples overwritten before they were downloaded since recording started.
+    pub dropped: u32,
+}
+
+/// Why a `record` or `capture` command was rejected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecorderError {
+    /// The rate was not finite, negative, above `MAX_RECORD_RATE`, or zero
+    /// for a capture.
+    BadRate(f32),
+    /// The capture length was zero or more than the ring holds.
+    BadCount(usize),
+}
+
+impl fmt::Display for RecorderError {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        match self {
+            RecorderError::BadRate(rate) => {
+                write!(f, "rate must be up to {MAX_RECORD_RATE} Hz, got {rate}")
+            }
+            RecorderError::BadCount(n) => {
+                write!(f, "n must be 1..={RECORDER_CAPACITY}, got {n}")
+            }
+        }
+    }
+}
+
+/// Check a recording rate, and the sample count of a capture (`Some`), before
+/// passing them to `record` or `capture`.
+pub fn check(
+    rate: f32,
+    count: Option<usize>,
+) -> Result<(), RecorderError> {
+    let min = if count.is_some() {
+        f32::MIN_POSITIVE
+    } else {
+        0.0
+    };
+    if !(min..=MAX_RECORD_RATE).contains(&rate) {
+        return Err(RecorderError::BadRate(rate));
+    }
+    match count {
+        Some(n) if !(1..=RECORDER_CAPACITY).contains(&n) => Err(RecorderError::BadCount(n)),
+        _ => Ok(()),
+    }
+}
+
+/// Fixed-capacity ring of samples and the recording settings.
+pub struct Recorder {
+    ring: [ImuRecord; RECORDER_CAPACITY],
+    /// Index of the oldest sample.
+    head: usize,
+    len: usize,
+    rate: f32,
+    capture_left: Option<usize>,
+    dropped: u32,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Recorder {
+    pub const fn new() -> Self {
+        const EMPTY: ImuRecord = ImuRecord {
+            t: 0,
+            sample: ImuData {
+                accel: [0.0; 3],
+                gyro: [0.0; 3],
+                temp: 0.0,
+            },
+        };
+        Recorder {
+            ring: [EMPTY; RECORDER_CAPACITY],
+            head: 0,
+            len: 0,
+            rate: 0.0,
+            capture_left: None,
+            dropped: 0,
+        }
+    }
+
+    /// Record continuously at `rate` Hz, keeping the samples already held;
+    /// 0 stops recording.
+    pub fn record(
+        &mut self,
+        rate: f32,
+    ) {
+        if self.rate == 0.0 {
+            self.dropped = 0;
+        }
+        self.rate = rate;
+        self.capture_left = None;
+    }
+
+    /// Discard the held samples and record `count` new ones at `rate` Hz.
+    pub fn capture(
+        &mut self,
+        count: usize,
+        rate: f32,
+    ) {
+        self.head = 0;
+        self.len = 0;
+        self.dropped = 0;
+        self.rate = rate;
+        self.capture_left = Some(count);
+    }
+
+    /// Interval between samples, if recording.
+    pub fn period(&self) -> Option<Duration> {
+        (self.rate > 0.0).then(|| Duration::from_micros((1_000_000.0 / self.rate) as u64))
+    }
+
+    /// Store a sample, overwriting the oldest if the ring is full. Ignored
+    /// unless recording.
+    pub fn push(
+        &mut self,
+        record: ImuRecord,
+    ) {
+        if self.rate == 0.0 {
+            return;
+        }
+        if self.len == RECORDER_CAPACITY {
+            self.head = (self.head + 1) % RECORDER_CAPACITY;
+            self.len -= 1;
+            self.dropped = self.dropped.saturating_add(1);
+        }
+        self.ring[(self.head + self.len) % RECORDER_CAPACITY] = record;
+        self.len += 1;
+
+        if let Some(left) = self.capture_left.as_mut() {
+            *left -= 1;
+            if *left == 0 {
+                self.rate = 0.0;
+                self.capture_left = None;
+            }
+        }
+    }
+
+    /// Remove and return up to `max` of the oldest samples.
+    pub fn take(
+        &mut self,
+        max: usize,
+    ) -> Vec<ImuRecord> {
+        let n = max.min(self.len);
+        let taken = (0..n)
+            .map(|i| self.ring[(self.head + i) % RECORDER_CAPACITY])
+            .collect();
+        self.head = (self.head + n) % RECORDER_CAPACITY;
+        self.len -= n;
+        taken
+    }
+
+    pub fn status(&self) -> RecorderStatus {
+        RecorderStatus {
+            rate: self.rate,
+            capture_left: self.capture_left,
+            buffered: self.len,
+            capacity: RECORDER_CAPACITY,
+            dropped: self.dropped,
+        }
+    }
+}
+
+/// Record continuously at `rate` Hz (see `Recorder::record`).
+pub fn record(rate: f32) {
+    RECORDER.lock(|r| r.borrow_mut().record(rate));
+}
+
+/// Capture `count` samples at `rate` Hz (see `Recorder::capture`).
+pub fn capture(
+    count: usize,
+    rate: f32,
+) {
+    RECORDER.lock(|r| r.borrow_mut().capture(count, rate));
+}
+
+/// Interval between samples, if recording.
+pub fn period() -> Option<Duration> {
+    RECORDER.lock(|r| r.borrow().period())
+}
+
+/// Store a sample taken by the I2C task.
+pub fn push(record: ImuRecord) {
+    RECORDER.lock(|r| r.borrow_mut().push(record));
+}
+
+/// Remove up to `max` (at most `MAX_DOWNLOAD`) of the oldest samples, and
+/// return them with the number still held.
+pub fn take(max: usize) -> (Vec<ImuRecord>, usize) {
+    RECORDER.lock(|r| {
+        let mut r = r.borrow_mut();
+        let samples = r.take(max.min(MAX_DOWNLOAD));
+        (samples, r.len)
+    })
+}
+
+pub fn status() -> RecorderStatus {
+    RECORDER.lock(|r| r.borrow().status())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(t: u64) -> ImuRecord {
+        ImuRecord {
+            t,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn ring_overwrites_oldest() {
+        let mut recorder = Recorder::new();
+        recorder.push(at(0));
+        assert_eq!(recorder.status().buffered, 0, "not recording");
+
+        recorder.record(100.0);
+        assert_eq!(recorder.period(), Some(Duration::from_millis(10)));
+        for t in 0..RECORDER_CAPACITY as u64 + 3 {
+            recorder.push(at(t));
+        }
+        assert_eq!(recorder.status().dropped, 3);
+        let first = recorder.take(2);
+        assert_eq!((first[0].t, first[1].t), (3, 4));
+        assert_eq!(recorder.status().buffered, RECORDER_CAPACITY - 2);
+
+        recorder.record(0.0);
+        assert_eq!(recorder.period(), None);
+    }
+
+    #[test]
+    fn capture_stops_after_count() {
+        let mut recorder = Recorder::new();
+        recorder.record(50.0);
+        recorder.push(at(1));
+        recorder.capture(2, 200.0);
+        for t in 10..15 {
+            recorder.push(at(t));
+        }
+        let status = recorder.status();
+        assert_eq!(
+            (status.rate, status.buffered, status.capture_left),
+            (0.0, 2, None)
+        );
+        let samples = recorder.take(10);
+        assert_eq!(samples.iter().map(|s| s.t).collect::<Vec<_>>(), [10, 11]);
+        assert!(recorder.take(10).is_empty());
+
+        assert!(check(0.0, None).is_ok());
+        assert_eq!(check(0.0, Some(5)), Err(RecorderError::BadRate(0.0)));
+        assert_eq!(check(10.0, Some(0)), Err(RecorderError::BadCount(0)));
+        assert!(check(f32::NAN, None).is_err());
+    }
+}
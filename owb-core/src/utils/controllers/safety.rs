@@ -0,0 +1,271 @@
+//! Tip-over, lift and collision cutoff.
+//!
+//! While the motor driver is enabled, the I2C task samples the IMU at
+//! `SafetyLimits::rate_hz` and runs each sample through `SafetyMonitor`, which
+//! trips on:
+//!
+//! - tilt: gravity more than `max_tilt` degrees off the z axis,
+//! - free fall: total acceleration below `free_fall` g,
+//! - lift: vertical acceleration more than `lift` g above gravity,
+//! - collision: a horizontal acceleration spike above `impact` g.
+//!
+//! Tilt, free fall and lift must persist for `hold_ms` so a bump does not trip
+//! them; a collision trips on a single sample. A trip zeroes the wheels at
+//! once, latches a `SafetyTrip` in the shared state, and publishes
+//! `Event::Safety`, which WebSocket sessions forward to their clients as a
+//! `safety` report. Motion commands are refused until a client sends
+//! `ic: clear_safety`; if the condition persists the monitor trips again.
+//!
+//! Samples are taken after calibration (`calibration`), so calibrate the IMU
+//! with the robot level for the tilt check to be meaningful.
+
+use core::{cell::Cell, fmt};
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::Duration;
+use serde::{Deserialize, Serialize};
+
+use super::{i2c::ImuData, state};
+
+/// Limits used until `set_limits` is called.
+pub const DEFAULT_SAFETY: SafetyLimits = SafetyLimits {
+    enabled: true,
+    rate_hz: 100.0,
+    max_tilt: 45.0,
+    free_fall: 0.4,
+    lift: 0.4,
+    impact: 2.5,
+    hold_ms: 50,
+};
+
+static LIMITS: Mutex<CriticalSectionRawMutex, Cell<SafetyLimits>> =
+    Mutex::new(Cell::new(DEFAULT_SAFETY));
+
+/// Detection thresholds. Fields left out of a command or config file take
+/// their `DEFAULT_SAFETY` values.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct SafetyLimits {
+    /// Whether the monitor runs at all.
+    pub enabled: bool,
+    /// IMU samples checked per second.
+    pub rate_hz: f32,
+    /// Largest tilt from level, in degrees.
+    pub max_tilt: f32,
+    /// Total acceleration below which the robot is falling, in g.
+    pub free_fall: f32,
+    /// Upward acceleration beyond gravity that means the robot is being
+    /// lifted, in g.
+    pub lift: f32,
+    /// Horizontal acceleration that means a collision, in g.
+    pub impact: f32,
+    /// How long tilt, free fall or lift must last, in milliseconds.
+    pub hold_ms: u32,
+}
+
+impl Default for SafetyLimits {
+    fn default() -> Self {
+        DEFAULT_SAFETY
+    }
+}
+
+/// What tripped the cutoff, with the reading that did it.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(tag = "cause", rename_all = "snake_case")]
+pub enum SafetyTrip {
+    /// Tilted `angle` degrees from level.
+    Tilt { angle: f32 },
+    /// Total acceleration fell to `accel` g.
+    FreeFall { accel: f32 },
+    /// Accelerated upwards by `accel` g beyond gravity.
+    Lift { accel: f32 },
+    /// Horizontal acceleration reached `accel` g.
+    Collision { accel: f32 },
+}
+
+/// Current limits and latched trip, for `GetCommand::Safety`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct SafetyReport {
+    pub limits: SafetyLimits,
+    /// The trip holding the motors stopped, if any.
+    pub trip: Option<SafetyTrip>,
+}
+
+/// Why safety limits were rejected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BadLimit {
+    pub field: &'static str,
+}
+
+impl fmt::Display for BadLimit {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        write!(f, "`{}` must be a positive number", self.field)
+    }
+}
+
+impl SafetyLimits {
+    /// Check that every threshold is a positive, finite number.
+    pub fn check(&self) -> Result<(), BadLimit> {
+        for (field, value) in [
+            ("rate_hz", self.rate_hz),
+            ("max_tilt", self.max_tilt),
+            ("free_fall", self.free_fall),
+            ("lift", self.lift),
+            ("impact", self.impact),
+        ] {
+            if !(value > 0.0 && value.is_finite()) {
+                return Err(BadLimit { field });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Replace the safety limits.
+pub fn set_limits(limits: SafetyLimits) {
+    LIMITS.lock(|l| l.set(limits));
+}
+
+/// Safety limits currently applied.
+pub fn limits() -> SafetyLimits {
+    LIMITS.lock(|l| l.get())
+}
+
+/// Interval between safety checks, if the monitor is enabled, the motor driver
+/// is enabled and no trip is latched.
+pub fn period() -> Option<Duration> {
+    let limits = limits();
+    let s = state::snapshot();
+    let armed = limits.enabled && s.motors.enabled && s.safety.is_none();
+    armed.then(|| Duration::from_micros((1_000_000.0 / limits.rate_hz) as u64))
+}
+
+/// The latched trip, if the cutoff fired and was not cleared.
+pub fn tripped() -> Option<SafetyTrip> {
+    state::snapshot().safety
+}
+
+pub fn report() -> SafetyReport {
+    SafetyReport {
+        limits: limits(),
+        trip: tripped(),
+    }
+}
+
+/// Detects tip-over, free fall, lift and collisions in IMU samples.
+#[derive(Debug, Default)]
+pub struct SafetyMonitor {
+    /// When each sustained condition (tilt, free fall, lift) was first seen,
+    /// in microseconds.
+    since: [Option<u64>; 3],
+}
+
+impl SafetyMonitor {
+    pub const fn new() -> Self {
+        SafetyMonitor { since: [None; 3] }
+    }
+
+    /// Forget conditions seen so far, e.g. after a trip was cleared.
+    pub fn reset(&mut self) {
+        self.since = [None; 3];
+    }
+
+    /// Check a sample taken at `now` (microseconds) against `limits`.
+    pub fn check(
+        &mut self,
+        limits: &SafetyLimits,
+        sample: &ImuData,
+        now: u64,
+    ) -> Option<SafetyTrip> {
+        let [x, y, z] = sample.accel;
+        let horizontal = libm::hypotf(x, y);
+        let total = libm::hypotf(horizontal, z);
+        if horizontal > limits.impact {
+            return Some(SafetyTrip::Collision { accel: horizontal });
+        }
+
+        let angle = libm::atan2f(horizontal, z).to_degrees();
+        let conditions = [
+            (angle > limits.max_tilt).then_some(SafetyTrip::Tilt { angle }),
+            (total < limits.free_fall).then_some(SafetyTrip::FreeFall { accel: total }),
+            (z - 1.0 > limits.lift).then_some(SafetyTrip::Lift { accel: z - 1.0 }),
+        ];
+        let hold = u64::from(limits.hold_ms) * 1000;
+        let mut trip = None;
+        for (since, condition) in self.since.iter_mut().zip(conditions) {
+            match condition {
+                Some(condition) => {
+                    let start = *since.get_or_insert(now);
+                    if trip.is_none() && now.saturating_sub(start) >= hold {
+                        trip = Some(condition);
+                    }
+                }
+                None => *since = None,
+            }
+        }
+        trip
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accel(accel: [f32; 3]) -> ImuData {
+        ImuData {
+            accel,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sustained_conditions_trip_after_hold() {
+        let mut monitor = SafetyMonitor::new();
+        let level = accel([0.0, 0.0, 1.0]);
+        let tipped = accel([0.87, 0.0, 0.5]);
+        assert_eq!(monitor.check(&DEFAULT_SAFETY, &level, 0), None);
+        assert_eq!(monitor.check(&DEFAULT_SAFETY, &tipped, 10_000), None);
+        // A bump shorter than `hold_ms` resets the timer.
+        assert_eq!(monitor.check(&DEFAULT_SAFETY, &level, 20_000), None);
+        assert_eq!(monitor.check(&DEFAULT_SAFETY, &tipped, 30_000), None);
+        let trip = monitor.check(&DEFAULT_SAFETY, &tipped, 80_000);
+        assert!(matches!(trip, Some(SafetyTrip::Tilt { angle }) if (angle - 60.0).abs() < 0.5));
+
+        monitor.reset();
+        let falling = accel([0.0, 0.0, 0.1]);
+        assert_eq!(monitor.check(&DEFAULT_SAFETY, &falling, 100_000), None);
+        assert!(matches!(
+            monitor.check(&DEFAULT_SAFETY, &falling, 150_000),
+            Some(SafetyTrip::FreeFall { .. })
+        ));
+
+        monitor.reset();
+        let lifted = accel([0.0, 0.0, 1.5]);
+        monitor.check(&DEFAULT_SAFETY, &lifted, 0);
+        assert!(matches!(
+            monitor.check(&DEFAULT_SAFETY, &lifted, 60_000),
+            Some(SafetyTrip::Lift { .. })
+        ));
+    }
+
+    #[test]
+    fn collisions_trip_at_once() {
+        let mut monitor = SafetyMonitor::new();
+        assert_eq!(
+            monitor.check(&DEFAULT_SAFETY, &accel([3.0, 0.0, 1.0]), 0),
+            Some(SafetyTrip::Collision { accel: 3.0 })
+        );
+
+        let limits = SafetyLimits {
+            impact: 0.0,
+            ..DEFAULT_SAFETY
+        };
+        assert_eq!(limits.check(), Err(BadLimit { field: "impact" }));
+        let partial: SafetyLimits = serde_json::from_str(r#"{"max_tilt":30}"#).unwrap();
+        assert_eq!(partial.max_tilt, 30.0);
+        assert_eq!(partial.impact, DEFAULT_SAFETY.impact);
+    }
+}
@@ -0,0 +1,234 @@
+//! Shared snapshot of controller state.
+//!
+//! Each controller runs in its own task and owns its hardware, so whatever other
+//! tasks need to know about it (the connection handshake, `get` commands) is
+//! published here. The snapshot sits behind a critical-section mutex and is only
+//! copied in and out, never held across an `.await`.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    animation::Effect,
+    calibration::ImuCalibration,
+    i2c::I2CCommand,
+    imu_config::{ImuConfig, DEFAULT_IMU_CONFIG},
+    safety::SafetyTrip,
+    thermal::ThermalStatus,
+};
+
+/// Number of driven wheels.
+pub const WHEEL_COUNT: usize = 3;
+
+/// Global controller state, updated by the I2C and LED controllers.
+pub static SYSTEM_STATE: Mutex<CriticalSectionRawMutex, RefCell<SystemState>> =
+    Mutex::new(RefCell::new(SystemState::new()));
+
+/// Query commands answered from the shared state rather than by a controller.
+///
+/// Serialized as JSON with tag `"gc"`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(tag = "gc", rename_all = "snake_case")]
+pub enum GetCommand {
+    /// Firmware/protocol versions, robot dimensions and available devices.
+    Capabilities,
+    /// LED on/off state and current color.
+    Leds,
+    /// Motor driver enable state and last applied wheel duties.
+    Motors,
+    /// Last motion command applied by the I2C controller.
+    Motion,
+    /// LED, motor and motion state together.
+    State,
+    /// Counters and queue levels from `utils::metrics`.
+    Metrics,
+    /// Coefficients currently applied to IMU samples.
+    ImuCalibration,
+    /// Latest orientation estimate from `attitude`.
+    Attitude,
+    /// IMU range, output data rate and power mode.
+    ImuConfig,
+    /// Recording rate and fill level of the IMU `recorder`.
+    Recorder,
+    /// Download (and remove) up to `max` of the oldest recorded IMU samples.
+    Samples { max: Option<usize> },
+    /// Safety cutoff limits and latched trip.
+    Safety,
+    /// Traction monitor settings, outstanding fault and derating.
+    Traction,
+    /// Thermal limits and latest temperature reading.
+    Thermal,
+    /// Speed limits applied to motion commands.
+    MotionLimits,
+}
+
+impl GetCommand {
+    /// Wire names of all `GetCommand` variants, as advertised to clients.
+    pub const NAMES: &'static [&'static str] = &[
+        "capabilities",
+        "leds",
+        "motors",
+        "motion",
+        "state",
+        "metrics",
+        "imu_calibration",
+        "attitude",
+        "imu_config",
+        "recorder",
+        "samples",
+        "safety",
+        "traction",
+        "thermal",
+        "motion_limits",
+    ];
+
+    /// Wire name of this command.
+    pub fn name(&self) -> &'static str {
+        match self {
+            GetCommand::Capabilities => "capabilities",
+            GetCommand::Leds => "leds",
+            GetCommand::Motors => "motors",
+            GetCommand::Motion => "motion",
+            GetCommand::State => "state",
+            GetCommand::Metrics => "metrics",
+            GetCommand::ImuCalibration => "imu_calibration",
+            GetCommand::Attitude => "attitude",
+            GetCommand::ImuConfig => "imu_config",
+            GetCommand::Recorder => "recorder",
+            GetCommand::Samples { .. } => "samples",
+            GetCommand::Safety => "safety",
+            GetCommand::Traction => "traction",
+            GetCommand::Thermal => "thermal",
+            GetCommand::MotionLimits => "motion_limits",
+        }
+    }
+}
+
+/// LED strip state as last applied by the LED controller.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub struct LedState {
+    /// Whether the strip is lit.
+    pub on: bool,
+    /// Last color set with `sc` (`[r, g, b]`), if any.
+    pub color: Option<[u8; 3]>,
+    /// Effect currently playing, if any.
+    pub animation: Option<Effect>,
+    /// Global brightness (`0..=255`).
+    pub brightness: u8,
+}
+
+/// Motor driver state as last applied by the I2C controller.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub struct MotorState {
+    /// Whether the PWM driver is enabled (`enable`/`disable`).
+    pub enabled: bool,
+    /// Signed wheel duties in `-1.0..=1.0`, as written to the PWM driver.
+    pub duties: [f32; WHEEL_COUNT],
+}
+
+/// Last motion command (`t`, `y` or `o`) carried out, if any.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub struct MotionState {
+    pub last: Option<I2CCommand>,
+}
+
+/// Everything a reconnecting client needs to restore its controls.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct StateReport {
+    pub leds: LedState,
+    pub motors: MotorState,
+    pub motion: MotionState,
+}
+
+/// Snapshot of what the controllers have published.
+#[derive(Debug, Clone, Copy)]
+pub struct SystemState {
+    /// Firmware version reported in the handshake.
+    pub firmware: &'static str,
+    /// Wheel radius (m).
+    pub wheel_radius: f32,
+    /// Robot center-to-wheel distance (m).
+    pub robot_radius: f32,
+    /// Number of LEDs driven by the LED controller (0 if none was created).
+    pub led_count: usize,
+    /// Whether the IMU initialized.
+    pub imu_ready: bool,
+    /// Whether the PWM motor driver initialized.
+    pub pwm_ready: bool,
+    /// Whether I2C device initialization failed at boot.
+    pub i2c_failed: bool,
+    /// Kind of the error raised by the last I2C command, if it failed.
+    pub fault: Option<&'static str>,
+    /// Safety cutoff holding the motors stopped until cleared.
+    pub safety: Option<SafetyTrip>,
+    /// Latest reading of the thermal monitor.
+    pub thermal: Option<ThermalStatus>,
+    pub leds: LedState,
+    pub motors: MotorState,
+    pub motion: MotionState,
+    /// Calibration applied to IMU samples.
+    pub imu_calibration: ImuCalibration,
+    /// Settings read back from the IMU after it was last configured.
+    pub imu_config: ImuConfig,
+    /// Number of open WebSocket connections.
+    pub ws_clients: usize,
+}
+
+impl SystemState {
+    const fn new() -> Self {
+        SystemState {
+            firmware: env!("CARGO_PKG_VERSION"),
+            wheel_radius: 0.0,
+            robot_radius: 0.0,
+            led_count: 0,
+            imu_ready: false,
+            pwm_ready: false,
+            i2c_failed: false,
+            fault: None,
+            safety: None,
+            thermal: None,
+            leds: LedState {
+                on: false,
+                color: None,
+                animation: None,
+                brightness: u8::MAX,
+            },
+            motors: MotorState {
+                enabled: false,
+                duties: [0.0; WHEEL_COUNT],
+            },
+            motion: MotionState { last: None },
+            imu_calibration: ImuCalibration::IDENTITY,
+            imu_config: DEFAULT_IMU_CONFIG,
+            ws_clients: 0,
+        }
+    }
+
+    /// LED, motor and motion state for `GetCommand::State`.
+    pub fn report(&self) -> StateReport {
+        StateReport {
+            leds: self.leds,
+            motors: self.motors,
+            motion: self.motion,
+        }
+    }
+}
+
+/// Copy out the current state.
+pub fn snapshot() -> SystemState {
+    SYSTEM_STATE.lock(|state| *state.borrow())
+}
+
+/// Modify the shared state in place, returning whatever `f` returns.
+pub fn update<R>(f: impl FnOnce(&mut SystemState) -> R) -> R {
+    SYSTEM_STATE.lock(|state| f(&mut state.borrow_mut()))
+}
+
+/// Override the firmware version reported to clients.
+///
+/// Defaults to the `owb-core` crate version; applications should set their own.
+pub fn set_firmware_version(version: &'static str) {
+    update(|s| s.firmware = version);
+}
@@ -0,0 +1,195 @@
+//! Robot status shown on the LED strip.
+//!
+//! `RobotStatus::from_state` condenses the shared controller state into the one
+//! condition worth showing, and `StatusIndicator` maps each condition to an
+//! `Animation`. `LedModule` plays the mapped pattern while the indicator is
+//! enabled (`LEDCommand::Status`), re-evaluating on every event-bus message.
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    animation::{Animation, Effect},
+    state::SystemState,
+    thermal::ThermalLevel,
+};
+
+/// What the robot is doing, from most to least urgent.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RobotStatus {
+    /// The I2C devices failed to initialize at boot.
+    I2cInitFailed,
+    /// The safety cutoff stopped the motors and was not cleared yet.
+    SafetyStop,
+    /// The motors were disabled because they are too hot.
+    Overheated,
+    /// The last I2C command failed.
+    Fault,
+    /// The motors run hot and are derated.
+    Hot,
+    /// No WebSocket client is connected.
+    NoClient,
+    /// Wheels are turning.
+    Moving,
+    /// The motor driver is enabled but the wheels are still.
+    MotorsEnabled,
+    /// A client is connected and the motors are disabled.
+    ClientConnected,
+}
+
+impl RobotStatus {
+    /// All statuses, in the order `StatusIndicator` stores their patterns.
+    pub const ALL: [RobotStatus; 9] = [
+        RobotStatus::I2cInitFailed,
+        RobotStatus::SafetyStop,
+        RobotStatus::Overheated,
+        RobotStatus::Fault,
+        RobotStatus::Hot,
+        RobotStatus::NoClient,
+        RobotStatus::Moving,
+        RobotStatus::MotorsEnabled,
+        RobotStatus::ClientConnected,
+    ];
+
+    /// The most urgent status that applies to `state`.
+    pub fn from_state(state: &SystemState) -> Self {
+        let thermal = state.thermal.map_or(ThermalLevel::Normal, |t| t.level);
+        if state.i2c_failed {
+            RobotStatus::I2cInitFailed
+        } else if state.safety.is_some() {
+            RobotStatus::SafetyStop
+        } else if thermal == ThermalLevel::Critical {
+            RobotStatus::Overheated
+        } else if state.fault.is_some() {
+            RobotStatus::Fault
+        } else if thermal == ThermalLevel::Warning {
+            RobotStatus::Hot
+        } else if state.ws_clients == 0 {
+            RobotStatus::NoClient
+        } else if state.motors.enabled && state.motors.duties.iter().any(|&d| d != 0.0) {
+            RobotStatus::Moving
+        } else if state.motors.enabled {
+            RobotStatus::MotorsEnabled
+        } else {
+            RobotStatus::ClientConnected
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Pattern shown for each `RobotStatus`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StatusIndicator {
+    patterns: [Animation; RobotStatus::ALL.len()],
+}
+
+const fn pattern(
+    fx: Effect,
+    speed: f32,
+    color: [u8; 3],
+) -> Animation {
+    Animation {
+        fx,
+        speed,
+        color,
+        color2: [0; 3],
+    }
+}
+
+impl Default for StatusIndicator {
+    /// Blinking red on init failure, fast blinking magenta after a safety
+    /// cutoff, blinking orange when overheated, solid orange on a fault, amber
+    /// breathing while hot, slow blue breathing while waiting for a client, a
+    /// green chase while moving, solid cyan with motors enabled and solid green
+    /// once a client is connected.
+    fn default() -> Self {
+        StatusIndicator {
+            patterns: [
+                pattern(Effect::Blink, 2.0, [255, 0, 0]),
+                pattern(Effect::Blink, 4.0, [255, 0, 255]),
+                pattern(Effect::Blink, 1.0, [255, 64, 0]),
+                pattern(Effect::Solid, 0.0, [255, 96, 0]),
+                pattern(Effect::Breathe, 1.0, [255, 160, 0]),
+                pattern(Effect::Breathe, 0.5, [0, 0, 255]),
+                pattern(Effect::Chase, 1.0, [0, 255, 0]),
+                pattern(Effect::Solid, 0.0, [0, 255, 255]),
+                pattern(Effect::Solid, 0.0, [0, 255, 0]),
+            ],
+        }
+    }
+}
+
+impl StatusIndicator {
+    /// Pattern shown for `status`.
+    pub fn pattern(
+        &self,
+        status: RobotStatus,
+    ) -> Animation {
+        self.patterns[status.index()]
+    }
+
+    /// Change the pattern shown for `status`.
+    pub fn set(
+        &mut self,
+        status: RobotStatus,
+        pattern: Animation,
+    ) {
+        self.patterns[status.index()] = pattern;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::controllers::{safety::SafetyTrip, state, thermal::ThermalStatus};
+
+    #[test]
+    fn most_urgent_status_wins() {
+        let mut s = state::snapshot();
+        s.i2c_failed = false;
+        s.fault = None;
+        s.ws_clients = 0;
+        s.motors.enabled = true;
+        s.motors.duties = [0.5, 0.0, 0.0];
+        assert_eq!(RobotStatus::from_state(&s), RobotStatus::NoClient);
+
+        s.ws_clients = 1;
+        assert_eq!(RobotStatus::from_state(&s), RobotStatus::Moving);
+        s.motors.duties = [0.0; 3];
+        assert_eq!(RobotStatus::from_state(&s), RobotStatus::MotorsEnabled);
+        s.motors.enabled = false;
+        assert_eq!(RobotStatus::from_state(&s), RobotStatus::ClientConnected);
+
+        let hot = ThermalStatus {
+            temp: 65.0,
+            trend: 1.0,
+            level: ThermalLevel::Warning,
+            duty_scale: 0.8,
+        };
+        s.thermal = Some(hot);
+        assert_eq!(RobotStatus::from_state(&s), RobotStatus::Hot);
+        s.fault = Some("pwm");
+        assert_eq!(RobotStatus::from_state(&s), RobotStatus::Fault);
+        s.thermal = Some(ThermalStatus {
+            level: ThermalLevel::Critical,
+            ..hot
+        });
+        assert_eq!(RobotStatus::from_state(&s), RobotStatus::Overheated);
+        s.safety = Some(SafetyTrip::Tilt { angle: 60.0 });
+        assert_eq!(RobotStatus::from_state(&s), RobotStatus::SafetyStop);
+        s.i2c_failed = true;
+        assert_eq!(RobotStatus::from_state(&s), RobotStatus::I2cInitFailed);
+    }
+
+    #[test]
+    fn patterns_are_configurable() {
+        let mut indicator = StatusIndicator::default();
+        let rainbow = pattern(Effect::Rainbow, 1.0, [0; 3]);
+        indicator.set(RobotStatus::Moving, rainbow);
+        assert_eq!(indicator.pattern(RobotStatus::Moving), rainbow);
+        assert_eq!(indicator.pattern(RobotStatus::Fault).fx, Effect::Solid);
+    }
+}
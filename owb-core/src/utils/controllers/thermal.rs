@@ -0,0 +1,321 @@
+//! Thermal monitoring and motor derating.
+//!
+//! The ICM42670 sits next to the motor drivers, so its die temperature tracks
+//! how hot they run. The I2C task samples it at `ThermalLimits::rate_hz` and
+//! runs each reading through `ThermalMonitor`, which smooths it, estimates the
+//! trend in °C per minute and classifies it:
+//!
+//! - below `warn`: normal, wheels get their full duty,
+//! - from `warn`: warning, every wheel duty is scaled down linearly, from 1 at
+//!   `warn` to `min_scale` at `critical`,
+//! - from `critical`: the motor driver is disabled and `Enable` is refused.
+//!
+//! A level is left only once the temperature falls `hysteresis` below the
+//! threshold that raised it. Leaving the critical level does not re-enable the
+//! motors; a client has to send `enable`. Every reading is published as
+//! `Event::Thermal` and stored in the shared state, which the status LEDs and
+//! WebSocket sessions watch for level changes.
+//!
+//! While the monitor is enabled `disable` leaves the IMU awake, so readings go
+//! on and a critical level clears once the drivers cool down. Disabling the
+//! monitor drops the last reading, which lifts any derating it applied.
+
+use core::{cell::Cell, fmt};
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::Duration;
+use serde::{Deserialize, Serialize};
+
+use super::state;
+
+/// Limits used until `set_limits` is called.
+pub const DEFAULT_THERMAL: ThermalLimits = ThermalLimits {
+    enabled: true,
+    rate_hz: 1.0,
+    warn: 60.0,
+    critical: 75.0,
+    min_scale: 0.3,
+    hysteresis: 3.0,
+};
+
+/// Time constant of the temperature smoothing, in seconds.
+const TEMP_TAU_S: f32 = 2.0;
+/// Time constant of the trend estimate, in seconds.
+const TREND_TAU_S: f32 = 30.0;
+
+static LIMITS: Mutex<CriticalSectionRawMutex, Cell<ThermalLimits>> =
+    Mutex::new(Cell::new(DEFAULT_THERMAL));
+
+/// Thresholds and derating. Fields left out of a command or config file take
+/// their `DEFAULT_THERMAL` values.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct ThermalLimits {
+    /// Whether the monitor runs at all.
+    pub enabled: bool,
+    /// Temperature readings per second.
+    pub rate_hz: f32,
+    /// Temperature at which derating starts, in °C.
+    pub warn: f32,
+    /// Temperature at which the motors are disabled, in °C.
+    pub critical: f32,
+    /// Duty factor reached at `critical`, in `(0, 1]`.
+    pub min_scale: f32,
+    /// How far below a threshold the temperature must fall to leave its
+    /// level, in °C.
+    pub hysteresis: f32,
+}
+
+impl Default for ThermalLimits {
+    fn default() -> Self {
+        DEFAULT_THERMAL
+    }
+}
+
+/// How hot the motor drivers run.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ThermalLevel {
+    #[default]
+    Normal,
+    /// Above `warn`; wheel duties are derated.
+    Warning,
+    /// Above `critical`; the motors are disabled.
+    Critical,
+}
+
+/// Latest thermal reading.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct ThermalStatus {
+    /// Smoothed die temperature, in °C.
+    pub temp: f32,
+    /// Rate of change, in °C per minute.
+    pub trend: f32,
+    pub level: ThermalLevel,
+    /// Factor applied to every wheel duty (0 while critical).
+    pub duty_scale: f32,
+}
+
+/// Current limits and latest reading, for `GetCommand::Thermal`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct ThermalReport {
+    pub limits: ThermalLimits,
+    /// `None` until the first reading.
+    pub status: Option<ThermalStatus>,
+}
+
+/// Why thermal limits were rejected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BadThermalLimit {
+    /// The field is not a number in range.
+    Field(&'static str),
+    /// `warn` is not below `critical`.
+    Order,
+}
+
+impl fmt::Display for BadThermalLimit {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        match self {
+            BadThermalLimit::Field("min_scale") => write!(f, "`min_scale` must be in (0, 1]"),
+            BadThermalLimit::Field("hysteresis") => {
+                write!(f, "`hysteresis` must be a non-negative number")
+            }
+            BadThermalLimit::Field(field) => write!(f, "`{field}` must be a number in range"),
+            BadThermalLimit::Order => write!(f, "`warn` must be below `critical`"),
+        }
+    }
+}
+
+impl ThermalLimits {
+    /// Check that every limit is finite and in range.
+    pub fn check(&self) -> Result<(), BadThermalLimit> {
+        if !(self.rate_hz > 0.0 && self.rate_hz.is_finite()) {
+            return Err(BadThermalLimit::Field("rate_hz"));
+        }
+        for (field, value) in [("warn", self.warn), ("critical", self.critical)] {
+            if !value.is_finite() {
+                return Err(BadThermalLimit::Field(field));
+            }
+        }
+        if self.warn >= self.critical {
+            return Err(BadThermalLimit::Order);
+        }
+        if !(self.min_scale > 0.0 && self.min_scale <= 1.0) {
+            return Err(BadThermalLimit::Field("min_scale"));
+        }
+        if !(self.hysteresis >= 0.0 && self.hysteresis.is_finite()) {
+            return Err(BadThermalLimit::Field("hysteresis"));
+        }
+        Ok(())
+    }
+
+    /// Level for `temp`, given the level it is coming from.
+    fn level(
+        &self,
+        from: ThermalLevel,
+        temp: f32,
+    ) -> ThermalLevel {
+        use ThermalLevel::*;
+        match from {
+            _ if temp >= self.critical => Critical,
+            Critical if temp > self.critical - self.hysteresis => Critical,
+            _ if temp >= self.warn => Warning,
+            Warning | Critical if temp > self.warn - self.hysteresis => Warning,
+            _ => Normal,
+        }
+    }
+
+    /// Duty factor for `temp` at `level`.
+    fn duty_scale(
+        &self,
+        level: ThermalLevel,
+        temp: f32,
+    ) -> f32 {
+        match level {
+            ThermalLevel::Normal => 1.0,
+            ThermalLevel::Warning => {
+                let heat = ((temp - self.warn) / (self.critical - self.warn)).clamp(0.0, 1.0);
+                1.0 - (1.0 - self.min_scale) * heat
+            }
+            ThermalLevel::Critical => 0.0,
+        }
+    }
+}
+
+/// Replace the thermal limits. Disabling the monitor clears the latest
+/// reading, since no further ones will come to lift its level.
+pub fn set_limits(limits: ThermalLimits) {
+    LIMITS.lock(|l| l.set(limits));
+    if !limits.enabled {
+        state::update(|s| s.thermal = None);
+    }
+}
+
+/// Thermal limits currently applied.
+pub fn limits() -> ThermalLimits {
+    LIMITS.lock(|l| l.get())
+}
+
+/// Interval between temperature readings, if the monitor is enabled.
+pub fn period() -> Option<Duration> {
+    let limits = limits();
+    limits
+        .enabled
+        .then(|| Duration::from_micros((1_000_000.0 / limits.rate_hz) as u64))
+}
+
+/// Latest reading, if any.
+pub fn status() -> Option<ThermalStatus> {
+    state::snapshot().thermal
+}
+
+/// Current level (`Normal` before the first reading).
+pub fn level() -> ThermalLevel {
+    status().map_or(ThermalLevel::Normal, |s| s.level)
+}
+
+/// Factor the I2C task applies to every wheel duty.
+pub fn duty_scale() -> f32 {
+    status().map_or(1.0, |s| s.duty_scale)
+}
+
+pub fn report() -> ThermalReport {
+    ThermalReport {
+        limits: limits(),
+        status: status(),
+    }
+}
+
+/// Smooths temperature readings and tracks their trend and level.
+#[derive(Debug, Default)]
+pub struct ThermalMonitor {
+    /// Last reading, with the time it was taken in microseconds.
+    last: Option<(u64, ThermalStatus)>,
+}
+
+impl ThermalMonitor {
+    pub const fn new() -> Self {
+        ThermalMonitor { last: None }
+    }
+
+    /// Fold in die temperature `temp` (°C), read at `now` (microseconds).
+    pub fn update(
+        &mut self,
+        limits: &ThermalLimits,
+        temp: f32,
+        now: u64,
+    ) -> ThermalStatus {
+        let (smoothed, trend, from) = match self.last {
+            Some((at, prev)) if now > at => {
+                let dt = (now - at) as f32 / 1_000_000.0;
+                let smoothed = prev.temp + (temp - prev.temp) * dt / (TEMP_TAU_S + dt);
+                let slope = (smoothed - prev.temp) / dt * 60.0;
+                let trend = prev.trend + (slope - prev.trend) * dt / (TREND_TAU_S + dt);
+                (smoothed, trend, prev.level)
+            }
+            Some((_, prev)) => (prev.temp, prev.trend, prev.level),
+            None => (temp, 0.0, ThermalLevel::Normal),
+        };
+        let level = limits.level(from, smoothed);
+        let status = ThermalStatus {
+            temp: smoothed,
+            trend,
+            level,
+            duty_scale: limits.duty_scale(level, smoothed),
+        };
+        self.last = Some((now, status));
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_derate_with_hysteresis() {
+        let limits = DEFAULT_THERMAL;
+        let step = |from, temp| limits.level(from, temp);
+        assert_eq!(step(ThermalLevel::Normal, 59.0), ThermalLevel::Normal);
+        assert_eq!(step(ThermalLevel::Normal, 61.0), ThermalLevel::Warning);
+        assert_eq!(step(ThermalLevel::Warning, 58.0), ThermalLevel::Warning);
+        assert_eq!(step(ThermalLevel::Warning, 56.0), ThermalLevel::Normal);
+        assert_eq!(step(ThermalLevel::Warning, 75.0), ThermalLevel::Critical);
+        assert_eq!(step(ThermalLevel::Critical, 73.0), ThermalLevel::Critical);
+        assert_eq!(step(ThermalLevel::Critical, 70.0), ThermalLevel::Warning);
+        assert_eq!(step(ThermalLevel::Critical, 50.0), ThermalLevel::Normal);
+
+        let scale = |temp| limits.duty_scale(limits.level(ThermalLevel::Normal, temp), temp);
+        assert_eq!(scale(40.0), 1.0);
+        assert_eq!(scale(60.0), 1.0);
+        assert!((scale(67.5) - 0.65).abs() < 1e-4);
+        assert_eq!(scale(80.0), 0.0);
+
+        let swapped = ThermalLimits {
+            warn: 80.0,
+            ..DEFAULT_THERMAL
+        };
+        assert_eq!(swapped.check(), Err(BadThermalLimit::Order));
+        assert!(DEFAULT_THERMAL.check().is_ok());
+    }
+
+    #[test]
+    fn tracks_trend() {
+        let mut monitor = ThermalMonitor::new();
+        let first = monitor.update(&DEFAULT_THERMAL, 40.0, 0);
+        assert_eq!((first.temp, first.trend), (40.0, 0.0));
+
+        // Heating at 6 °C per minute, sampled once a second.
+        let mut status = first;
+        for s in 1..=300u64 {
+            status = monitor.update(&DEFAULT_THERMAL, 40.0 + 0.1 * s as f32, s * 1_000_000);
+        }
+        assert!((status.trend - 6.0).abs() < 0.3, "trend {}", status.trend);
+        assert!((status.temp - 70.0).abs() < 0.5, "temp {}", status.temp);
+        assert_eq!(status.level, ThermalLevel::Warning);
+        assert!(status.duty_scale < 0.6);
+    }
+}
@@ -0,0 +1,376 @@
+//! Wheel stall, disconnect and slip detection.
+//!
+//! While the motor driver is enabled and the wheels are driven, the I2C task
+//! samples the IMU at `TractionConfig::rate_hz` and hands the gyro z rate to
+//! `TractionMonitor`. The monitor turns the applied wheel duties into the
+//! expected yaw rate with `EmbodiedKinematics::compute_body_velocity`, scaled
+//! by `yaw_gain`, and flags a fault when the two differ by more than
+//! `tolerance` for `hold_ms`. The probable cause is:
+//!
+//! - stall: the robot does not turn at all, e.g. pushed against an obstacle or
+//!   with the motor supply gone,
+//! - disconnected wheel: the measured rate matches what the other two wheels
+//!   alone would produce (a guess when several wheels would explain it, as in
+//!   pure rotation),
+//! - slip: any other mismatch, typically wheels spinning on a smooth floor.
+//!
+//! A fault is published as `Event::Traction` once, and cleared with another
+//! event when the mismatch goes away. Depending on `TractionConfig::action`
+//! the I2C task also scales every wheel duty by `derate` until the motors are
+//! disabled, or stops the wheels.
+//!
+//! Only rotation is observed: a stall while driving straight produces no yaw
+//! error and goes unnoticed. Duties are not wheel speeds, so `yaw_gain` must be
+//! measured for each robot; the monitor is off by default.
+
+use core::{cell::Cell, fmt};
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::Duration;
+use serde::{Deserialize, Serialize};
+
+use super::state::{self, WHEEL_COUNT};
+use crate::utils::ek;
+
+/// Settings used until `set_config` is called.
+pub const DEFAULT_TRACTION: TractionConfig = TractionConfig {
+    enabled: false,
+    rate_hz: 50.0,
+    yaw_gain: 180.0,
+    tolerance: 20.0,
+    hold_ms: 500,
+    action: TractionAction::Report,
+    derate: 0.5,
+};
+
+static CONFIG: Mutex<CriticalSectionRawMutex, Cell<TractionConfig>> =
+    Mutex::new(Cell::new(DEFAULT_TRACTION));
+
+/// Last fault reported and not cleared yet.
+static FAULT: Mutex<CriticalSectionRawMutex, Cell<Option<TractionFault>>> =
+    Mutex::new(Cell::new(None));
+
+/// Factor applied to every wheel duty while derated.
+static DUTY_SCALE: Mutex<CriticalSectionRawMutex, Cell<f32>> = Mutex::new(Cell::new(1.0));
+
+/// What the I2C task does when a fault is detected, besides reporting it.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TractionAction {
+    /// Only publish the fault.
+    Report,
+    /// Scale wheel duties by `TractionConfig::derate` until the motors are
+    /// disabled.
+    Derate,
+    /// Zero the wheels; the next motion command drives them again.
+    Stop,
+}
+
+/// Detection settings. Fields left out of a command or config file take their
+/// `DEFAULT_TRACTION` values.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct TractionConfig {
+    /// Whether the monitor runs at all.
+    pub enabled: bool,
+    /// IMU samples checked per second.
+    pub rate_hz: f32,
+    /// Gyro z rate in °/s per unit of body rotation computed from the wheel
+    /// duties; negative if positive duties turn the robot clockwise.
+    pub yaw_gain: f32,
+    /// Largest difference between expected and measured yaw rate, in °/s.
+    pub tolerance: f32,
+    /// How long the difference must last, in milliseconds.
+    pub hold_ms: u32,
+    pub action: TractionAction,
+    /// Duty factor applied by `TractionAction::Derate`, in `(0, 1]`.
+    pub derate: f32,
+}
+
+impl Default for TractionConfig {
+    fn default() -> Self {
+        DEFAULT_TRACTION
+    }
+}
+
+/// Probable cause of a yaw rate mismatch, with the expected and measured rates
+/// in °/s.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(tag = "cause", rename_all = "snake_case")]
+pub enum TractionFault {
+    /// The robot does not turn although the wheels should turn it.
+    Stall { expected: f32, measured: f32 },
+    /// Wheel `wheel` (0-based) does not contribute to the motion.
+    Disconnected {
+        wheel: usize,
+        expected: f32,
+        measured: f32,
+    },
+    /// The robot turns, but not as the wheels should turn it.
+    Slip { expected: f32, measured: f32 },
+}
+
+/// Current settings, outstanding fault and derating, for
+/// `GetCommand::Traction`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct TractionReport {
+    pub config: TractionConfig,
+    pub fault: Option<TractionFault>,
+    /// Factor currently applied to wheel duties.
+    pub duty_scale: f32,
+}
+
+/// Why traction settings were rejected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BadSetting {
+    pub field: &'static str,
+}
+
+impl fmt::Display for BadSetting {
+    fn fmt(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+    ) -> fmt::Result {
+        match self.field {
+            "yaw_gain" => write!(f, "`yaw_gain` must be a non-zero number"),
+            "derate" => write!(f, "`derate` must be in (0, 1]"),
+            field => write!(f, "`{field}` must be a positive number"),
+        }
+    }
+}
+
+impl TractionConfig {
+    /// Check that every setting is finite and in range.
+    pub fn check(&self) -> Result<(), BadSetting> {
+        let positive = |value: f32| value > 0.0 && value.is_finite();
+        if !positive(self.rate_hz) {
+            return Err(BadSetting { field: "rate_hz" });
+        }
+        if !(self.yaw_gain != 0.0 && self.yaw_gain.is_finite()) {
+            return Err(BadSetting { field: "yaw_gain" });
+        }
+        if !positive(self.tolerance) {
+            return Err(BadSetting { field: "tolerance" });
+        }
+        if !(self.derate > 0.0 && self.derate <= 1.0) {
+            return Err(BadSetting { field: "derate" });
+        }
+        Ok(())
+    }
+}
+
+/// Replace the traction settings.
+pub fn set_config(config: TractionConfig) {
+    CONFIG.lock(|c| c.set(config));
+}
+
+/// Traction settings currently applied.
+pub fn config() -> TractionConfig {
+    CONFIG.lock(|c| c.get())
+}
+
+/// Interval between traction checks, if the monitor and the motor driver are
+/// enabled.
+pub fn period() -> Option<Duration> {
+    let config = config();
+    let armed = config.enabled && state::snapshot().motors.enabled;
+    armed.then(|| Duration::from_micros((1_000_000.0 / config.rate_hz) as u64))
+}
+
+/// Record the outstanding fault (`None` once cleared).
+pub fn set_fault(fault: Option<TractionFault>) {
+    FAULT.lock(|f| f.set(fault));
+}
+
+/// Factor the I2C task applies to every wheel duty.
+pub fn duty_scale() -> f32 {
+    DUTY_SCALE.lock(|s| s.get())
+}
+
+/// Scale wheel duties by `scale` from the next time they are applied; `1.0`
+/// ends derating.
+pub fn set_duty_scale(scale: f32) {
+    DUTY_SCALE.lock(|s| s.set(scale));
+}
+
+pub fn report() -> TractionReport {
+    TractionReport {
+        config: config(),
+        fault: FAULT.lock(|f| f.get()),
+        duty_scale: duty_scale(),
+    }
+}
+
+/// What `TractionMonitor::check` found in a sample.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TractionChange {
+    /// A mismatch lasted `hold_ms`.
+    Fault(TractionFault),
+    /// The mismatch behind the last fault went away.
+    Cleared,
+}
+
+/// Compares the yaw rate expected from the wheel duties with the gyro.
+pub struct TractionMonitor {
+    kinematics: ek,
+    /// When the current mismatch was first seen, in microseconds.
+    since: Option<u64>,
+    /// Whether the current mismatch was already reported.
+    reported: bool,
+}
+
+impl TractionMonitor {
+    pub fn new(
+        wheel_radius: f32,
+        robot_radius: f32,
+    ) -> Self {
+        TractionMonitor {
+            kinematics: ek::new(wheel_radius, robot_radius),
+            since: None,
+            reported: false,
+        }
+    }
+
+    /// Forget the current mismatch, e.g. after the motors were disabled.
+    pub fn reset(&mut self) {
+        self.since = None;
+        self.reported = false;
+    }
+
+    /// Expected yaw rate in °/s for wheel `duties`.
+    fn expected(
+        &self,
+        config: &TractionConfig,
+        duties: [f32; WHEEL_COUNT],
+    ) -> f32 {
+        let (_, _, w) = self.kinematics.compute_body_velocity(duties);
+        w * config.yaw_gain
+    }
+
+    /// Most likely cause of `measured` differing from the rate expected for
+    /// `duties`.
+    fn diagnose(
+        &self,
+        config: &TractionConfig,
+        duties: [f32; WHEEL_COUNT],
+        expected: f32,
+        measured: f32,
+    ) -> TractionFault {
+        if measured.abs() < config.tolerance {
+            return TractionFault::Stall { expected, measured };
+        }
+        let without = |wheel: usize| {
+            let mut duties = duties;
+            duties[wheel] = 0.0;
+            (self.expected(config, duties) - measured).abs()
+        };
+        let best = (0..WHEEL_COUNT)
+            .filter(|&wheel| duties[wheel] != 0.0)
+            .min_by(|&a, &b| without(a).total_cmp(&without(b)));
+        match best {
+            Some(wheel) if without(wheel) < config.tolerance => TractionFault::Disconnected {
+                wheel,
+                expected,
+                measured,
+            },
+            _ => TractionFault::Slip { expected, measured },
+        }
+    }
+
+    /// Check gyro z rate `measured` (°/s), taken at `now` (microseconds) while
+    /// the wheels run at `duties`.
+    pub fn check(
+        &mut self,
+        config: &TractionConfig,
+        duties: [f32; WHEEL_COUNT],
+        measured: f32,
+        now: u64,
+    ) -> Option<TractionChange> {
+        let driven = duties.iter().any(|&d| d != 0.0);
+        let expected = self.expected(config, duties);
+        if !driven || (expected - measured).abs() <= config.tolerance {
+            self.since = None;
+            return core::mem::take(&mut self.reported).then_some(TractionChange::Cleared);
+        }
+
+        let start = *self.since.get_or_insert(now);
+        let hold = u64::from(config.hold_ms) * 1000;
+        if self.reported || now.saturating_sub(start) < hold {
+            return None;
+        }
+        self.reported = true;
+        Some(TractionChange::Fault(
+            self.diagnose(config, duties, expected, measured),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: TractionConfig = TractionConfig {
+        enabled: true,
+        ..DEFAULT_TRACTION
+    };
+
+    fn monitor() -> TractionMonitor {
+        TractionMonitor::new(0.148, 0.195)
+    }
+
+    #[test]
+    fn sustained_mismatch_is_reported_once() {
+        let mut monitor = monitor();
+        let spin = [0.5; WHEEL_COUNT];
+        let expected = monitor.expected(&CONFIG, spin);
+        assert!((expected - 0.5 * 0.148 / 0.195 * 180.0).abs() < 0.1);
+
+        assert_eq!(monitor.check(&CONFIG, spin, expected, 0), None);
+        assert_eq!(monitor.check(&CONFIG, spin, 0.0, 100_000), None);
+        let fault = monitor.check(&CONFIG, spin, 0.0, 600_000);
+        assert!(matches!(
+            fault,
+            Some(TractionChange::Fault(TractionFault::Stall { .. }))
+        ));
+        assert_eq!(monitor.check(&CONFIG, spin, 0.0, 700_000), None);
+        assert_eq!(
+            monitor.check(&CONFIG, spin, expected, 800_000),
+            Some(TractionChange::Cleared)
+        );
+        // Wheels at rest are never a mismatch.
+        assert_eq!(
+            monitor.check(&CONFIG, [0.0; WHEEL_COUNT], 90.0, 900_000),
+            None
+        );
+    }
+
+    #[test]
+    fn diagnoses_disconnected_wheel_and_slip() {
+        let mut monitor = monitor();
+        let spin = [0.5; WHEEL_COUNT];
+        let expected = monitor.expected(&CONFIG, spin);
+
+        // Turning while strafing; wheel 2 contributes nothing.
+        let drive = [1.0, -0.5, 0.8];
+        let partial = monitor.expected(&CONFIG, [1.0, -0.5, 0.0]);
+        let full = monitor.expected(&CONFIG, drive);
+        let fault = monitor.diagnose(&CONFIG, drive, full, partial);
+        assert!(matches!(
+            fault,
+            TractionFault::Disconnected { wheel: 2, .. }
+        ));
+
+        let fault = monitor.diagnose(&CONFIG, spin, expected, -expected);
+        assert!(matches!(fault, TractionFault::Slip { .. }));
+
+        monitor.check(&CONFIG, spin, -expected, 0);
+        assert!(monitor.check(&CONFIG, spin, -expected, 500_000).is_some());
+
+        let bad = TractionConfig {
+            derate: 1.5,
+            ..DEFAULT_TRACTION
+        };
+        assert_eq!(bad.check(), Err(BadSetting { field: "derate" }));
+        assert!(DEFAULT_TRACTION.check().is_ok());
+    }
+}
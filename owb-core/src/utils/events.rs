@@ -0,0 +1,181 @@
+//! Telemetry event bus.
+//!
+//! Controllers publish what happens (IMU samples, motor changes, applied
+//! motion setpoints, WebSocket connections, device faults, safety cutoffs,
+//! traction faults, temperature readings) to `EVENTS`, and any number of
+//! listeners up to `MAX_LISTENERS` (e.g. `/events` streams) subscribe to it.
+//! Firmware tasks such as the LED status indicator, and WebSocket sessions
+//! watching for thermal changes, use one of `BACKGROUND_LISTENERS` extra slots
+//! instead. Publishing never blocks: a
+//! listener that falls behind loses its oldest events.
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    pubsub::{PubSubChannel, Subscriber},
+};
+use embassy_time::Duration;
+
+use crate::utils::{
+    connection::server::MAX_SERVER_LISTENERS,
+    controllers::{
+        i2c::I2CCommand, safety::SafetyTrip, state::MotorState, thermal::ThermalStatus,
+        traction::TractionFault, ImuData,
+    },
+};
+
+/// Maximum number of simultaneous event listeners.
+pub const MAX_LISTENERS: usize = 4;
+/// Extra slots for firmware tasks and WebSocket sessions, not counted by
+/// `has_listeners`.
+pub const BACKGROUND_LISTENERS: usize = 1 + MAX_SERVER_LISTENERS;
+/// Events buffered per listener before the oldest are dropped.
+pub const EVENT_QUEUE: usize = 8;
+/// IMU sampling rate used until `set_imu_rate` is called.
+pub const DEFAULT_IMU_RATE_HZ: f32 = 10.0;
+
+const SUBSCRIBERS: usize = MAX_LISTENERS + BACKGROUND_LISTENERS;
+
+type EventChannel = PubSubChannel<CriticalSectionRawMutex, Event, EVENT_QUEUE, SUBSCRIBERS, 0>;
+
+/// Event bus shared by all controllers and listeners.
+pub static EVENTS: EventChannel = PubSubChannel::new();
+
+/// Periodic IMU sampling rate, in Hz (0 disables it).
+static IMU_RATE_HZ: Mutex<CriticalSectionRawMutex, Cell<f32>> =
+    Mutex::new(Cell::new(DEFAULT_IMU_RATE_HZ));
+
+/// Number of (non-background) listeners currently subscribed.
+static LISTENERS: Mutex<CriticalSectionRawMutex, Cell<usize>> = Mutex::new(Cell::new(0));
+
+/// Something worth telling a dashboard about.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// Periodic IMU sample.
+    Imu(ImuData),
+    /// Motor enable state or wheel duties changed.
+    Motors(MotorState),
+    /// A motion setpoint (`T`, `Y` or `O`) was applied.
+    Motion(I2CCommand),
+    /// A WebSocket client connected or disconnected.
+    Connection { connected: bool, clients: usize },
+    /// An I2C command failed with a `DeviceError` of this kind.
+    Fault { error: &'static str },
+    /// The safety cutoff tripped (`Some`) or was cleared (`None`).
+    Safety(Option<SafetyTrip>),
+    /// A wheel stall, disconnect or slip was detected (`Some`) or went away
+    /// (`None`).
+    Traction(Option<TractionFault>),
+    /// Periodic motor driver temperature reading.
+    Thermal(ThermalStatus),
+}
+
+impl Event {
+    /// Event name used on the wire (e.g. the SSE `event:` field).
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Imu(_) => "imu",
+            Event::Motors(_) => "motors",
+            Event::Motion(_) => "motion",
+            Event::Connection { .. } => "connection",
+            Event::Fault { .. } => "fault",
+            Event::Safety(_) => "safety",
+            Event::Traction(_) => "traction",
+            Event::Thermal(_) => "thermal",
+        }
+    }
+}
+
+/// Publish an event to every listener without waiting.
+pub fn publish(event: Event) {
+    EVENTS.immediate_publisher().publish_immediate(event);
+}
+
+/// Whether anyone is listening, so producers can skip expensive work.
+pub fn has_listeners() -> bool {
+    LISTENERS.lock(|n| n.get()) > 0
+}
+
+/// Set the periodic IMU sampling rate in Hz; `0` stops sampling.
+pub fn set_imu_rate(hz: f32) {
+    IMU_RATE_HZ.lock(|rate| rate.set(hz.max(0.0)));
+}
+
+/// Interval between periodic IMU samples, if sampling is enabled.
+pub fn imu_period() -> Option<Duration> {
+    let hz = IMU_RATE_HZ.lock(|rate| rate.get());
+    (hz > 0.0).then(|| Duration::from_micros((1_000_000.0 / hz) as u64))
+}
+
+/// A subscription to `EVENTS` that holds one of the `MAX_LISTENERS` slots, or
+/// one of the `BACKGROUND_LISTENERS` slots.
+pub struct Listener {
+    sub: Subscriber<'static, CriticalSectionRawMutex, Event, EVENT_QUEUE, SUBSCRIBERS, 0>,
+    counted: bool,
+}
+
+impl Listener {
+    /// Take a listener slot, or `None` if all slots are in use.
+    pub fn new() -> Option<Self> {
+        LISTENERS.lock(|n| {
+            if n.get() >= MAX_LISTENERS {
+                return None;
+            }
+            let sub = EVENTS.subscriber().ok()?;
+            n.set(n.get() + 1);
+            Some(Listener { sub, counted: true })
+        })
+    }
+
+    /// Take a background slot, which does not keep periodic IMU sampling
+    /// running, or `None` if all slots are in use.
+    pub fn background() -> Option<Self> {
+        let sub = EVENTS.subscriber().ok()?;
+        Some(Listener {
+            sub,
+            counted: false,
+        })
+    }
+
+    /// Wait for the next event, skipping over any that were missed.
+    pub async fn next(&mut self) -> Event {
+        self.sub.next_message_pure().await
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if self.counted {
+            LISTENERS.lock(|n| n.set(n.get().saturating_sub(1)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listener_slots_are_bounded() {
+        let mut held: [Option<Listener>; MAX_LISTENERS] = Default::default();
+        for slot in held.iter_mut() {
+            *slot = Listener::new();
+            assert!(slot.is_some());
+        }
+        assert!(has_listeners());
+        assert!(Listener::new().is_none());
+
+        held[0] = None;
+        assert!(Listener::new().is_some());
+    }
+
+    #[test]
+    fn imu_rate() {
+        set_imu_rate(50.0);
+        assert_eq!(imu_period(), Some(Duration::from_millis(20)));
+        set_imu_rate(0.0);
+        assert_eq!(imu_period(), None);
+        set_imu_rate(DEFAULT_IMU_RATE_HZ);
+    }
+}
@@ -41,7 +41,9 @@ impl EmbodiedKinematics {
     /// Transform a global motion command into body-frame velocities.
     ///
     /// `speed` is the translational magnitude, `angle` and `orientation` are in degrees
-    /// (0° = +X, increasing CCW). Returns `(vx, vy)` in the robot's body frame.
+    /// (0° = forward, increasing clockwise seen from above, so 90° is the robot's
+    /// right; the web UI and teleop client send angles this way). Returns `(vx, vy)` in
+    /// the robot's body frame.
     pub fn convert_to_body_frame(
         speed: f32,
         angle: f32,
@@ -92,8 +94,9 @@ impl EmbodiedKinematics {
 
     /// Compute wheel angular velocities to achieve the desired motion.
     ///
-    /// `speed` is forward translational speed, `angle` and `orientation` are in degrees,
-    /// and `omega` is rotational speed (deg/sec). Returns an array of wheel speeds.
+    /// `speed` is forward translational speed, `angle` and `orientation` are in degrees
+    /// as for `convert_to_body_frame`, and `omega` is rotational speed (deg/sec, positive
+    /// clockwise). Returns an array of wheel speeds.
     pub fn compute_wheel_velocities(
         &self,
         speed: f32,
@@ -1,5 +1,7 @@
 //! Math utilities for the Omni-Wheel Bot.
 //!
-//! This module provides kinematics calculations for three-wheeled omni-directional robots.
+//! This module provides kinematics calculations for three-wheeled omni-directional
+//! robots (`kinematics`) and IMU orientation filters (`orientation`).
 
 pub mod kinematics;
+pub mod orientation;
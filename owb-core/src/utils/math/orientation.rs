@@ -0,0 +1,233 @@
+//! Orientation filters fusing gyro and accelerometer samples.
+//!
+//! Both filters integrate the gyro rate into a unit quaternion and pull it
+//! towards the attitude implied by gravity, so roll and pitch stay anchored
+//! while yaw (with no magnetometer) drifts with the residual gyro bias:
+//!
+//! - `Madgwick`: gradient-descent correction weighted by `beta`.
+//! - `Mahony`: complementary filter with proportional (`kp`) and integral
+//!   (`ki`) feedback; the integral term also soaks up gyro bias.
+//!
+//! Angles are in degrees and rates in °/s, as elsewhere in the crate; the
+//! quaternion is `[w, x, y, z]`.
+
+use libm::{asinf, atan2f, sqrtf};
+
+/// Unit quaternion `[w, x, y, z]` for no rotation.
+pub const IDENTITY: [f32; 4] = [1.0, 0.0, 0.0, 0.0];
+
+/// Roll, pitch and yaw in degrees for quaternion `q` (aerospace sequence:
+/// yaw about z, then pitch about y, then roll about x).
+pub fn to_euler(q: [f32; 4]) -> (f32, f32, f32) {
+    let [w, x, y, z] = q;
+    let roll = atan2f(2.0 * (w * x + y * z), 1.0 - 2.0 * (x * x + y * y));
+    let pitch = asinf((2.0 * (w * y - z * x)).clamp(-1.0, 1.0));
+    let yaw = atan2f(2.0 * (w * z + x * y), 1.0 - 2.0 * (y * y + z * z));
+    (roll.to_degrees(), pitch.to_degrees(), yaw.to_degrees())
+}
+
+/// Quaternion with the roll and pitch implied by an accelerometer reading at
+/// rest and zero yaw, or `None` if `accel` is zero.
+pub fn from_gravity(accel: [f32; 3]) -> Option<[f32; 4]> {
+    let [ax, ay, az] = normalize3(accel)?;
+    let (half_roll, half_pitch) = (atan2f(ay, az) / 2.0, asinf((-ax).clamp(-1.0, 1.0)) / 2.0);
+    let (sr, cr) = (libm::sinf(half_roll), libm::cosf(half_roll));
+    let (sp, cp) = (libm::sinf(half_pitch), libm::cosf(half_pitch));
+    Some([cr * cp, sr * cp, cr * sp, -sr * sp])
+}
+
+fn normalize3(v: [f32; 3]) -> Option<[f32; 3]> {
+    let norm = sqrtf(v[0] * v[0] + v[1] * v[1] + v[2] * v[2]);
+    (norm > 0.0 && norm.is_finite()).then(|| v.map(|c| c / norm))
+}
+
+fn normalize4(q: [f32; 4]) -> [f32; 4] {
+    let norm = sqrtf(q.iter().map(|c| c * c).sum());
+    if norm > 0.0 && norm.is_finite() {
+        q.map(|c| c / norm)
+    } else {
+        IDENTITY
+    }
+}
+
+/// `q` advanced by body rates `g` (rad/s) over `dt` seconds, plus `dq`.
+fn integrate(
+    q: [f32; 4],
+    [gx, gy, gz]: [f32; 3],
+    dq: [f32; 4],
+    dt: f32,
+) -> [f32; 4] {
+    let [w, x, y, z] = q;
+    let rate = [
+        0.5 * (-x * gx - y * gy - z * gz),
+        0.5 * (w * gx + y * gz - z * gy),
+        0.5 * (w * gy - x * gz + z * gx),
+        0.5 * (w * gz + x * gy - y * gx),
+    ];
+    normalize4([
+        w + (rate[0] + dq[0]) * dt,
+        x + (rate[1] + dq[1]) * dt,
+        y + (rate[2] + dq[2]) * dt,
+        z + (rate[3] + dq[3]) * dt,
+    ])
+}
+
+/// Madgwick's gradient-descent orientation filter (IMU variant).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Madgwick {
+    pub q: [f32; 4],
+    /// Correction gain; larger converges faster but follows accelerometer
+    /// noise more.
+    pub beta: f32,
+}
+
+impl Madgwick {
+    pub fn new(beta: f32) -> Self {
+        Madgwick { q: IDENTITY, beta }
+    }
+
+    /// Fuse one sample: `gyro` in °/s, `accel` in any unit, `dt` in seconds.
+    pub fn update(
+        &mut self,
+        gyro: [f32; 3],
+        accel: [f32; 3],
+        dt: f32,
+    ) {
+        let g = gyro.map(f32::to_radians);
+        let mut dq = [0.0; 4];
+        if let Some([ax, ay, az]) = normalize3(accel) {
+            let [w, x, y, z] = self.q;
+            // Objective function: predicted minus measured gravity direction.
+            let f = [
+                2.0 * (x * z - w * y) - ax,
+                2.0 * (w * x + y * z) - ay,
+                2.0 * (0.5 - x * x - y * y) - az,
+            ];
+            // Jacobian transpose times f.
+            let step = [
+                -2.0 * y * f[0] + 2.0 * x * f[1],
+                2.0 * z * f[0] + 2.0 * w * f[1] - 4.0 * x * f[2],
+                -2.0 * w * f[0] + 2.0 * z * f[1] - 4.0 * y * f[2],
+                2.0 * x * f[0] + 2.0 * y * f[1],
+            ];
+            let norm = sqrtf(step.iter().map(|c| c * c).sum());
+            if norm > 0.0 {
+                dq = step.map(|c| -self.beta * c / norm);
+            }
+        }
+        self.q = integrate(self.q, g, dq, dt);
+    }
+}
+
+/// Mahony's complementary orientation filter (IMU variant).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mahony {
+    pub q: [f32; 4],
+    /// Proportional gain.
+    pub kp: f32,
+    /// Integral gain (`0` disables bias estimation).
+    pub ki: f32,
+    /// Integrated error (rad/s), an estimate of the gyro bias.
+    integral: [f32; 3],
+}
+
+impl Mahony {
+    pub fn new(
+        kp: f32,
+        ki: f32,
+    ) -> Self {
+        Mahony {
+            q: IDENTITY,
+            kp,
+            ki,
+            integral: [0.0; 3],
+        }
+    }
+
+    /// Fuse one sample: `gyro` in °/s, `accel` in any unit, `dt` in seconds.
+    pub fn update(
+        &mut self,
+        gyro: [f32; 3],
+        accel: [f32; 3],
+        dt: f32,
+    ) {
+        let mut g = gyro.map(f32::to_radians);
+        if let Some([ax, ay, az]) = normalize3(accel) {
+            let [w, x, y, z] = self.q;
+            // Gravity direction predicted by the current estimate.
+            let v = [
+                2.0 * (x * z - w * y),
+                2.0 * (w * x + y * z),
+                w * w - x * x - y * y + z * z,
+            ];
+            // Error is the cross product of measured and predicted gravity.
+            let e = [
+                ay * v[2] - az * v[1],
+                az * v[0] - ax * v[2],
+                ax * v[1] - ay * v[0],
+            ];
+            for i in 0..3 {
+                if self.ki > 0.0 {
+                    self.integral[i] += self.ki * e[i] * dt;
+                }
+                g[i] += self.kp * e[i] + self.integral[i];
+            }
+        }
+        self.q = integrate(self.q, g, [0.0; 4], dt);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(
+        a: f32,
+        b: f32,
+    ) -> bool {
+        (a - b).abs() < 0.5
+    }
+
+    #[test]
+    fn integrates_gyro_yaw() {
+        let mut filter = Madgwick::new(0.1);
+        for _ in 0..100 {
+            filter.update([0.0, 0.0, 90.0], [0.0, 0.0, 1.0], 0.01);
+        }
+        let (roll, pitch, yaw) = to_euler(filter.q);
+        assert!(close(roll, 0.0) && close(pitch, 0.0));
+        assert!(close(yaw, 90.0), "yaw {yaw}");
+    }
+
+    #[test]
+    fn converges_to_gravity() {
+        // Tilted 30° about x: gravity seen along +y and +z.
+        let accel = [0.0, 0.5, 0.866];
+        let mut madgwick = Madgwick::new(0.5);
+        let mut mahony = Mahony::new(2.0, 0.0);
+        for _ in 0..2000 {
+            madgwick.update([0.0; 3], accel, 0.01);
+            mahony.update([0.0; 3], accel, 0.01);
+        }
+        for q in [madgwick.q, mahony.q] {
+            let (roll, pitch, _) = to_euler(q);
+            assert!(
+                close(roll, 30.0) && close(pitch, 0.0),
+                "roll {roll} pitch {pitch}"
+            );
+        }
+
+        let (roll, pitch, yaw) = to_euler(from_gravity([-0.5, 0.0, 0.866]).unwrap());
+        assert!(close(roll, 0.0) && close(pitch, 30.0) && close(yaw, 0.0));
+    }
+
+    #[test]
+    fn mahony_learns_gyro_bias() {
+        let mut filter = Mahony::new(1.0, 0.5);
+        for _ in 0..5000 {
+            filter.update([2.0, 0.0, 0.0], [0.0, 0.0, 1.0], 0.01);
+        }
+        let (roll, _, _) = to_euler(filter.q);
+        assert!(close(roll, 0.0), "roll {roll}");
+    }
+}
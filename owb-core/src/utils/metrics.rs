@@ -0,0 +1,276 @@
+//! Runtime counters for field diagnostics.
+//!
+//! Counters are plain `u32`s behind a critical-section mutex, bumped from the
+//! server and controller tasks. `report()` adds uptime and queue levels and is
+//! served as Prometheus text on `/metrics` and as `{"rt":"metrics",...}` for
+//! `{"ct":"g","gc":"metrics"}`.
+
+extern crate alloc;
+
+use alloc::{collections::BTreeMap, string::String};
+use core::{cell::RefCell, fmt::Write};
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::controllers::{
+    i2c::{DeviceError, I2CCommand, DEVICE_ERROR_KINDS},
+    leds::LEDCommand,
+    GetCommand, SystemCommand, I2C_CHANNEL, LED_CHANNEL,
+};
+
+static COUNTERS: Mutex<CriticalSectionRawMutex, RefCell<Counters>> =
+    Mutex::new(RefCell::new(Counters::new()));
+
+/// Raw counters, indexed like the corresponding `NAMES` lists.
+struct Counters {
+    i2c_commands: [u32; I2CCommand::NAMES.len()],
+    led_commands: [u32; LEDCommand::NAMES.len()],
+    get_commands: [u32; GetCommand::NAMES.len()],
+    parse_failures: u32,
+    device_errors: [u32; DEVICE_ERROR_KINDS.len()],
+    i2c_failures: u32,
+    ws_connects: u32,
+    ws_disconnects: u32,
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Counters {
+            i2c_commands: [0; I2CCommand::NAMES.len()],
+            led_commands: [0; LEDCommand::NAMES.len()],
+            get_commands: [0; GetCommand::NAMES.len()],
+            parse_failures: 0,
+            device_errors: [0; DEVICE_ERROR_KINDS.len()],
+            i2c_failures: 0,
+            ws_connects: 0,
+            ws_disconnects: 0,
+        }
+    }
+}
+
+/// Point-in-time copy of all metrics.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MetricsReport {
+    /// Seconds since boot.
+    pub uptime_s: u64,
+    /// Commands received, by `ct` and then command name.
+    pub commands: BTreeMap<String, BTreeMap<String, u32>>,
+    /// Frames or request bodies that failed to decode.
+    pub parse_failures: u32,
+    /// `DeviceError`s raised, by variant.
+    pub device_errors: BTreeMap<String, u32>,
+    /// Device errors caused by failed I2C transactions.
+    pub i2c_failures: u32,
+    pub ws_connects: u32,
+    pub ws_disconnects: u32,
+    /// Commands waiting in `I2C_CHANNEL`.
+    pub i2c_queue: usize,
+    /// Commands waiting in `LED_CHANNEL`.
+    pub led_queue: usize,
+}
+
+fn bump(
+    counts: &mut [u32],
+    names: &[&str],
+    name: &str,
+) {
+    if let Some(i) = names.iter().position(|n| *n == name) {
+        counts[i] = counts[i].wrapping_add(1);
+    }
+}
+
+/// Count a command received from any transport.
+pub fn command(cmd: &SystemCommand) {
+    COUNTERS.lock(|c| {
+        let c = &mut *c.borrow_mut();
+        match cmd {
+            SystemCommand::I(i) => bump(&mut c.i2c_commands, I2CCommand::NAMES, i.name()),
+            SystemCommand::L(l) => bump(&mut c.led_commands, LEDCommand::NAMES, l.name()),
+            SystemCommand::G(g) => bump(&mut c.get_commands, GetCommand::NAMES, g.name()),
+        }
+    });
+}
+
+/// Count a frame or request body that could not be decoded.
+pub fn parse_failure() {
+    COUNTERS.lock(|c| {
+        let c = &mut *c.borrow_mut();
+        c.parse_failures = c.parse_failures.wrapping_add(1);
+    });
+}
+
+/// Count a device error, and an I2C failure if the bus caused it.
+pub fn device_error<E: core::fmt::Debug>(error: &DeviceError<E>) {
+    COUNTERS.lock(|c| {
+        let c = &mut *c.borrow_mut();
+        bump(&mut c.device_errors, DEVICE_ERROR_KINDS, error.kind());
+        if error.is_bus_error() {
+            c.i2c_failures = c.i2c_failures.wrapping_add(1);
+        }
+    });
+}
+
+/// Count a WebSocket connection opening.
+pub fn ws_connected() {
+    COUNTERS.lock(|c| {
+        let c = &mut *c.borrow_mut();
+        c.ws_connects = c.ws_connects.wrapping_add(1);
+    });
+}
+
+/// Count a WebSocket connection closing.
+pub fn ws_disconnected() {
+    COUNTERS.lock(|c| {
+        let c = &mut *c.borrow_mut();
+        c.ws_disconnects = c.ws_disconnects.wrapping_add(1);
+    });
+}
+
+fn named(
+    names: &[&str],
+    counts: &[u32],
+) -> BTreeMap<String, u32> {
+    names
+        .iter()
+        .zip(counts)
+        .map(|(name, count)| (String::from(*name), *count))
+        .collect()
+}
+
+/// Snapshot all counters together with uptime and queue levels.
+pub fn report() -> MetricsReport {
+    report_at(embassy_time::Instant::now().as_secs())
+}
+
+fn report_at(uptime_s: u64) -> MetricsReport {
+    COUNTERS.lock(|c| {
+        let c = c.borrow();
+        let mut commands = BTreeMap::new();
+        commands.insert(String::from("i"), named(I2CCommand::NAMES, &c.i2c_commands));
+        commands.insert(String::from("l"), named(LEDCommand::NAMES, &c.led_commands));
+        commands.insert(String::from("g"), named(GetCommand::NAMES, &c.get_commands));
+
+        MetricsReport {
+            uptime_s,
+            commands,
+            parse_failures: c.parse_failures,
+            device_errors: named(DEVICE_ERROR_KINDS, &c.device_errors),
+            i2c_failures: c.i2c_failures,
+            ws_connects: c.ws_connects,
+            ws_disconnects: c.ws_disconnects,
+            i2c_queue: I2C_CHANNEL.len(),
+            led_queue: LED_CHANNEL.len(),
+        }
+    })
+}
+
+impl MetricsReport {
+    /// Render in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        // Writing to a String cannot fail.
+        let _ = self.write_prometheus(&mut out);
+        out
+    }
+
+    fn write_prometheus(
+        &self,
+        out: &mut String,
+    ) -> core::fmt::Result {
+        writeln!(out, "# HELP owb_uptime_seconds Seconds since boot.")?;
+        writeln!(out, "# TYPE owb_uptime_seconds gauge")?;
+        writeln!(out, "owb_uptime_seconds {}", self.uptime_s)?;
+
+        writeln!(out, "# HELP owb_commands_total Commands received, by type.")?;
+        writeln!(out, "# TYPE owb_commands_total counter")?;
+        for (ct, counts) in &self.commands {
+            for (cmd, count) in counts {
+                writeln!(
+                    out,
+                    "owb_commands_total{{ct=\"{ct}\",cmd=\"{cmd}\"}} {count}"
+                )?;
+            }
+        }
+
+        writeln!(
+            out,
+            "# HELP owb_parse_failures_total Frames that failed to decode."
+        )?;
+        writeln!(out, "# TYPE owb_parse_failures_total counter")?;
+        writeln!(out, "owb_parse_failures_total {}", self.parse_failures)?;
+
+        writeln!(
+            out,
+            "# HELP owb_device_errors_total Device errors, by variant."
+        )?;
+        writeln!(out, "# TYPE owb_device_errors_total counter")?;
+        for (kind, count) in &self.device_errors {
+            writeln!(out, "owb_device_errors_total{{kind=\"{kind}\"}} {count}")?;
+        }
+
+        writeln!(
+            out,
+            "# HELP owb_i2c_failures_total Failed I2C transactions."
+        )?;
+        writeln!(out, "# TYPE owb_i2c_failures_total counter")?;
+        writeln!(out, "owb_i2c_failures_total {}", self.i2c_failures)?;
+
+        writeln!(
+            out,
+            "# HELP owb_ws_connects_total WebSocket connections opened."
+        )?;
+        writeln!(out, "# TYPE owb_ws_connects_total counter")?;
+        writeln!(out, "owb_ws_connects_total {}", self.ws_connects)?;
+        writeln!(
+            out,
+            "# HELP owb_ws_disconnects_total WebSocket connections closed."
+        )?;
+        writeln!(out, "# TYPE owb_ws_disconnects_total counter")?;
+        writeln!(out, "owb_ws_disconnects_total {}", self.ws_disconnects)?;
+
+        writeln!(
+            out,
+            "# HELP owb_queue_depth Commands waiting in a controller queue."
+        )?;
+        writeln!(out, "# TYPE owb_queue_depth gauge")?;
+        writeln!(out, "owb_queue_depth{{queue=\"i2c\"}} {}", self.i2c_queue)?;
+        writeln!(out, "owb_queue_depth{{queue=\"led\"}} {}", self.led_queue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    #[test]
+    fn counts_commands_by_name() {
+        command(&SystemCommand::L(LEDCommand::SC { r: 1, g: 2, b: 3 }));
+        command(&SystemCommand::L(LEDCommand::SC { r: 1, g: 2, b: 3 }));
+        let report = report_at(42);
+        assert!(report.commands["l"]["sc"] >= 2);
+        assert_eq!(report.commands["i"].len(), I2CCommand::NAMES.len());
+
+        let text = report.to_prometheus();
+        assert!(text.contains("owb_uptime_seconds 42\n"));
+        assert!(text.contains("# TYPE owb_commands_total counter\n"));
+        assert!(text.contains("owb_commands_total{ct=\"l\",cmd=\"sc\"} "));
+        assert!(text.contains("owb_queue_depth{queue=\"i2c\"} 0\n"));
+    }
+
+    #[test]
+    fn counts_bus_errors() {
+        let before = report_at(0);
+        device_error::<()>(&DeviceError::ImuError(icm42670::Error::BusError(())));
+        device_error::<()>(&DeviceError::PwmNotInitialized);
+        let after = report_at(0);
+        assert!(after.i2c_failures > before.i2c_failures);
+        assert!(after.device_errors["imu"] > before.device_errors["imu"]);
+        assert!(
+            after.device_errors["pwm_not_initialized"]
+                > before.device_errors["pwm_not_initialized"]
+        );
+    }
+}
@@ -5,15 +5,19 @@
 //!
 //! - `connection`: WebSocket server and message handling
 //! - `controllers`: I2C and LED controllers for robotics hardware
-//! - `math`: kinematics calculations for omni-wheel motion
+//! - `events`: telemetry event bus shared by controllers and listeners
+//! - `metrics`: runtime counters exposed on `/metrics`
+//! - `math`: kinematics for omni-wheel motion and IMU orientation filters
 //! - `frontend`: compressed HTML/CSS/JS assets for the web UI
 //!
 //! The `mk_static!` macro simplifies static initialization in no-std contexts.
 
 pub mod connection;
 pub mod controllers;
+pub mod events;
 pub(crate) mod frontend;
 pub mod math;
+pub mod metrics;
 
 pub use connection::server::run as wss;
 pub use controllers::SystemController;
@@ -2,11 +2,16 @@ use core::cell::RefCell;
 
 use embedded_hal_bus::i2c::RefCellDevice;
 use embedded_hal_mock::eh1::i2c::{Mock as I2cMock, Transaction as I2cTrans};
-use owb_core::utils::controllers::i2c::I2CDevices;
-use pwm_pca9685::{Address as PwmAddress, Pca9685};
+use owb_core::utils::connection::server::{self, ServerTimer, WebSocket};
+use owb_core::utils::controllers::i2c::{I2CCommand, I2CDevices};
+use owb_core::utils::controllers::imu_config::{
+    AccelRange, GyroRange, ImuConfig, DEFAULT_IMU_CONFIG,
+};
+use owb_core::utils::controllers::state;
+use owb_core::utils::controllers::thermal::{ThermalLevel, ThermalMonitor, DEFAULT_THERMAL};
+use owb_core::utils::controllers::SystemCommand;
 use owb_core::utils::math::kinematics::EmbodiedKinematics;
-use owb_core::utils::connection::server::{WebSocket, ServerTimer};
-
+use pwm_pca9685::{Address as PwmAddress, Pca9685};
 
 /// Default I2C address for the PWM motor controller.
 pub const PWM_ADDRESS: u8 = 0x55;
@@ -96,6 +101,100 @@ fn test_apply_wheel_speeds_zero() {
     i2c_bus.borrow_mut().done();
 }
 
+#[test]
+fn test_configure_imu() {
+    // Initialization, then each setting read-modify-written and read back
+    let expectations = [
+        write_read(IMU_ADDRESS, vec![0x75], vec![0x67]),
+        write_read(IMU_ADDRESS, vec![0x21], vec![0x00]),
+        write(IMU_ADDRESS, vec![0x21, 0x00]),
+        write_read(IMU_ADDRESS, vec![0x20], vec![0x00]),
+        write(IMU_ADDRESS, vec![0x20, 0x00]),
+        write_read(IMU_ADDRESS, vec![0x1F], vec![0x0F]),
+        write(IMU_ADDRESS, vec![0x1F, 0x0F]),
+        write_read(IMU_ADDRESS, vec![0x21], vec![0x00]),
+        write(IMU_ADDRESS, vec![0x21, 0x40]),
+        write_read(IMU_ADDRESS, vec![0x20], vec![0x00]),
+        write(IMU_ADDRESS, vec![0x20, 0x40]),
+        write_read(IMU_ADDRESS, vec![0x21], vec![0x40]),
+        write(IMU_ADDRESS, vec![0x21, 0x48]),
+        write_read(IMU_ADDRESS, vec![0x20], vec![0x40]),
+        write(IMU_ADDRESS, vec![0x20, 0x48]),
+        write_read(IMU_ADDRESS, vec![0x1F], vec![0x0F]),
+        write(IMU_ADDRESS, vec![0x1F, 0x0F]),
+        write_read(IMU_ADDRESS, vec![0x1F], vec![0x0F]),
+        write_read(IMU_ADDRESS, vec![0x21], vec![0x48]),
+        write_read(IMU_ADDRESS, vec![0x20], vec![0x48]),
+        write_read(IMU_ADDRESS, vec![0x21], vec![0x48]),
+        write_read(IMU_ADDRESS, vec![0x20], vec![0x48]),
+    ];
+
+    let mock = I2cMock::new(&expectations);
+    let i2c_bus = RefCell::new(mock);
+    let mut devs = I2CDevices::new(&i2c_bus, 0.148, 0.195);
+    devs.init_devices().unwrap();
+    let config = ImuConfig {
+        accel_range: AccelRange::G4,
+        gyro_range: GyroRange::Dps500,
+        accel_odr: 200.0,
+        gyro_odr: 200.0,
+        ..DEFAULT_IMU_CONFIG
+    };
+    assert_eq!(devs.configure_imu(config).unwrap(), config);
+    i2c_bus.borrow_mut().done();
+}
+
+#[test]
+fn test_thermal_lockout_clears_after_disable() {
+    // Initialization, then disable (PWM only, the IMU stays awake for the
+    // thermal monitor) and enable once the drivers have cooled down
+    let expectations = [
+        write_read(IMU_ADDRESS, vec![0x75], vec![0x67]),
+        write_read(IMU_ADDRESS, vec![0x21], vec![0x00]),
+        write(IMU_ADDRESS, vec![0x21, 0x00]),
+        write_read(IMU_ADDRESS, vec![0x20], vec![0x00]),
+        write(IMU_ADDRESS, vec![0x20, 0x00]),
+        write_read(IMU_ADDRESS, vec![0x1F], vec![0x0F]),
+        write(IMU_ADDRESS, vec![0x1F, 0x0F]),
+        write(PWM_ADDRESS, vec![0x00, 0x11]),
+        write(PWM_ADDRESS, vec![0x00, 0x01]),
+        write_read(IMU_ADDRESS, vec![0x1F], vec![0x0F]),
+        write(IMU_ADDRESS, vec![0x1F, 0x0F]),
+    ];
+
+    let mock = I2cMock::new(&expectations);
+    let i2c_bus = RefCell::new(mock);
+    let mut devs = I2CDevices::new(&i2c_bus, 0.148, 0.195);
+    devs.init_devices().unwrap();
+    let pwm = Pca9685::new(RefCellDevice::new(&i2c_bus), PwmAddress::from(PWM_ADDRESS)).unwrap();
+    devs.pwm = Some(pwm);
+    let enable = SystemCommand::I(I2CCommand::Enable);
+
+    let mut monitor = ThermalMonitor::new();
+    let hot = monitor.update(&DEFAULT_THERMAL, 80.0, 0);
+    assert_eq!(hot.level, ThermalLevel::Critical);
+    state::update(|s| {
+        s.imu_ready = true;
+        s.pwm_ready = true;
+        s.thermal = Some(hot);
+    });
+    assert!(server::unavailable(&enable).is_some());
+
+    devs.disable().unwrap();
+
+    // Readings keep coming while disabled; cool down to 40 °C
+    let mut status = hot;
+    for s in 1..=60u64 {
+        status = monitor.update(&DEFAULT_THERMAL, 40.0, s * 1_000_000);
+    }
+    assert_eq!(status.level, ThermalLevel::Normal);
+    state::update(|s| s.thermal = Some(status));
+    assert_eq!(server::unavailable(&enable), None);
+
+    devs.enable().unwrap();
+    i2c_bus.borrow_mut().done();
+}
+
 /// Smoke test for wheel kinematics via the controller helper.
 #[test]
 fn wheel_velocities_nonzero() {
@@ -115,6 +214,8 @@ fn example_embodied_kinematics() {
 /// Example: instantiating WebSocket server types.
 #[test]
 fn example_websocket_types_exist() {
-    let _ws: WebSocket = WebSocket;
+    let _ws: WebSocket = WebSocket {
+        max_message_size: 1024,
+    };
     let _timer: ServerTimer = ServerTimer;
-}
\ No newline at end of file
+}